@@ -0,0 +1,759 @@
+// account.rs - Account Snapshots and History
+
+// This file contains the snapshots of the account: the positions and the open orders of a symbol, read with two
+// signed requests (/fapi/v2/positionRisk and /fapi/v1/openOrders).
+
+// The order functions of binance_orders.rs do not read the account after each order, so a snapshot is only taken
+// when it is asked for:
+// - `account_snapshot`: takes a snapshot now and returns it.
+// - `spawn_account_snapshots`: takes a snapshot periodically.
+// Every snapshot taken is also published to the subscribers of `subscribe_account_snapshots`, so a logger or a
+// dashboard can follow the account without taking snapshots itself. The snapshots taken inside
+// `accounts::with_account` carry the name of the account.
+
+// It also reads the wallet and its history, for sizing and reporting (the balances of the wallet are read with
+// `balance_info` of binance_orders.rs):
+// - `account_information`: the totals of the wallet (margin balance, available balance...), from /fapi/v2/account.
+// - `income` and `income_history`: the realized PnL, funding fees, commissions... from /fapi/v1/income. The exchange
+//   returns at most 1000 incomes per request, so `income_history` reads every page of the time range.
+// - `realized_pnl`: the sum of the REALIZED_PNL incomes of a symbol in a time range.
+// - `user_trades`: the fills of a symbol, from /fapi/v1/userTrades.
+// - `all_orders`: the orders of a symbol (open, filled, canceled...), from /fapi/v1/allOrders.
+// - `order_history` and `user_trades_history`: every order or trade of a time range, for the reconciliation. The
+//   exchange only accepts ranges of 7 days, so the first record is searched one week after another, and the next
+//   pages are read by id (orderId or fromId) until the end of the range.
+
+use crate::accounts::current_account;
+use crate::binance_orders::spawn_scoped;
+use crate::exchange::{BinanceExchange, Exchange};
+use crate::models::{
+    AccountInformation, Income, IncomeType, OpenOrder, OrderInfo, PositionRisk, PositionSide,
+    UserTrade,
+};
+use crate::request::BinanceRequest;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// Number of snapshots kept for the subscribers that are behind.
+const SNAPSHOT_CHANNEL_CAPACITY: usize = 16;
+/// Incomes per request of the exchange when no limit is sent, and its maximum.
+const DEFAULT_INCOME_LIMIT: u32 = 100;
+const MAX_INCOME_LIMIT: u32 = 1000;
+/// Records per request of the order and trade histories, and the longest range of time of a request (7 days).
+const MAX_HISTORY_LIMIT: u32 = 1000;
+const MAX_HISTORY_WINDOW: u64 = 7 * 24 * 3_600_000;
+/// Time the exchange keeps the order and trade histories (6 months).
+const MAX_HISTORY_RETENTION: u64 = 180 * 24 * 3_600_000;
+
+/// Positions and open orders of a symbol at a given time.
+#[derive(Debug, Clone)]
+pub struct AccountSnapshot {
+    /// Name of the account (see accounts.rs), or None for the default credentials.
+    pub account: Option<String>,
+    pub symbol: String,
+    /// One position per position side (BOTH in one-way mode, LONG and SHORT in hedge mode).
+    pub positions: Vec<PositionRisk>,
+    pub open_orders: Vec<OpenOrder>,
+    /// Local time of the snapshot, in milliseconds since the epoch.
+    pub time: u64,
+}
+
+impl AccountSnapshot {
+    /// Get the position of a position side.
+    pub fn position(&self, position_side: PositionSide) -> Option<&PositionRisk> {
+        self.positions
+            .iter()
+            .find(|p| p.position_side == position_side)
+    }
+
+    /// Get the amount in operation of a position side (0 when there is no position).
+    pub fn position_amount(&self, position_side: PositionSide) -> f64 {
+        self.position(position_side).map_or(0.0, |p| p.position_amt)
+    }
+
+    /// Get the sum of the unrealized profit of the positions.
+    pub fn unrealized_profit(&self) -> f64 {
+        self.positions.iter().map(|p| p.un_realized_profit).sum()
+    }
+}
+
+fn snapshot_channel() -> &'static broadcast::Sender<AccountSnapshot> {
+    static CHANNEL: OnceLock<broadcast::Sender<AccountSnapshot>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(SNAPSHOT_CHANNEL_CAPACITY).0)
+}
+
+/// Subscribes to the snapshots of the account. The receiver gets every snapshot taken after the subscription.
+pub fn subscribe_account_snapshots() -> broadcast::Receiver<AccountSnapshot> {
+    snapshot_channel().subscribe()
+}
+
+/// Takes a snapshot of the positions and open orders of a symbol.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+///
+/// # Returns
+/// The snapshot, that is also published to the subscribers, or the error of the requests.
+///
+pub async fn account_snapshot(symbol: &str) -> Result<AccountSnapshot, String> {
+    let exchange = BinanceExchange::new();
+    let snapshot = AccountSnapshot {
+        account: current_account().map(|account| account.name().to_string()),
+        symbol: symbol.to_string(),
+        positions: exchange.positions(symbol).await?,
+        open_orders: exchange.open_orders(symbol).await?,
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+    };
+
+    for position in &snapshot.positions {
+        debug!(
+            symbol,
+            position_side = %position.position_side,
+            amount = position.position_amt,
+            entry_price = position.entry_price,
+            "Amount in operation of the position"
+        );
+    }
+    debug!(
+        symbol,
+        open_orders = snapshot.open_orders.len(),
+        "Number of open orders"
+    );
+
+    // Sending only fails when nobody is subscribed.
+    let _ = snapshot_channel().send(snapshot.clone());
+    Ok(snapshot)
+}
+
+/// Spawns a task that takes a snapshot of a symbol every `interval`, in the account of the caller (see accounts.rs).
+/// Failed snapshots are skipped.
+pub fn spawn_account_snapshots(symbol: String, interval: Duration) -> tokio::task::JoinHandle<()> {
+    spawn_scoped(async move {
+        loop {
+            let _ = account_snapshot(&symbol).await;
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Retrieves the information of the account: the totals of the wallet, the assets and the positions.
+///
+/// # Returns
+/// The `AccountInformation` or the mapped error.
+///
+pub async fn account_information() -> Result<AccountInformation, String> {
+    BinanceRequest::get("/fapi/v2/account")
+        .signed()
+        .fetch()
+        .await
+}
+
+/// Filters of the income history. The fields left to None are not sent.
+#[derive(Debug, Clone, Default)]
+pub struct IncomeQuery {
+    pub symbol: Option<String>,
+    pub income_type: Option<IncomeType>,
+    /// First time of the range, in milliseconds since the epoch.
+    pub start_time: Option<u64>,
+    /// Last time of the range, in milliseconds since the epoch.
+    pub end_time: Option<u64>,
+    /// Incomes per request (100 by default, 1000 at most).
+    pub limit: Option<u32>,
+}
+
+/// Retrieves one page of the income history: the first `limit` incomes from the start time, or the last ones
+/// when there is no start time.
+///
+/// # Arguments
+/// * `query`: The filters of the incomes.
+///
+/// # Returns
+/// The incomes sorted by time or the mapped error.
+///
+pub async fn income(query: &IncomeQuery) -> Result<Vec<Income>, String> {
+    BinanceRequest::get("/fapi/v1/income")
+        .param_opt("symbol", query.symbol.as_deref())
+        .param_opt("incomeType", query.income_type.and_then(|t| t.as_str()))
+        .param_opt("startTime", query.start_time)
+        .param_opt("endTime", query.end_time)
+        .param_opt("limit", query.limit)
+        .signed()
+        .fetch()
+        .await
+}
+
+/// Retrieves every income of a time range, reading one page after another.
+///
+/// Each page starts at the time of the last income of the previous one, so the incomes that share that time are
+/// not lost; they are read twice and removed by their transaction id. When a whole page shares one time, the page
+/// is made bigger (up to 1000 incomes, the maximum of the exchange) to read past it.
+///
+/// # Arguments
+/// * `query`: The filters of the incomes. Without start time, the history starts at the epoch.
+///
+/// # Returns
+/// The incomes sorted by time or the error of the first page that failed.
+///
+pub async fn income_history(query: &IncomeQuery) -> Result<Vec<Income>, String> {
+    let mut query = query.clone();
+    query.start_time = Some(query.start_time.unwrap_or(0));
+    let mut limit = query.limit.unwrap_or(DEFAULT_INCOME_LIMIT);
+    let mut seen = HashSet::new();
+    let mut history = Vec::new();
+
+    loop {
+        query.limit = Some(limit);
+        let page = income(&query).await?;
+        let Some(last_time) = page.last().map(|i| i.time) else {
+            return Ok(history);
+        };
+        let is_last_page = (page.len() as u32) < limit;
+        let count = history.len();
+        history.extend(page.into_iter().filter(|i| seen.insert(i.tran_id)));
+
+        if is_last_page {
+            return Ok(history);
+        } else if history.len() > count {
+            query.start_time = Some(last_time);
+        } else if limit < MAX_INCOME_LIMIT {
+            limit = (limit * 2).min(MAX_INCOME_LIMIT);
+        } else {
+            // More than 1000 incomes in one millisecond: the rest of them cannot be read.
+            query.start_time = Some(last_time + 1);
+        }
+    }
+}
+
+/// Computes the realized PnL of a symbol in a time range, from its REALIZED_PNL incomes. The commissions and the
+/// funding fees are not included.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `start_time`, `end_time`: The range, in milliseconds since the epoch (None to leave it open).
+///
+/// # Returns
+/// The realized PnL in the margin asset or the mapped error.
+///
+pub async fn realized_pnl(
+    symbol: &str,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+) -> Result<f64, String> {
+    let incomes = income_history(&IncomeQuery {
+        symbol: Some(symbol.to_string()),
+        income_type: Some(IncomeType::RealizedPnl),
+        start_time,
+        end_time,
+        limit: Some(1000),
+    })
+    .await?;
+    Ok(incomes.iter().map(|i| i.income).sum())
+}
+
+/// Filters of the trades of a symbol. The fields left to None are not sent.
+#[derive(Debug, Clone, Default)]
+pub struct TradeQuery {
+    /// First time of the range, in milliseconds since the epoch.
+    pub start_time: Option<u64>,
+    /// Last time of the range, in milliseconds since the epoch.
+    pub end_time: Option<u64>,
+    /// Id of the first trade returned (it cannot be sent with a time range).
+    pub from_id: Option<u64>,
+    /// Trades per request (500 by default, 1000 at most).
+    pub limit: Option<u32>,
+}
+
+/// Retrieves the trades (fills) of a symbol.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `query`: The filters of the trades.
+///
+/// # Returns
+/// The trades sorted by id or the mapped error.
+///
+pub async fn user_trades(symbol: &str, query: &TradeQuery) -> Result<Vec<UserTrade>, String> {
+    BinanceRequest::get("/fapi/v1/userTrades")
+        .param("symbol", symbol)
+        .param_opt("startTime", query.start_time)
+        .param_opt("endTime", query.end_time)
+        .param_opt("fromId", query.from_id)
+        .param_opt("limit", query.limit)
+        .signed()
+        .fetch()
+        .await
+}
+
+/// Filters of the orders of a symbol. The fields left to None are not sent.
+#[derive(Debug, Clone, Default)]
+pub struct OrderQuery {
+    /// First time of the range, in milliseconds since the epoch.
+    pub start_time: Option<u64>,
+    /// Last time of the range, in milliseconds since the epoch (at most 7 days after the start time).
+    pub end_time: Option<u64>,
+    /// Id of the first order returned (the time range is ignored when it is sent).
+    pub order_id: Option<u64>,
+    /// Orders per request (500 by default, 1000 at most).
+    pub limit: Option<u32>,
+}
+
+/// Retrieves the orders of a symbol, whatever their status.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `query`: The filters of the orders.
+///
+/// # Returns
+/// The orders sorted by id or the mapped error.
+///
+pub async fn all_orders(symbol: &str, query: &OrderQuery) -> Result<Vec<OrderInfo>, String> {
+    BinanceRequest::get("/fapi/v1/allOrders")
+        .param("symbol", symbol)
+        .param_opt("orderId", query.order_id)
+        .param_opt("startTime", query.start_time)
+        .param_opt("endTime", query.end_time)
+        .param_opt("limit", query.limit)
+        .signed()
+        .fetch()
+        .await
+}
+
+/// Page of a history: the records of a range of time of at most 7 days, or the records from an id.
+enum HistoryPage {
+    Range { start_time: u64, end_time: u64 },
+    FromId(u64),
+}
+
+/// Reads every record of a history (orders or trades) between `start_time` and `end_time`.
+///
+/// Without `from_id`, the first page with records is searched one range of 7 days after another from the start
+/// time. The next pages are read from the id that follows the last record, until a page is not full or has a
+/// record after the end time.
+///
+/// # Arguments
+/// * `start_time`, `end_time`: The range. Without start time, the range is the 7 days before the end time, and
+///   without end time it ends now. A start time older than MAX_HISTORY_RETENTION (e.g., 0) is moved to the oldest
+///   time kept by the exchange, instead of searching every week since then.
+/// * `from_id`: The id of the first record, to read the history from it instead of from the start time.
+/// * `limit`: The records per page.
+/// * `record_time`, `record_id`: The time and the id of a record.
+/// * `read_page`: The request of a page.
+///
+/// # Returns
+/// The records sorted by id or the error of the first page that failed.
+///
+async fn read_history<T, F, Fut>(
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    from_id: Option<u64>,
+    limit: u32,
+    record_time: impl Fn(&T) -> u64,
+    record_id: impl Fn(&T) -> u64,
+    mut read_page: F,
+) -> Result<Vec<T>, String>
+where
+    F: FnMut(HistoryPage) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, String>>,
+{
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let end_time = end_time.unwrap_or(now);
+    let mut history = Vec::new();
+
+    let mut page = match from_id {
+        Some(id) => read_page(HistoryPage::FromId(id)).await?,
+        None => {
+            let mut range_start = start_time
+                .unwrap_or(end_time.saturating_sub(MAX_HISTORY_WINDOW))
+                .max(now.saturating_sub(MAX_HISTORY_RETENTION));
+            if range_start > end_time {
+                return Ok(history);
+            }
+            loop {
+                let range_end = (range_start + MAX_HISTORY_WINDOW).min(end_time);
+                let page = read_page(HistoryPage::Range {
+                    start_time: range_start,
+                    end_time: range_end,
+                })
+                .await?;
+                if !page.is_empty() {
+                    break page;
+                } else if range_end >= end_time {
+                    return Ok(history);
+                }
+                range_start = range_end + 1;
+            }
+        }
+    };
+
+    loop {
+        let is_last_page = (page.len() as u32) < limit;
+        let next_id = page.last().map(|r| record_id(r) + 1);
+        let is_past_end = page.iter().any(|r| record_time(r) > end_time);
+        history.extend(page.into_iter().filter(|r| record_time(r) <= end_time));
+        match next_id {
+            Some(id) if !is_last_page && !is_past_end => {
+                page = read_page(HistoryPage::FromId(id)).await?;
+            }
+            _ => return Ok(history),
+        }
+    }
+}
+
+/// Retrieves every order of a symbol in a time range, reading one page after another.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `query`: The range of the orders (it can be longer than 7 days), or the id of the first order, and the
+///   orders per request (1000 by default).
+///
+/// # Returns
+/// The orders sorted by id or the error of the first page that failed.
+///
+pub async fn order_history(symbol: &str, query: &OrderQuery) -> Result<Vec<OrderInfo>, String> {
+    let limit = query.limit.unwrap_or(MAX_HISTORY_LIMIT);
+    read_history(
+        query.start_time,
+        query.end_time,
+        query.order_id,
+        limit,
+        |o: &OrderInfo| o.time,
+        |o: &OrderInfo| o.order_id,
+        |page| {
+            let query = match page {
+                HistoryPage::Range {
+                    start_time,
+                    end_time,
+                } => OrderQuery {
+                    start_time: Some(start_time),
+                    end_time: Some(end_time),
+                    order_id: None,
+                    limit: Some(limit),
+                },
+                HistoryPage::FromId(order_id) => OrderQuery {
+                    order_id: Some(order_id),
+                    limit: Some(limit),
+                    ..Default::default()
+                },
+            };
+            async move { all_orders(symbol, &query).await }
+        },
+    )
+    .await
+}
+
+/// Retrieves every trade of a symbol in a time range, reading one page after another.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `query`: The range of the trades (it can be longer than 7 days), or the id of the first trade, and the
+///   trades per request (1000 by default).
+///
+/// # Returns
+/// The trades sorted by id or the error of the first page that failed.
+///
+pub async fn user_trades_history(
+    symbol: &str,
+    query: &TradeQuery,
+) -> Result<Vec<UserTrade>, String> {
+    let limit = query.limit.unwrap_or(MAX_HISTORY_LIMIT);
+    read_history(
+        query.start_time,
+        query.end_time,
+        query.from_id,
+        limit,
+        |t: &UserTrade| t.time,
+        |t: &UserTrade| t.id,
+        |page| {
+            let query = match page {
+                HistoryPage::Range {
+                    start_time,
+                    end_time,
+                } => TradeQuery {
+                    start_time: Some(start_time),
+                    end_time: Some(end_time),
+                    from_id: None,
+                    limit: Some(limit),
+                },
+                HistoryPage::FromId(from_id) => TradeQuery {
+                    from_id: Some(from_id),
+                    limit: Some(limit),
+                    ..Default::default()
+                },
+            };
+            async move { user_trades(symbol, &query).await }
+        },
+    )
+    .await
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_orders::new_order_market;
+    use crate::mock_server;
+    use crate::models::{OrderType, Side};
+    use tokio::test;
+
+    /// Test that a snapshot has the typed positions and open orders, and that it is published to the subscribers.
+    #[test]
+    async fn account_snapshot_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_position("BTCUSDT", "LONG", 0.002, 30000.0);
+        server.set_price(31000.0);
+        let mut receiver = subscribe_account_snapshots();
+
+        let snapshot = server.run(account_snapshot("BTCUSDT")).await.unwrap();
+        assert_eq!(snapshot.positions.len(), 2);
+        assert_eq!(snapshot.position_amount(PositionSide::Long), 0.002);
+        assert_eq!(snapshot.position_amount(PositionSide::Short), 0.0);
+        assert_eq!(snapshot.position_amount(PositionSide::Both), 0.0);
+        assert!((snapshot.unrealized_profit() - 2.0).abs() < 1e-9);
+        assert!(snapshot.open_orders.is_empty());
+
+        // Other tests may publish snapshots too.
+        loop {
+            let published = receiver.recv().await.unwrap();
+            if published.time == snapshot.time
+                && published.position_amount(PositionSide::Long) == 0.002
+            {
+                break;
+            }
+        }
+    }
+
+    /// Test that the trades, the realized PnL and the commissions of an open and a close are read back.
+    #[test]
+    async fn trades_and_realized_pnl_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+        let handle = server.clone();
+        let (trades, pnl, commissions, account) = server
+            .run(async move {
+                let mut id = 0;
+                new_order_market(&mut id, Side::Buy, PositionSide::Long).await;
+                handle.set_price(31000.0);
+                new_order_market(&mut id, Side::Sell, PositionSide::Long).await;
+
+                let trades = user_trades("BTCUSDT", &TradeQuery::default())
+                    .await
+                    .unwrap();
+                let pnl = realized_pnl("BTCUSDT", None, None).await.unwrap();
+                let commissions = income_history(&IncomeQuery {
+                    income_type: Some(IncomeType::Commission),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+                (
+                    trades,
+                    pnl,
+                    commissions,
+                    account_information().await.unwrap(),
+                )
+            })
+            .await;
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].side, Side::Buy);
+        assert_eq!(trades[1].side, Side::Sell);
+        assert_eq!(trades[1].position_side, PositionSide::Long);
+        let quantity = trades[0].qty;
+        assert!((trades[1].realized_pnl - quantity * 1000.0).abs() < 1e-6);
+        assert!((pnl - quantity * 1000.0).abs() < 1e-6);
+
+        assert_eq!(commissions.len(), 2);
+        let paid: f64 = commissions.iter().map(|i| i.income).sum();
+        assert!(paid < 0.0);
+        assert!((account.total_wallet_balance - (10_000.0 + pnl + paid)).abs() < 1e-6);
+    }
+
+    /// Test that the order and trade histories search the first week with records, then read the pages by id.
+    #[test]
+    async fn order_and_trade_history_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let month_ago = now - 30 * 24 * 3_600_000;
+
+        let (orders, trades, from_second, before) = server
+            .run(async move {
+                let mut id = 0;
+                for _ in 0..3 {
+                    new_order_market(&mut id, Side::Buy, PositionSide::Long).await;
+                    new_order_market(&mut id, Side::Sell, PositionSide::Long).await;
+                }
+                let orders = order_history(
+                    "BTCUSDT",
+                    &OrderQuery {
+                        start_time: Some(month_ago),
+                        limit: Some(4),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+                let trades = user_trades_history(
+                    "BTCUSDT",
+                    &TradeQuery {
+                        start_time: Some(month_ago),
+                        limit: Some(4),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+                let from_second = user_trades_history(
+                    "BTCUSDT",
+                    &TradeQuery {
+                        from_id: Some(trades[1].id),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+                let before = order_history(
+                    "BTCUSDT",
+                    &OrderQuery {
+                        start_time: Some(month_ago),
+                        end_time: Some(month_ago + 24 * 3_600_000),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+                (orders, trades, from_second, before)
+            })
+            .await;
+
+        assert_eq!(orders.len(), 6);
+        assert!(orders.windows(2).all(|w| w[0].order_id < w[1].order_id));
+        assert!(orders
+            .iter()
+            .all(|o| o.order_type == OrderType::Market && o.status == "FILLED"));
+        assert_eq!(orders[1].side, Side::Sell);
+        assert_eq!(trades.len(), 6);
+        assert_eq!(from_second.len(), 5);
+        assert!(before.is_empty());
+
+        let requests = server.requests();
+        let order_requests: Vec<&String> = requests
+            .iter()
+            .filter(|r| r.starts_with("GET /fapi/v1/allOrders"))
+            .collect();
+        // 5 weeks to find the orders, the page from the 5th order and the day without orders.
+        assert_eq!(order_requests.len(), 7);
+        assert!(order_requests[5].contains("orderId="));
+        assert!(order_requests[5].contains("limit=4"));
+    }
+
+    /// Test that a history from the epoch is only searched from the oldest week kept by the exchange.
+    #[test]
+    async fn order_history_retention_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+
+        let (orders, too_old) = server
+            .run(async move {
+                new_order_market(&mut 0, Side::Buy, PositionSide::Long).await;
+                let orders = order_history(
+                    "BTCUSDT",
+                    &OrderQuery {
+                        start_time: Some(0),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+                let too_old = order_history(
+                    "BTCUSDT",
+                    &OrderQuery {
+                        start_time: Some(0),
+                        end_time: Some(MAX_HISTORY_WINDOW),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+                (orders, too_old)
+            })
+            .await;
+
+        assert_eq!(orders.len(), 1);
+        assert!(too_old.is_empty());
+        // The 26 weeks of the retention, and no request for the range that ended before it.
+        let order_requests = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("GET /fapi/v1/allOrders"))
+            .count();
+        assert_eq!(order_requests, 26);
+    }
+
+    /// Test that the income history reads every page, including the incomes that share a time across two pages.
+    #[test]
+    async fn income_history_test() {
+        let server = mock_server::isolated();
+        for (i, time) in [1000, 2000, 2000, 2000, 3000, 4000, 5000]
+            .iter()
+            .enumerate()
+        {
+            server.add_income("BTCUSDT", "FUNDING_FEE", -(i as f64), *time);
+        }
+        server.add_income("ETHUSDT", "FUNDING_FEE", -10.0, 3000);
+
+        let (history, page, range) = server
+            .run(async {
+                let query = IncomeQuery {
+                    symbol: Some("BTCUSDT".to_string()),
+                    limit: Some(2),
+                    ..Default::default()
+                };
+                let history = income_history(&query).await.unwrap();
+                let page = income(&query).await.unwrap();
+                let range = income_history(&IncomeQuery {
+                    start_time: Some(2000),
+                    end_time: Some(4000),
+                    limit: Some(3),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+                (history, page, range)
+            })
+            .await;
+
+        assert_eq!(history.len(), 7);
+        assert!(history
+            .iter()
+            .all(|i| i.income_type == IncomeType::FundingFee));
+        assert!(history.windows(2).all(|w| w[0].time <= w[1].time));
+        // Without start time, a page has the last incomes.
+        assert_eq!(
+            page.iter().map(|i| i.time).collect::<Vec<_>>(),
+            vec![4000, 5000]
+        );
+        assert_eq!(range.len(), 6);
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("GET /fapi/v1/income") && r.contains("startTime=2000")));
+    }
+}
@@ -0,0 +1,500 @@
+// accounts.rs - Multiple Accounts
+
+// This file contains the registry of the named accounts (e.g., the master account and its sub-accounts), so one
+// process can trade several accounts. Each account has its own credentials and signer, an optional base url and
+// its default symbol settings (leverage, margin type and position mode).
+
+// The requests are routed with `with_account`: every request of the future (orders, positions, history, ...) is
+// sent to the base url of the account and signed with its key. Outside of it, the credentials of credentials.rs and
+// the environment base url are used, as before. The tasks spawned with `binance_orders::spawn_scoped` (account
+// snapshots, position reconciliation, countdown heartbeat) keep the account of the caller, so their streams belong
+// to it.
+
+// The settings kept per exchange (position mode cache, risk limits, kill switch, slippage guard) are kept per
+// account too (see `binance_orders::exchange_key`), so the sub-accounts of one server do not share them. The rate
+// limiter is still shared by the accounts of a server, like the weight limit of the exchange (per IP).
+
+// `aggregated_exposure` reads the positions of every registered account and sums them per symbol and per account.
+
+use crate::binance_orders::position_risk;
+use crate::credentials::Credentials;
+use crate::error::UNKNOWN_ACCOUNT;
+use crate::margin::{set_leverage, set_margin_type, MarginType};
+use crate::models::PositionSide;
+use crate::position_mode::{set_position_mode, PositionMode};
+use crate::signer::{signer_from_credentials, Signer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Amount below which a position is considered closed.
+const AMOUNT_TOLERANCE: f64 = 1e-12;
+
+tokio::task_local! {
+    /// Account of the requests inside `with_account`.
+    static CURRENT_ACCOUNT: Arc<Account>;
+}
+
+/// Configuration of an account.
+#[derive(Debug, Clone)]
+pub struct AccountConfig {
+    /// Name used to route the requests (e.g., "main", "sub-hedge").
+    pub name: String,
+    pub credentials: Credentials,
+    /// Base url of the api, or None for the one of the environment.
+    pub base_url: Option<String>,
+    /// Symbol traded by default with the account.
+    pub default_symbol: String,
+    /// Leverage of the default symbol, set by `apply_account_settings`.
+    pub leverage: Option<u32>,
+    /// Margin type of the default symbol, set by `apply_account_settings`.
+    pub margin_type: Option<MarginType>,
+    /// Position mode of the account, set by `apply_account_settings`.
+    pub position_mode: Option<PositionMode>,
+}
+
+impl AccountConfig {
+    /// Creates the configuration of an account on the environment base url, trading BTCUSDT with the settings it
+    /// already has.
+    pub fn new(name: &str, credentials: Credentials) -> Self {
+        AccountConfig {
+            name: name.to_string(),
+            credentials,
+            base_url: None,
+            default_symbol: "BTCUSDT".to_string(),
+            leverage: None,
+            margin_type: None,
+            position_mode: None,
+        }
+    }
+}
+
+/// A registered account, with the signer of its key.
+pub struct Account {
+    config: AccountConfig,
+    credentials: Arc<Credentials>,
+    signer: Arc<dyn Signer>,
+}
+
+impl Account {
+    /// Get the name of the account.
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Get the base url of the account, or None when it uses the environment one.
+    pub fn base_url(&self) -> Option<&str> {
+        self.config.base_url.as_deref()
+    }
+
+    /// Get the symbol traded by default with the account.
+    pub fn default_symbol(&self) -> &str {
+        &self.config.default_symbol
+    }
+
+    /// Get the configuration of the account.
+    pub fn config(&self) -> &AccountConfig {
+        &self.config
+    }
+
+    /// Get the credentials of the account.
+    pub fn credentials(&self) -> Arc<Credentials> {
+        self.credentials.clone()
+    }
+
+    /// Get the signer of the SIGNED requests of the account.
+    pub fn signer(&self) -> Arc<dyn Signer> {
+        self.signer.clone()
+    }
+}
+
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registered accounts per name.
+static ACCOUNTS: RwLock<Option<HashMap<String, Arc<Account>>>> = RwLock::new(None);
+
+/// Registers an account, replacing the account with the same name.
+///
+/// # Arguments
+/// * `config`: The configuration of the account.
+///
+/// # Returns
+/// Ok when the account was registered, or the error of its signer (e.g., a private key that can not be read).
+///
+pub fn register_account(config: AccountConfig) -> Result<(), String> {
+    let signer = signer_from_credentials(&config.credentials)?;
+    let account = Account {
+        credentials: Arc::new(config.credentials.clone()),
+        config,
+        signer,
+    };
+    info!(account = account.name(), "Account registered");
+    ACCOUNTS
+        .write()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(account.name().to_string(), Arc::new(account));
+    Ok(())
+}
+
+/// Removes an account from the registry.
+///
+/// # Returns
+/// True when the account was registered.
+///
+pub fn remove_account(name: &str) -> bool {
+    ACCOUNTS
+        .write()
+        .unwrap()
+        .as_mut()
+        .is_some_and(|accounts| accounts.remove(name).is_some())
+}
+
+/// Get a registered account.
+pub fn account(name: &str) -> Option<Arc<Account>> {
+    ACCOUNTS
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|accounts| accounts.get(name).cloned())
+}
+
+/// Get the names of the registered accounts, sorted.
+pub fn account_names() -> Vec<String> {
+    let mut names: Vec<String> = ACCOUNTS
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|accounts| accounts.keys().cloned().collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Get the account of the current task (inside `with_account`), or None for the default credentials.
+pub fn current_account() -> Option<Arc<Account>> {
+    CURRENT_ACCOUNT.try_with(|account| account.clone()).ok()
+}
+
+/// Runs a future with every request sent to the account `name`.
+///
+/// # Arguments
+/// * `name`: The name of a registered account.
+/// * `future`: The future whose requests are routed to the account.
+///
+/// # Returns
+/// The output of the future, or E20 when the account is not registered (the future is not run then).
+///
+pub async fn with_account<F: Future>(name: &str, future: F) -> Result<F::Output, String> {
+    let account = account(name).ok_or_else(|| UNKNOWN_ACCOUNT.to_string())?;
+    Ok(CURRENT_ACCOUNT.scope(account, future).await)
+}
+
+/// Runs a future in an account, or in the default credentials when it is None (see `spawn_scoped`).
+pub(crate) async fn in_account<F: Future>(account: Option<Arc<Account>>, future: F) -> F::Output {
+    match account {
+        Some(account) => CURRENT_ACCOUNT.scope(account, future).await,
+        None => future.await,
+    }
+}
+
+/// Applies the position mode, and the margin type and leverage of the default symbol, of an account. The settings
+/// that are None are not changed.
+///
+/// # Returns
+/// Ok when the account has the settings, or E20 or the mapped error of the first setting that failed.
+///
+pub async fn apply_account_settings(name: &str) -> Result<(), String> {
+    with_account(name, async {
+        let config = current_account().unwrap().config().clone();
+        if let Some(mode) = config.position_mode {
+            set_position_mode(mode).await?;
+        }
+        if let Some(margin_type) = config.margin_type {
+            set_margin_type(&config.default_symbol, margin_type).await?;
+        }
+        if let Some(leverage) = config.leverage {
+            set_leverage(&config.default_symbol, leverage).await?;
+        }
+        info!(account = name, symbol = %config.default_symbol, "Account settings applied");
+        Ok(())
+    })
+    .await?
+}
+
+/// Open position of one account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionExposure {
+    pub account: String,
+    pub symbol: String,
+    pub position_side: PositionSide,
+    /// Amount of the position (negative when short).
+    pub position_amt: f64,
+    pub mark_price: f64,
+    /// Amount times the mark price (negative when short).
+    pub notional: f64,
+    pub unrealized_profit: f64,
+}
+
+/// Exposure of every account in one symbol.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolExposure {
+    pub symbol: String,
+    /// Sum of the amounts of the positions (the longs and shorts of the accounts offset each other).
+    pub net_amount: f64,
+    pub net_notional: f64,
+    /// Sum of the absolute notional values of the positions.
+    pub gross_notional: f64,
+    pub unrealized_profit: f64,
+    pub positions: Vec<PositionExposure>,
+}
+
+/// Exposure of one account in every symbol.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountExposure {
+    pub account: String,
+    pub net_notional: f64,
+    pub gross_notional: f64,
+    pub unrealized_profit: f64,
+}
+
+/// Exposure of the registered accounts, per symbol and per account.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AggregatedExposure {
+    /// Sorted by symbol.
+    pub symbols: Vec<SymbolExposure>,
+    /// Sorted by account name, with the accounts without positions.
+    pub accounts: Vec<AccountExposure>,
+    pub net_notional: f64,
+    pub gross_notional: f64,
+    pub unrealized_profit: f64,
+}
+
+impl AggregatedExposure {
+    /// Get the exposure of a symbol.
+    pub fn symbol(&self, symbol: &str) -> Option<&SymbolExposure> {
+        self.symbols.iter().find(|s| s.symbol == symbol)
+    }
+
+    /// Get the exposure of an account.
+    pub fn account(&self, name: &str) -> Option<&AccountExposure> {
+        self.accounts.iter().find(|a| a.account == name)
+    }
+}
+
+/// Reads the open positions of every registered account and aggregates them.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT"), or None for every symbol.
+///
+/// # Returns
+/// The `AggregatedExposure`, or the mapped error of the first account whose positions could not be read.
+///
+pub async fn aggregated_exposure(symbol: Option<&str>) -> Result<AggregatedExposure, String> {
+    let mut exposure = AggregatedExposure::default();
+    let mut symbols: BTreeMap<String, SymbolExposure> = BTreeMap::new();
+
+    for name in account_names() {
+        let positions = match with_account(&name, position_risk(symbol)).await {
+            Ok(Ok(positions)) => positions,
+            // Removed while the others were read.
+            Err(_) => continue,
+            Ok(Err(error)) => {
+                warn!(account = %name, %error, "Failed to read the positions of the account");
+                return Err(error);
+            }
+        };
+
+        let mut account = AccountExposure {
+            account: name.clone(),
+            ..Default::default()
+        };
+        for position in positions {
+            if position.position_amt.abs() < AMOUNT_TOLERANCE {
+                continue;
+            }
+            let position = PositionExposure {
+                account: name.clone(),
+                notional: position.position_amt * position.mark_price,
+                symbol: position.symbol,
+                position_side: position.position_side,
+                position_amt: position.position_amt,
+                mark_price: position.mark_price,
+                unrealized_profit: position.un_realized_profit,
+            };
+            account.net_notional += position.notional;
+            account.gross_notional += position.notional.abs();
+            account.unrealized_profit += position.unrealized_profit;
+
+            let total = symbols
+                .entry(position.symbol.clone())
+                .or_insert_with(|| SymbolExposure {
+                    symbol: position.symbol.clone(),
+                    ..Default::default()
+                });
+            total.net_amount += position.position_amt;
+            total.net_notional += position.notional;
+            total.gross_notional += position.notional.abs();
+            total.unrealized_profit += position.unrealized_profit;
+            total.positions.push(position);
+        }
+
+        exposure.net_notional += account.net_notional;
+        exposure.gross_notional += account.gross_notional;
+        exposure.unrealized_profit += account.unrealized_profit;
+        exposure.accounts.push(account);
+    }
+
+    exposure.symbols = symbols.into_values().collect();
+    Ok(exposure)
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_orders::place_order;
+    use crate::credentials::Secret;
+    use crate::mock_server;
+    use crate::models::{OrderRequest, OrderType, Side};
+    use crate::risk::{is_kill_switch_active, set_kill_switch};
+    use crate::signer::KeyType;
+    use tokio::test;
+
+    fn config(name: &str, api_key: &str, server: &mock_server::MockServer) -> AccountConfig {
+        let credentials = Credentials {
+            api_key: Secret::new(api_key.to_string()),
+            secret_key: Some(Secret::new(format!("{}-secret", api_key))),
+            key_type: KeyType::Hmac,
+            private_key_path: None,
+        };
+        AccountConfig {
+            base_url: Some(server.url().to_string()),
+            ..AccountConfig::new(name, credentials)
+        }
+    }
+
+    /// Test that the requests are routed to the server and the key of each account, that the settings of two
+    /// accounts of one server are kept apart, and that the exposure is aggregated across the accounts.
+    ///
+    /// The registry is shared by the tests running in parallel, so only the accounts of this test are looked at, and
+    /// they are removed at the end.
+    #[test]
+    async fn accounts_routing_and_exposure_test() {
+        let alpha_server = mock_server::isolated();
+        let beta_server = mock_server::isolated();
+        alpha_server.set_price(30000.0);
+        beta_server.set_price(30000.0);
+        alpha_server.set_position("BTCUSDT", "BOTH", 0.01, 29000.0);
+        beta_server.set_position("BTCUSDT", "BOTH", -0.004, 31000.0);
+
+        register_account(config("alpha", "alpha-key", &alpha_server)).unwrap();
+        register_account(config("beta", "beta-key", &beta_server)).unwrap();
+        assert_eq!(
+            with_account("gamma", async {}).await.unwrap_err(),
+            UNKNOWN_ACCOUNT
+        );
+
+        // Orders are sent to the server of the account, with its key.
+        let order = OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            quantity: 0.001,
+            price: None,
+            stop_price: None,
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+        with_account("beta", place_order(&order))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!alpha_server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("POST /fapi/v1/order")));
+        let beta_requests = beta_server.requests();
+        let beta_keys = beta_server.api_keys();
+        let index = beta_requests
+            .iter()
+            .position(|r| r.starts_with("POST /fapi/v1/order"))
+            .unwrap();
+        assert_eq!(beta_keys[index], "beta-key");
+        beta_server.set_position("BTCUSDT", "BOTH", -0.004, 31000.0);
+
+        let exposure = aggregated_exposure(Some("BTCUSDT")).await.unwrap();
+        let btc = exposure.symbol("BTCUSDT").unwrap();
+        let positions: Vec<&PositionExposure> = btc
+            .positions
+            .iter()
+            .filter(|p| p.account == "alpha" || p.account == "beta")
+            .collect();
+        assert_eq!(positions.len(), 2);
+        let net_amount: f64 = positions.iter().map(|p| p.position_amt).sum();
+        let unrealized_profit: f64 = positions.iter().map(|p| p.unrealized_profit).sum();
+        assert!((net_amount - 0.006).abs() < 1e-9);
+        assert!((unrealized_profit - 14.0).abs() < 1e-6);
+        let alpha = exposure.account("alpha").unwrap();
+        let beta = exposure.account("beta").unwrap();
+        assert!((alpha.net_notional - 300.0).abs() < 1e-6);
+        assert!((beta.net_notional + 120.0).abs() < 1e-6);
+        assert!((alpha.gross_notional + beta.gross_notional - 420.0).abs() < 1e-6);
+
+        // A sub-account of the same server has its own key and its own settings.
+        register_account(config("alpha-sub", "alpha-sub-key", &alpha_server)).unwrap();
+        with_account("alpha-sub", set_kill_switch(true))
+            .await
+            .unwrap();
+        assert!(with_account("alpha-sub", is_kill_switch_active())
+            .await
+            .unwrap());
+        assert!(!with_account("alpha", is_kill_switch_active())
+            .await
+            .unwrap());
+        assert!(!alpha_server.run(is_kill_switch_active()).await);
+
+        // The settings of an account are applied to its server only.
+        register_account(AccountConfig {
+            leverage: Some(10),
+            margin_type: Some(MarginType::Isolated),
+            ..config("alpha", "alpha-key", &alpha_server)
+        })
+        .unwrap();
+        apply_account_settings("alpha").await.unwrap();
+        let alpha_requests = alpha_server.requests();
+        let alpha_keys = alpha_server.api_keys();
+        let index = alpha_requests
+            .iter()
+            .position(|r| r.starts_with("POST /fapi/v1/leverage") && r.contains("leverage=10"))
+            .unwrap();
+        assert_eq!(alpha_keys[index], "alpha-key");
+        assert!(alpha_requests
+            .iter()
+            .any(|r| r.starts_with("POST /fapi/v1/marginType")));
+        assert!(!beta_server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("POST /fapi/v1/leverage")));
+
+        assert!(remove_account("alpha-sub"));
+        assert!(!remove_account("alpha-sub"));
+        let names = account_names();
+        assert!(names.contains(&"alpha".to_string()) && names.contains(&"beta".to_string()));
+        assert!(!names.contains(&"alpha-sub".to_string()));
+
+        assert!(remove_account("alpha"));
+        assert!(remove_account("beta"));
+        assert!(account("alpha").is_none() && account("beta").is_none());
+    }
+}
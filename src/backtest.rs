@@ -0,0 +1,209 @@
+// backtest.rs - Backtest Exchange
+
+// This file contains the `BacktestExchange`, an implementation of the `Exchange` trait that replays historical
+// candles and simulates the orders with the matching engine of simulator.rs.
+
+// The strategy sees only the candles that were already closed at the current step of the replay, so it can not look
+// into the future. Each call to `step` closes the next candle, fills the resting orders reached by its high/low and
+// updates the positions and the wallet balance.
+
+use crate::exchange::Exchange;
+use crate::get_candles::get_klines;
+use crate::models::{Balance, KlineData, OpenOrder, OrderAck, OrderRequest, PositionRisk};
+use crate::simulator::{SimFill, Simulator, SimulatorConfig};
+use async_trait::async_trait;
+use std::sync::Mutex;
+
+struct BacktestState {
+    /// Number of candles of the history that are already closed.
+    cursor: usize,
+    simulator: Simulator,
+}
+
+/// `Exchange` implementation that replays historical candles of one symbol.
+pub struct BacktestExchange {
+    symbol: String,
+    history: Vec<KlineData>,
+    state: Mutex<BacktestState>,
+}
+
+impl BacktestExchange {
+    /// Creates a backtest over the given candles (ordered from the oldest to the newest).
+    ///
+    /// # Arguments
+    /// * `symbol`: The symbol of the candles (e.g., "BTCUSDT").
+    /// * `history`: The candles that will be replayed.
+    /// * `config`: The initial balance and fees of the simulation.
+    ///
+    pub fn new(symbol: &str, history: Vec<KlineData>, config: SimulatorConfig) -> Self {
+        BacktestExchange {
+            symbol: symbol.to_string(),
+            history,
+            state: Mutex::new(BacktestState {
+                cursor: 0,
+                simulator: Simulator::new(config),
+            }),
+        }
+    }
+
+    /// Creates a backtest over the last `limit` candles of the exchange.
+    pub async fn from_binance(
+        symbol: &str,
+        interval: &str,
+        limit: usize,
+        config: SimulatorConfig,
+    ) -> Result<Self, String> {
+        let history = get_klines(symbol, interval, limit).await?;
+        Ok(BacktestExchange::new(symbol, history, config))
+    }
+
+    /// Closes the next candle of the history.
+    ///
+    /// # Returns
+    /// `false` when there are no more candles to replay.
+    ///
+    pub fn step(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.cursor >= self.history.len() {
+            return false;
+        }
+        let candle = self.history[state.cursor].clone();
+        state.simulator.on_candle(&self.symbol, &candle);
+        state.cursor += 1;
+        true
+    }
+
+    /// Every fill done since the beginning of the replay.
+    pub fn fills(&self) -> Vec<SimFill> {
+        self.state.lock().unwrap().simulator.fills().to_vec()
+    }
+}
+
+#[async_trait]
+impl Exchange for BacktestExchange {
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderAck, String> {
+        self.state.lock().unwrap().simulator.submit(order)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<OrderAck, String> {
+        self.state
+            .lock()
+            .unwrap()
+            .simulator
+            .cancel(symbol, order_id)
+    }
+
+    async fn amend_order(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        quantity: f64,
+        price: f64,
+    ) -> Result<OrderAck, String> {
+        self.state
+            .lock()
+            .unwrap()
+            .simulator
+            .amend(symbol, order_id, quantity, price)
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<OpenOrder>, String> {
+        Ok(self.state.lock().unwrap().simulator.open_orders(symbol))
+    }
+
+    async fn positions(&self, symbol: &str) -> Result<Vec<PositionRisk>, String> {
+        Ok(self.state.lock().unwrap().simulator.positions(symbol))
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>, String> {
+        Ok(self.state.lock().unwrap().simulator.balances())
+    }
+
+    async fn candles(
+        &self,
+        symbol: &str,
+        _interval: &str,
+        limit: usize,
+    ) -> Result<Vec<KlineData>, String> {
+        if symbol != self.symbol {
+            return Ok(Vec::new());
+        }
+        let cursor = self.state.lock().unwrap().cursor;
+        let start = cursor.saturating_sub(limit);
+        Ok(self.history[start..cursor].to_vec())
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{OrderType, PositionSide, Side};
+    use tokio::test;
+
+    fn candle(open_time: i64, open: f64, low: f64, high: f64, close: f64) -> KlineData {
+        KlineData {
+            open_time,
+            open,
+            high,
+            low,
+            close,
+            volume: 1.0,
+            close_time: open_time + 59_999,
+            quote_asset_volume: close,
+            number_of_trades: 1,
+            take_buy_base_asset_volume: 0.5,
+            take_buy_quote_asset_volume: close / 2.0,
+            ignore: 0.0,
+        }
+    }
+
+    /// Test that the strategy only sees closed candles and that the orders are filled during the replay.
+    ///
+    /// It places a stop order after the first candle and checks that it is filled by the high of the third one.
+    ///
+    #[test]
+    async fn backtest_replay_test() {
+        let history = vec![
+            candle(0, 30000.0, 29900.0, 30100.0, 30000.0),
+            candle(60_000, 30000.0, 29950.0, 30200.0, 30150.0),
+            candle(120_000, 30150.0, 30100.0, 30600.0, 30500.0),
+        ];
+        let exchange = BacktestExchange::new("BTCUSDT", history, SimulatorConfig::default());
+
+        assert!(exchange
+            .candles("BTCUSDT", "1m", 10)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(exchange.step());
+        assert_eq!(
+            exchange.candles("BTCUSDT", "1m", 10).await.unwrap().len(),
+            1
+        );
+
+        let order = OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::StopMarket,
+            quantity: 0.01,
+            price: None,
+            stop_price: Some(30400.0),
+            position_side: PositionSide::Long,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+        exchange.place_order(&order).await.unwrap();
+
+        assert!(exchange.step());
+        assert!(exchange.fills().is_empty());
+        assert!(exchange.step());
+        assert_eq!(exchange.fills().len(), 1);
+        assert!(!exchange.step());
+
+        let positions = exchange.positions("BTCUSDT").await.unwrap();
+        assert_eq!(positions[0].position_amt, 0.01);
+        assert_eq!(positions[0].entry_price, 30400.0);
+    }
+}
@@ -11,18 +11,32 @@
 // Note: Make sure to handle API authentication securely, following best practices to protect sensitive
 // information such as API keys and secret keys.
 
+use crate::account;
+use crate::accounts;
 use crate::convert_to_formatted_string;
+use crate::credentials;
 use crate::error;
 use crate::get_candles;
+use crate::models::{
+    Balance, BookTicker, ExchangeInfo, FundingRate, OpenOrder, OrderAck, OrderInfo, OrderRequest,
+    OrderType, PositionRisk, PositionSide, PremiumIndex, Side, SymbolInfo, WorkingType,
+};
+use crate::position_mode::{self, PositionMode};
+use crate::rate_limiter;
+use crate::request::BinanceRequest;
+use crate::signer;
+use crate::slippage;
+use crate::time_sync;
 use async_recursion::async_recursion;
 use binance_spot_connector_rust::http::request;
 use futures_util::future::BoxFuture;
 use reqwest::Client;
 use serde_json::Value;
-use urlencoding::encode;
 
 use serde::Deserialize;
 use substring::Substring;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
 
 use error::*;
 use get_candles::{get_candle_info, get_candle_last_min};
@@ -31,15 +45,39 @@ static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_P
 use hmac::{Hmac, Mac, NewMac};
 use json::JsonValue;
 use reqwest::{header, Response, StatusCode};
-use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
 use rust_decimal_macros::dec;
 use sha2::Sha256;
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{env, string};
+use tokio::task::JoinHandle;
 
 pub const QUANTITY_IN_DOLLAR: u64 = 50; //Value that witch strategy will use in the orders (in dollar).
 
+/// Maximum number of rates of a /fapi/v1/fundingRate request.
+const MAX_FUNDING_RATE_LIMIT: u32 = 1000;
+
+/// Attempts of an order rejected with E17: the position mode is read again and the order is sent once more.
+const MAX_POSITION_SIDE_ATTEMPTS: u32 = 2;
+
+/// Attempts of a request that does not reach the exchange, and the time waited before the second one (doubled after
+/// each attempt, up to MAX_RESEND_BACKOFF).
+pub const MAX_SEND_ATTEMPTS: u32 = 4;
+const RESEND_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_RESEND_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Time (in milliseconds) that a request can take before it is considered lost and sent again.
+static REQUEST_TIMEOUT_MS: AtomicU64 = AtomicU64::new(10_000);
+
+tokio::task_local! {
+    /// Base url used by `exchange_url` inside `with_exchange_url`, instead of the environment one.
+    static EXCHANGE_URL_OVERRIDE: String;
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ResultResponseBinance {
     code: i32,
@@ -47,6 +85,15 @@ pub struct ResultResponseBinance {
 }
 
 pub async fn exchange_url() -> String {
+    if let Some(url) = accounts::current_account().and_then(|a| a.base_url().map(str::to_string)) {
+        return url;
+    }
+    if let Ok(url) = EXCHANGE_URL_OVERRIDE.try_with(|url| url.clone()) {
+        return url;
+    }
+
+    credentials::load_dotenv();
+
     // Verifica se estamos em um ambiente de teste
     let is_test = env::var("RUST_TEST").is_ok();
 
@@ -60,77 +107,162 @@ pub async fn exchange_url() -> String {
     }
 }
 
+/// Runs a future with every request of this crate sent to `url` instead of the environment base url.
+///
+/// It is used by the mock server of the tests, so that each test can talk with its own server.
+///
+pub async fn with_exchange_url<F: Future>(url: String, future: F) -> F::Output {
+    EXCHANGE_URL_OVERRIDE.scope(url, future).await
+}
+
+/// Get the key of the settings kept per exchange (position mode, risk limits, slippage guard): the base url, with the
+/// name of the account inside `accounts::with_account`, so the accounts of one server do not share them.
+pub async fn exchange_key() -> String {
+    let url = exchange_url().await;
+    match accounts::current_account() {
+        Some(account) => format!("{}#{}", url, account.name()),
+        None => url,
+    }
+}
+
+/// Spawns a task that keeps the exchange url (`with_exchange_url`) and the account (`accounts::with_account`) of the
+/// caller, that tokio does not pass to the new task.
+pub fn spawn_scoped<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let url = EXCHANGE_URL_OVERRIDE.try_with(|url| url.clone()).ok();
+    let account = accounts::current_account();
+    tokio::spawn(async move {
+        let future = accounts::in_account(account, future);
+        match url {
+            Some(url) => with_exchange_url(url, future).await,
+            None => future.await,
+        }
+    })
+}
+
+/// Changes the time that a request can take before it is considered lost and sent again.
+pub fn set_request_timeout(timeout: Duration) {
+    REQUEST_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
 /// In the Binance futures api, the amount that will be invested in each order is in BTC. So, it is necessary to
 /// convert the amount in USDT to an BTC quantity. That process is done here.
-pub async fn calculate_quantity_in_btc(min_price: bool) -> f64 {
-    //Get current price
-    let btc_in_dollar_string: String = price_ticker("BTCUSDT".to_string()).await;
+///
+/// Returns the minimum quantity (0.001) when `min_price` is set, E04 when the quantity rounds to 0, or the mapped
+/// error of the price request.
+pub async fn calculate_quantity_in_btc(min_price: bool) -> Result<f64, String> {
+    if min_price {
+        return Ok(0.001);
+    }
 
-    //Converting to float
-    let btc_in_dollar_string_without_quotes = btc_in_dollar_string.replace('"', "");
-    let btc_in_dollar = btc_in_dollar_string_without_quotes.parse::<f64>().unwrap();
+    //Get current price
+    let btc_in_dollar = last_price("BTCUSDT").await?;
 
-    //Result is the quantity of BTC that we will buy.
-    let mut result = QUANTITY_IN_DOLLAR as f64 / btc_in_dollar;
-    //Truncating the result.
-    let result_with_precision = format!("{:.3}", result);
-    result = result_with_precision.parse::<f64>().unwrap();
+    //Result is the quantity of BTC that we will buy, rounded to 3 decimals.
+    let result = (QUANTITY_IN_DOLLAR as f64 / btc_in_dollar * 1000.0).round() / 1000.0;
 
-    if result == 0.000 {
-        println!("{}", ERROR_NOT_VALID_QUANTITY);
-        std::process::exit(1);
+    if !result.is_finite() || result <= 0.0 {
+        tracing::error!("{}", ERROR_NOT_VALID_QUANTITY);
+        return Err(ERROR_NOT_VALID_QUANTITY.to_string());
     }
+    Ok(result)
+}
 
-    if min_price {
-        0.001
-    } else {
-        result
-    }
+/// Reads the update time and the id of a placed order, and logs the order.
+///
+/// Returns E02 when the response has no valid update time or order id.
+async fn placed_order_id(data: &Value) -> Result<u64, String> {
+    let update_time = order_update_time(data)?;
+    let time = convert_to_formatted_string(update_time).await;
+    debug!(order = %data, update_time = %time, "Order data");
+    data["orderId"]
+        .to_string()
+        .parse()
+        .map_err(|_| ERROR_NOT_MAPPED.to_string())
+}
+
+/// Reads the update time of an order response, or E02 when it is missing.
+fn order_update_time(data: &Value) -> Result<u128, String> {
+    data["updateTime"]
+        .to_string()
+        .parse::<u128>()
+        .map_err(|_| ERROR_NOT_MAPPED.to_string())
 }
 
 /// Get binance client. It is necessary to stablish communication if the exchange.
-pub async fn get_client() -> reqwest::Client {
-    dotenv::dotenv().ok();
+///
+/// Returns E24 when the credentials can not be loaded (the cause is logged, see credentials.rs).
+pub async fn get_client() -> Result<reqwest::Client, String> {
+    let credentials = credentials::credentials().map_err(credentials_error)?;
+    Ok(build_client(Some(credentials.api_key.expose())))
+}
+
+/// Logs why the credentials or the signer could not be loaded, and returns E24.
+fn credentials_error(error: String) -> String {
+    tracing::error!(%error, "The credentials of the API key could not be loaded");
+    CREDENTIALS_ERROR.to_string()
+}
+
+/// Response returned for a request that was not sent because the credentials could not be loaded (mapped to E24).
+pub fn credentials_error_response() -> Response {
+    let body = serde_json::json!({ "code": -2014, "msg": CREDENTIALS_ERROR });
+    Response::from(
+        http::Response::builder()
+            .status(StatusCode::UNAUTHORIZED.as_u16())
+            .body(body.to_string())
+            .unwrap(),
+    )
+}
+
+/// Get binance client without the API key, for the public endpoints (see request.rs).
+pub async fn get_public_client() -> reqwest::Client {
+    build_client(None)
+}
 
+fn build_client(api_key: Option<&str>) -> reqwest::Client {
     let mut headers = header::HeaderMap::new();
     headers.insert(
         header::CONTENT_TYPE,
         header::HeaderValue::from_static("application/x-www-form-urlencoded"),
     );
 
-    headers.insert(
-        header::HeaderName::from_static("x-mbx-apikey"),
-        header::HeaderValue::from_str(&env::var("BINANCE_API_KEY").unwrap()).unwrap(),
-    );
+    if let Some(api_key) = api_key {
+        let mut api_key = header::HeaderValue::from_str(api_key).unwrap();
+        api_key.set_sensitive(true);
+        headers.insert(header::HeaderName::from_static("x-mbx-apikey"), api_key);
+    }
 
     reqwest::Client::builder()
         .default_headers(headers)
         .user_agent(APP_USER_AGENT)
+        .timeout(Duration::from_millis(
+            REQUEST_TIMEOUT_MS.load(Ordering::Relaxed),
+        ))
         .build()
         .unwrap()
 }
 
-/// Get actual time stamp.
+/// Get actual time stamp, corrected by the offset of the exchange server clock (see time_sync.rs).
 ///
 /// Parameters:
 /// - SystemTime: It is the actual time of the system. ( ex: SystemTime::now() );
 ///
 pub async fn get_timestamp(time: SystemTime) -> u128 {
     let since_epoch = time.duration_since(UNIX_EPOCH).unwrap();
-    since_epoch.as_millis()
+    (since_epoch.as_millis() as i128 + time_sync::clock_offset().await as i128) as u128
 }
 
-/// Sign a request using Users secret key.
+/// Sign a request using the API key of the user (HMAC, Ed25519 or RSA, see signer.rs).
 ///
 /// Parameters:
-/// - request: It is the actual time of the system. ( ex: SystemTime::now() );
+/// - request: The query string of the request, without the signature.
 ///
-pub async fn get_signature(request: String) -> String {
-    let secret_key = env::var("BINANCE_SECRET_KEY").unwrap();
-    let mut signed_key = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes()).unwrap();
-    signed_key.update(request.as_bytes());
-
-    hex::encode(signed_key.finalize().into_bytes())
+/// Returns E24 when the signer can not be created from the credentials.
+pub async fn get_signature(request: String) -> Result<String, String> {
+    Ok(signer::signer().map_err(credentials_error)?.sign(&request))
 }
 
 /// Function that place a new order in the exchange.
@@ -138,125 +270,117 @@ pub async fn get_signature(request: String) -> String {
 /// Parameters:
 /// - price_order: it is the price - 1 of the order that will be executed.
 /// - last_order_id: mutable reference that will store the order id.
-/// - is_buy_order: bool that indicates with the order will be buy or sell.
+/// - side: the side of the order (buy or sell).
+/// - is_reduce_only: bool that indicates if the order can only reduce the position.
+/// - position_side: the position side of the order (BOTH lets it be derived from the position mode).
+/// - working_type: the price that triggers the stop: the last price (CONTRACT_PRICE) or the mark price.
+///
+/// When the stop would trigger immediately (E01), a market order is sent instead (`StopFallback::Market`). Use
+/// `new_order_with_fallback` to choose what happens then.
 ///
-#[async_recursion]
 pub async fn new_order(
     price_order: f64,
     last_order_id: &mut u64,
-    is_buy_order: bool,
+    side: Side,
     is_reduce_only: bool,
-    position_side: Option<String>,
+    position_side: PositionSide,
+    working_type: WorkingType,
 ) -> String {
-    let new_price_order: Decimal;
-    let buy_or_sell: String;
-    let quantity = calculate_quantity_in_btc(true).await;
-
-    let mut p_side = "BOTH".to_string();
-    let temp_position_side = position_side.clone();
-    if temp_position_side.is_some() {
-        let unwrapped_position_side = temp_position_side.unwrap();
-        if unwrapped_position_side == "LONG" {
-            p_side = "LONG".to_string();
-
-            if is_buy_order {
-                new_price_order =
-                    (Decimal::from_f64_retain(price_order + 1.0).unwrap() * dec!(100)).trunc()
-                        / dec!(100);
-            } else {
-                new_price_order = (Decimal::from_f64_retain(price_order).unwrap() * dec!(100))
-                    .trunc()
-                    / dec!(100);
-            }
-        } else {
-            p_side = "SHORT".to_string();
-
-            if !is_buy_order {
-                new_price_order =
-                    (Decimal::from_f64_retain(price_order - 1.0).unwrap() * dec!(100)).trunc()
-                        / dec!(100);
-            } else {
-                new_price_order = (Decimal::from_f64_retain(price_order).unwrap() * dec!(100))
-                    .trunc()
-                    / dec!(100);
-            }
-        }
-        if is_buy_order {
-            buy_or_sell = "BUY".to_string();
-        } else {
-            buy_or_sell = "SELL".to_string();
-        }
-    } else if is_buy_order {
-        new_price_order =
-            (Decimal::from_f64_retain(price_order + 1.0).unwrap() * dec!(100)).trunc() / dec!(100);
-        buy_or_sell = "BUY".to_string();
-    } else {
-        new_price_order =
-            (Decimal::from_f64_retain(price_order - 1.0).unwrap() * dec!(100)).trunc() / dec!(100);
-        buy_or_sell = "SELL".to_string();
-    }
-
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let mut params = format!(
-            "symbol=BTCUSDT&side={}&type=STOP_MARKET&stopPrice={}&timeInForce=GTC&quantity={}&timestamp={}&reduceOnly={}&recvWindow=50000&positionSide={}",
-            buy_or_sell,new_price_order, quantity, timestamp, is_reduce_only, p_side
-        );
-    if p_side != "BOTH" {
-        params = format!(
-            "symbol=BTCUSDT&side={}&type=STOP_MARKET&stopPrice={}&timeInForce=GTC&quantity={}&timestamp={}&recvWindow=50000&positionSide={}",
-            buy_or_sell,new_price_order, quantity, timestamp, p_side
-        );
-    }
-
-    println!("{}", params);
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
+    send_stop_order(
+        price_order,
+        last_order_id,
+        side,
+        is_reduce_only,
+        position_side,
+        working_type,
+        1,
+    )
+    .await
+}
 
-    let result = match client.post(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "POST").await,
+/// Sends the STOP_MARKET order of `new_order`. `attempt` counts the attempts rejected with E17, at most
+/// MAX_POSITION_SIDE_ATTEMPTS.
+#[async_recursion]
+async fn send_stop_order(
+    price_order: f64,
+    last_order_id: &mut u64,
+    side: Side,
+    is_reduce_only: bool,
+    position_side: PositionSide,
+    working_type: WorkingType,
+    attempt: u32,
+) -> String {
+    let quantity = match calculate_quantity_in_btc(true).await {
+        Ok(quantity) => quantity,
+        Err(error) => return error,
     };
+    let new_price_order = stop_order_price(price_order, side, position_side);
+
+    let (p_side, reduce_only) =
+        match position_mode::order_position_side(side, position_side, is_reduce_only).await {
+            Ok(params) => params,
+            Err(error) => return error,
+        };
 
-    account_trade_info().await;
+    let result = BinanceRequest::post("/fapi/v1/order")
+        .param("symbol", "BTCUSDT")
+        .param("side", side)
+        .param("type", OrderType::StopMarket)
+        .param("stopPrice", new_price_order)
+        .param("timeInForce", "GTC")
+        .param("quantity", quantity)
+        .param_opt("reduceOnly", reduce_only)
+        .param("positionSide", p_side)
+        .param("workingType", working_type)
+        .signed()
+        .send()
+        .await;
 
     let status: StatusCode = result.status();
     if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        println!("Order data: {}", data);
-        let temp = data["updateTime"].to_string().parse::<u128>().unwrap();
-        let time = convert_to_formatted_string(temp).await;
-        println!("{}", time);
-        *last_order_id = data["orderId"].to_string().parse().unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
+        *last_order_id = match placed_order_id(&data).await {
+            Ok(order_id) => order_id,
+            Err(error) => return error,
+        };
         status.to_string()
     } else {
         let error = error_handler(result, None).await;
 
         if error == "E01: Order would immediately trigger." {
-            new_order_market(last_order_id, is_buy_order, p_side).await
-        } else if error == "E03: Error 502, exchange server is in trouble." {
+            send_market_order(
+                last_order_id,
+                side,
+                p_side,
+                is_reduce_only,
+                Some(price_order),
+                1,
+            )
+            .await
+        } else if is_exchange_unavailable(&error) || is_risk_rejection(&error) {
+            error
+        } else if error == POSITION_SIDE_MISMATCH && attempt == MAX_POSITION_SIDE_ATTEMPTS {
+            // The order was already sent again with the position mode read from the exchange.
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
+            || error == POSITION_SIDE_MISMATCH
         {
-            new_order(
+            send_stop_order(
                 price_order,
                 last_order_id,
-                is_buy_order,
+                side,
                 is_reduce_only,
                 position_side,
+                working_type,
+                attempt + u32::from(error == POSITION_SIDE_MISMATCH),
             )
             .await
         } else {
-            std::process::exit(1);
+            error
         }
     }
 }
@@ -266,166 +390,404 @@ pub async fn new_order(
 /// Parameters:
 /// - price_order: it is the price - 1 of the order that will be executed.
 /// - last_order_id: mutable reference that will store the order id.
-/// - is_buy_order: bool that indicates with the order will be buy or sell.
-/// - position_side: Option<String> that represent the side (long, short or both).
+/// - side: the side of the order (buy or sell).
+/// - position_side: the position side of the order (BOTH lets it be derived from the position mode).
 ///
-#[async_recursion]
 pub async fn new_order_limit(
     price_order: f64,
     last_order_id: &mut u64,
-    is_buy_order: bool,
-    position_side: Option<String>,
+    side: Side,
+    position_side: PositionSide,
 ) -> String {
-    let new_price_order: Decimal;
-    let buy_or_sell: String;
-    //let price_order: f64 = 30000.0;
+    send_limit_order(price_order, last_order_id, side, position_side, 1).await
+}
 
+/// Sends the LIMIT order of `new_order_limit`. `attempt` counts the attempts rejected with E17, at most
+/// MAX_POSITION_SIDE_ATTEMPTS.
+#[async_recursion]
+async fn send_limit_order(
+    price_order: f64,
+    last_order_id: &mut u64,
+    side: Side,
+    position_side: PositionSide,
+    attempt: u32,
+) -> String {
     //Getting quantity in BTC.
-    let quantity = calculate_quantity_in_btc(true).await;
-
-    let mut p_side = "BOTH".to_string();
-    let temp_position_side = position_side.clone();
-
-    if temp_position_side.is_some() {
-        let unwrapped_position_side = temp_position_side.unwrap();
-        if unwrapped_position_side == "LONG" {
-            p_side = "LONG".to_string();
-            new_price_order = (Decimal::from_f64_retain(price_order + 1.0).unwrap() * dec!(10))
-                .trunc()
-                / dec!(10);
-        } else {
-            p_side = "SHORT".to_string();
-            new_price_order = (Decimal::from_f64_retain(price_order - 1.0).unwrap() * dec!(10))
-                .trunc()
-                / dec!(10);
-        }
-        if is_buy_order {
-            buy_or_sell = "BUY".to_string();
-        } else {
-            buy_or_sell = "SELL".to_string();
-        }
-    } else if is_buy_order {
-        new_price_order =
-            (Decimal::from_f64_retain(price_order + 1.0).unwrap() * dec!(10)).trunc() / dec!(10);
-        buy_or_sell = "BUY".to_string();
-    } else {
-        new_price_order =
-            (Decimal::from_f64_retain(price_order - 1.0).unwrap() * dec!(10)).trunc() / dec!(10);
-        buy_or_sell = "SELL".to_string();
-        //quantity *= 100_f64;
-    }
-
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
+    let quantity = match calculate_quantity_in_btc(true).await {
+        Ok(quantity) => quantity,
+        Err(error) => return error,
+    };
 
-    let params = format!(
-            "symbol=BTCUSDT&side={}&type={}&price={}&timeInForce=GTC&quantity={}&timestamp={}&recvWindow=50000&positionSide={}",
-            buy_or_sell,"LIMIT",new_price_order,quantity, timestamp, p_side
-        );
-    println!("params: {}", params);
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
+    let offset = match position_side {
+        PositionSide::Long => 1.0,
+        PositionSide::Short => -1.0,
+        PositionSide::Both => side.sign(),
+    };
+    let new_price_order =
+        (Decimal::from_f64_retain(price_order + offset).unwrap() * dec!(10)).trunc() / dec!(10);
 
-    let result = match client.post(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "POST").await,
+    let (p_side, _) = match position_mode::order_position_side(side, position_side, false).await {
+        Ok(params) => params,
+        Err(error) => return error,
     };
-    account_trade_info().await;
+
+    let result = BinanceRequest::post("/fapi/v1/order")
+        .param("symbol", "BTCUSDT")
+        .param("side", side)
+        .param("type", OrderType::Limit)
+        .param("price", new_price_order)
+        .param("timeInForce", "GTC")
+        .param("quantity", quantity)
+        .param("positionSide", p_side)
+        .signed()
+        .send()
+        .await;
 
     let status = result.status();
     if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        println!("Order data: {}", data);
-        let temp = data["updateTime"].to_string().parse::<u128>().unwrap();
-        let time = convert_to_formatted_string(temp).await;
-        println!("{}", time);
-        *last_order_id = data["orderId"].to_string().parse().unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
+        *last_order_id = match placed_order_id(&data).await {
+            Ok(order_id) => order_id,
+            Err(error) => return error,
+        };
         status.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) || is_risk_rejection(&error) {
+            error
+        } else if error == POSITION_SIDE_MISMATCH && attempt == MAX_POSITION_SIDE_ATTEMPTS {
+            // The order was already sent again with the position mode read from the exchange.
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
+            || error == POSITION_SIDE_MISMATCH
         {
-            new_order_limit(price_order, last_order_id, is_buy_order, position_side).await
+            let attempt = attempt + u32::from(error == POSITION_SIDE_MISMATCH);
+            send_limit_order(price_order, last_order_id, side, position_side, attempt).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
 
+/// Computes the stop price of `new_order`: 1 away from the price, except for the orders that close a LONG or SHORT
+/// position, truncated to 2 decimals.
+fn stop_order_price(price_order: f64, side: Side, position_side: PositionSide) -> f64 {
+    let offset = match (position_side, side) {
+        (PositionSide::Long, Side::Sell) | (PositionSide::Short, Side::Buy) => 0.0,
+        _ => side.sign(),
+    };
+    ((Decimal::from_f64_retain(price_order + offset).unwrap() * dec!(100)).trunc() / dec!(100))
+        .to_f64()
+        .unwrap()
+}
+
+/// What `new_order_with_fallback` does when the stop would trigger immediately (E01).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopFallback {
+    /// Send a MARKET order, through the slippage guard when it is set (what `new_order` does).
+    Market,
+    /// Send a LIMIT IOC order at the stop price moved by `max_slippage` (a fraction), so it is never filled at a
+    /// worse price.
+    LimitIoc { max_slippage: f64 },
+    /// Place the stop again `offset` away from the last price, up to `max_attempts` times.
+    AdjustStop { offset: f64, max_attempts: u32 },
+    /// Return the E01 error.
+    Error,
+}
+
+/// What `new_order_with_fallback` did with the order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopOrderOutcome {
+    /// The stop was placed at its price.
+    Placed { order_id: u64, stop_price: f64 },
+    /// The stop was placed at an adjusted price, after `attempts` adjustments.
+    StopAdjusted {
+        order_id: u64,
+        stop_price: f64,
+        attempts: u32,
+    },
+    /// A MARKET order was sent. `slippage` is the report of the slippage guard, when it is set.
+    Market {
+        order_id: u64,
+        slippage: Option<slippage::SlippageReport>,
+    },
+    /// A LIMIT IOC order was sent (it may be expired, with nothing filled).
+    LimitIoc(slippage::SlippageReport),
+}
+
+/// Places a STOP_MARKET order like `new_order`, with a fallback chosen by the caller for the stops that would
+/// trigger immediately (E01).
+///
+/// # Arguments
+/// * `price_order`: The price of the stop (moved like in `new_order`).
+/// * `last_order_id`: Mutable reference that will store the order id.
+/// * `side`: The side of the order.
+/// * `is_reduce_only`: The order can only reduce the position (the fallback orders too).
+/// * `position_side`: The position side of the order (BOTH lets it be derived from the position mode).
+/// * `working_type`: The price that triggers the stop (the default of the exchange, CONTRACT_PRICE, when None).
+/// * `fallback`: What to do when the stop would trigger immediately.
+///
+/// # Returns
+/// What was done with the order, or the mapped error (E01 with `StopFallback::Error`, or when the adjusted stops
+/// would still trigger immediately).
+///
+pub async fn new_order_with_fallback(
+    price_order: f64,
+    last_order_id: &mut u64,
+    side: Side,
+    is_reduce_only: bool,
+    position_side: PositionSide,
+    working_type: Option<WorkingType>,
+    fallback: StopFallback,
+) -> Result<StopOrderOutcome, OrderError> {
+    let stop_price = stop_order_price(price_order, side, position_side);
+    let mut order = OrderRequest {
+        symbol: "BTCUSDT".to_string(),
+        side,
+        order_type: OrderType::StopMarket,
+        quantity: calculate_quantity_in_btc(true).await?,
+        price: None,
+        stop_price: Some(stop_price),
+        position_side,
+        reduce_only: is_reduce_only,
+        client_order_id: None,
+        working_type,
+    };
+
+    let outcome = match place_order(&order).await {
+        Ok(ack) => StopOrderOutcome::Placed {
+            order_id: ack.order_id,
+            stop_price,
+        },
+        Err(error) if error != ORDER_WOULD_TRIGGER_IMMEDIATELY => return Err(error),
+        Err(error) => match fallback {
+            StopFallback::Error => return Err(error),
+            StopFallback::Market => {
+                order.order_type = OrderType::Market;
+                order.stop_price = None;
+                match slippage::slippage_guard().await {
+                    Some(guard) => {
+                        let report =
+                            slippage::guarded_market_order(&order, Some(stop_price), &guard)
+                                .await?;
+                        StopOrderOutcome::Market {
+                            order_id: report.order_id,
+                            slippage: Some(report),
+                        }
+                    }
+                    None => StopOrderOutcome::Market {
+                        order_id: place_order(&order).await?.order_id,
+                        slippage: None,
+                    },
+                }
+            }
+            StopFallback::LimitIoc { max_slippage } => {
+                let guard = slippage::SlippageGuard {
+                    max_slippage,
+                    action: slippage::SlippageAction::LimitIoc,
+                };
+                StopOrderOutcome::LimitIoc(
+                    slippage::guarded_market_order(&order, Some(stop_price), &guard).await?,
+                )
+            }
+            StopFallback::AdjustStop {
+                offset,
+                max_attempts,
+            } => {
+                let tick_size = Decimal::from_f64_retain(slippage::tick_size(&order.symbol).await?)
+                    .ok_or_else(|| ERROR_MALFORMED_RESPONSE.to_string())?;
+                // Rounded away from the last price, so the stop stays at least `offset` away from it.
+                let strategy = match side {
+                    Side::Buy => RoundingStrategy::AwayFromZero,
+                    Side::Sell => RoundingStrategy::ToZero,
+                };
+                let mut adjusted = None;
+                for attempts in 1..=max_attempts {
+                    let price = last_price(&order.symbol).await?;
+                    let adjusted_price = Decimal::from_f64_retain(price + side.sign() * offset)
+                        .map(|price| {
+                            (price / tick_size).round_dp_with_strategy(0, strategy) * tick_size
+                        })
+                        .and_then(|price| price.to_f64())
+                        .ok_or_else(|| ERROR_NOT_MAPPED.to_string())?;
+                    order.stop_price = Some(adjusted_price);
+                    match place_order(&order).await {
+                        Ok(ack) => {
+                            adjusted = Some(StopOrderOutcome::StopAdjusted {
+                                order_id: ack.order_id,
+                                stop_price: adjusted_price,
+                                attempts,
+                            });
+                            break;
+                        }
+                        Err(error) if error == ORDER_WOULD_TRIGGER_IMMEDIATELY => continue,
+                        Err(error) => return Err(error),
+                    }
+                }
+                adjusted.ok_or(error)?
+            }
+        },
+    };
+
+    *last_order_id = match &outcome {
+        StopOrderOutcome::Placed { order_id, .. }
+        | StopOrderOutcome::StopAdjusted { order_id, .. }
+        | StopOrderOutcome::Market { order_id, .. } => *order_id,
+        StopOrderOutcome::LimitIoc(report) => report.order_id,
+    };
+    if !matches!(outcome, StopOrderOutcome::Placed { .. }) {
+        info!(
+            stop_price,
+            ?fallback,
+            ?outcome,
+            "Stop would trigger immediately, fallback applied"
+        );
+    }
+    Ok(outcome)
+}
+
 /// Function that place a new order market in the exchange.
 ///
 /// Parameters:
 /// - last_order_id: mutable reference that will store the order id.
-/// - is_buy_order: bool that indicates with the order will be buy or sell.
-/// - position_side: Option<String> that represent the side (long, short or both).
+/// - side: the side of the order (buy or sell).
+/// - position_side: the position side of the order (BOTH lets it be derived from the position mode).
 ///
-#[async_recursion]
 pub async fn new_order_market(
     last_order_id: &mut u64,
-    is_buy_order: bool,
-    position_side: String,
+    side: Side,
+    position_side: PositionSide,
 ) -> String {
-    let mut buy_or_sell: String = "SELL".to_string();
-    //Getting quantity in BTC.
-    let quantity = calculate_quantity_in_btc(true).await;
+    send_market_order(last_order_id, side, position_side, false, None, 1).await
+}
 
-    if is_buy_order {
-        buy_or_sell = "BUY".to_string();
+/// Sends the market order of `new_order_market` and of the E01 fallback of `new_order`, through the slippage guard
+/// when one is set.
+///
+/// # Arguments
+/// * `is_reduce_only`: Whether the order can only reduce the position (the stop it replaces was reduce only).
+/// * `reference_price`: The price the order should get (the stop price), or None to use the last price.
+/// * `attempt`: The attempts rejected with E17, at most MAX_POSITION_SIDE_ATTEMPTS.
+///
+#[async_recursion]
+async fn send_market_order(
+    last_order_id: &mut u64,
+    side: Side,
+    position_side: PositionSide,
+    is_reduce_only: bool,
+    reference_price: Option<f64>,
+    attempt: u32,
+) -> String {
+    if let Some(guard) = slippage::slippage_guard().await {
+        return new_order_market_guarded(
+            last_order_id,
+            side,
+            position_side,
+            is_reduce_only,
+            reference_price,
+            guard,
+        )
+        .await;
     }
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!(
-        "symbol=BTCUSDT&side={}&type={}&quantity={}&timestamp={}&recvWindow=50000&positionSide={}",
-        buy_or_sell, "MARKET", quantity, timestamp, position_side
-    );
-
-    let signature = get_signature(params.clone()).await;
 
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    let result = match client.post(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "POST").await,
+    //Getting quantity in BTC.
+    let quantity = match calculate_quantity_in_btc(true).await {
+        Ok(quantity) => quantity,
+        Err(error) => return error,
     };
-    let status = result.status();
 
-    account_trade_info().await;
+    let (p_side, reduce_only) =
+        match position_mode::order_position_side(side, position_side, is_reduce_only).await {
+            Ok(params) => params,
+            Err(error) => return error,
+        };
+    let result = BinanceRequest::post("/fapi/v1/order")
+        .param("symbol", "BTCUSDT")
+        .param("side", side)
+        .param("type", OrderType::Market)
+        .param("quantity", quantity)
+        .param("positionSide", p_side)
+        .param_opt("reduceOnly", reduce_only)
+        .signed()
+        .send()
+        .await;
+    let status = result.status();
 
     if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        println!("Order data: {}", data);
-        let temp = data["updateTime"].to_string().parse::<u128>().unwrap();
-        let time = convert_to_formatted_string(temp).await;
-        println!("{}", time);
-        *last_order_id = data["orderId"].to_string().parse().unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
+        *last_order_id = match placed_order_id(&data).await {
+            Ok(order_id) => order_id,
+            Err(error) => return error,
+        };
         status.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) || is_risk_rejection(&error) {
+            error
+        } else if error == POSITION_SIDE_MISMATCH && attempt == MAX_POSITION_SIDE_ATTEMPTS {
+            // The order was already sent again with the position mode read from the exchange.
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
+            || error == POSITION_SIDE_MISMATCH
         {
-            new_order_market(last_order_id, is_buy_order, position_side).await
+            send_market_order(
+                last_order_id,
+                side,
+                position_side,
+                is_reduce_only,
+                reference_price,
+                attempt + u32::from(error == POSITION_SIDE_MISMATCH),
+            )
+            .await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
+        }
+    }
+}
+
+/// Sends the market order of `new_order_market` through the slippage guard (see slippage.rs).
+///
+/// # Arguments
+/// * `last_order_id`: Mutable reference that will store the order id.
+/// * `side`, `position_side`: The side and position side of the order.
+/// * `is_reduce_only`: Whether the order can only reduce the position.
+/// * `reference_price`: The price the order should get, or None to use the last price.
+/// * `guard`: The slippage guard.
+///
+/// # Returns
+/// "200 OK" when the order was filled (even partially), SLIPPAGE_TOO_HIGH (E19) when it was refused or nothing
+/// was filled at the protected price, or the mapped error.
+///
+async fn new_order_market_guarded(
+    last_order_id: &mut u64,
+    side: Side,
+    position_side: PositionSide,
+    is_reduce_only: bool,
+    reference_price: Option<f64>,
+    guard: slippage::SlippageGuard,
+) -> String {
+    let quantity = match calculate_quantity_in_btc(true).await {
+        Ok(quantity) => quantity,
+        Err(error) => return error,
+    };
+    let order = slippage::legacy_market_order(side, quantity, position_side, is_reduce_only);
+    match slippage::guarded_market_order(&order, reference_price, &guard).await {
+        Ok(report) if report.executed_qty > 0.0 => {
+            *last_order_id = report.order_id;
+            StatusCode::OK.to_string()
         }
+        Ok(_) => SLIPPAGE_TOO_HIGH.to_string(),
+        Err(error) => error.into(),
     }
 }
 
@@ -434,52 +796,46 @@ pub async fn new_order_market(
 /// Parameters:
 /// - price_order: it is the price of the order that will be executed.
 /// - order_id: mutable reference that contain the last order id executed.
-/// - is_buy_order: bool that indicates with the order will be buy or sell.
+/// - side: the side of the new order (buy or sell).
+/// - working_type: the price that triggers the new stop (see `new_order`).
 ///
 
 #[async_recursion]
 pub async fn cancel_an_existing_order_and_send_a_new_order(
     price_order: f64,
     order_id: &mut u64,
-    is_buy_order: bool,
+    side: Side,
     is_reduce_only: bool,
-    position_side: Option<String>,
+    position_side: PositionSide,
+    working_type: WorkingType,
 ) -> String {
-    let client: reqwest::Client = get_client().await;
-    // Cancel the order
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&orderId={}&timestamp={}&recvWindow=50000",
-        order_id, timestamp
-    );
-    let signature = get_signature(params.clone()).await;
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
     // Sending HTTP delete will cancel the order
-    let result = match client.delete(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "DELETE").await,
-    };
+    let result = BinanceRequest::delete("/fapi/v1/order")
+        .param("symbol", "BTCUSDT")
+        .param("orderId", *order_id)
+        .signed()
+        .send()
+        .await;
     let status: StatusCode = result.status();
 
     if status == StatusCode::OK {
-        let _data: serde_json::Value = result.json().await.unwrap();
+        let _data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
 
         new_order(
             price_order,
             order_id,
-            is_buy_order,
+            side,
             is_reduce_only,
             position_side,
+            working_type,
         )
         .await
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
@@ -487,13 +843,14 @@ pub async fn cancel_an_existing_order_and_send_a_new_order(
             cancel_an_existing_order_and_send_a_new_order(
                 price_order,
                 order_id,
-                is_buy_order,
+                side,
                 is_reduce_only,
                 position_side,
+                working_type,
             )
             .await
         } else {
-            std::process::exit(1);
+            error
         }
     }
 }
@@ -501,39 +858,31 @@ pub async fn cancel_an_existing_order_and_send_a_new_order(
 /// Function that cancel all open orders in the user's binance account.
 #[async_recursion]
 pub async fn cancel_all_open_orders() -> String {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!("symbol=BTCUSDT&timestamp={}", timestamp);
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/allOpenOrders?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.delete(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "DELETE").await,
-    };
+    let result = BinanceRequest::delete("/fapi/v1/allOpenOrders")
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
 
     let status = result.status();
     if status == StatusCode::OK {
-        let _data: serde_json::Value = result.json().await.unwrap();
-        //println!("Order data: {}", data);
+        let _data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
+        //debug!(order = %data, "Order data");
         "No more open orders.".to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             cancel_all_open_orders().await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
@@ -541,22 +890,11 @@ pub async fn cancel_all_open_orders() -> String {
 /// Function that cancel all open orders in the user's binance account
 /// and does not look for errors.
 pub async fn cancel_all_open_orders_without_error_check() {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!("symbol=BTCUSDT&timestamp={}", timestamp);
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/allOpenOrders?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.delete(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "DELETE").await,
-    };
+    let result = BinanceRequest::delete("/fapi/v1/allOpenOrders")
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
 }
 
 /// Retrieves the status of an order with the given order ID.
@@ -576,90 +914,69 @@ pub async fn order_status(order_id: u64) -> String {
         return "Invalid Order ID.".to_string();
     }
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!(
-        "orderId={}&symbol=BTCUSDT&timestamp={}&recvWindow=50000",
-        order_id, timestamp
-    );
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    //println!("req: {}", request);
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/order")
+        .param("orderId", order_id)
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
 
     if result.status() == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
         //println!("data :{}", data);
 
         data["status"].to_string().replace('\"', "")
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             order_status(order_id).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
 
 #[async_recursion]
 pub async fn get_stop_price(order_id: u64) -> String {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "orderId={}&symbol=BTCUSDT&timestamp={}&recvWindow=50000",
-        order_id, timestamp
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    println!("req: {}", request);
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/order")
+        .param("orderId", order_id)
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
         let data_string = data["stopPrice"].to_string();
-        println!("data: {}", data_string);
+        debug!(stop_price = %data_string, "Stop price of the order");
         let str_no_quotes = (data_string).substring(1, data_string.len() - 1);
-        let stop_price: f64 = str_no_quotes.parse::<f64>().unwrap();
+        let stop_price: f64 = match str_no_quotes.parse::<f64>() {
+            Ok(stop_price) => stop_price,
+            Err(_) => return ERROR_NOT_MAPPED.to_string(),
+        };
 
         stop_price.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_stop_price(order_id).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
@@ -677,47 +994,38 @@ pub async fn get_stop_price(order_id: u64) -> String {
 ///
 #[async_recursion]
 pub async fn cancel_open_order(order_id: u64) -> String {
-    let client: reqwest::Client = get_client().await;
-    // Cancel the order
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&orderId={}&timestamp={}&recvWindow=50000",
-        order_id, timestamp
-    );
-    let signature = get_signature(params.clone()).await;
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
     // Sending HTTP delete will cancel the order
-    let result = match client.delete(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "DELETE").await,
-    };
+    let result = BinanceRequest::delete("/fapi/v1/order")
+        .param("symbol", "BTCUSDT")
+        .param("orderId", order_id)
+        .signed()
+        .send()
+        .await;
     let status = result.status();
-    account_trade_info().await;
 
     if status == StatusCode::OK {
-        let _data: serde_json::Value = result.json().await.unwrap();
+        let _data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
         //println!("Cancel order data: {}", data);
-        let temp = _data["updateTime"].to_string().parse::<u128>().unwrap();
-        let time = convert_to_formatted_string(temp).await;
-        println!("{}", time);
+        let time = match order_update_time(&_data) {
+            Ok(update_time) => convert_to_formatted_string(update_time).await,
+            Err(error) => return error,
+        };
+        debug!(update_time = %time, "Order canceled");
         status.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
-            cancel_open_order(order_id).await;
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             cancel_open_order(order_id).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
@@ -731,28 +1039,21 @@ pub async fn cancel_open_order(order_id: u64) -> String {
 ///
 #[async_recursion]
 pub async fn test_binance_connection() -> String {
-    let client: reqwest::Client = get_client().await;
-
-    let request = format!("{}/fapi/v1/ping", exchange_url().await);
-    // Sending HTTP delete will cancel the order
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/ping").send().await;
     let status = result.status();
     if status == StatusCode::OK {
         status.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             test_binance_connection().await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
@@ -768,39 +1069,31 @@ pub async fn test_binance_connection() -> String {
 ///
 #[async_recursion]
 pub async fn binance_open_orders() -> Result<Value, String> {
-    let client: reqwest::Client = get_client().await;
-    // Cancel the order
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!("symbol=BTCUSDT&timestamp={}&recvWindow=50000", timestamp);
-    let signature = get_signature(params.clone()).await;
-    let request = format!(
-        "{}/fapi/v1/openOrders?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    // Sending HTTP delete will cancel the order
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/openOrders")
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
     let status = result.status();
     if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         //println!("Data: {}", data);
         //data.to_string()
         Ok(data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             binance_open_orders().await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            Err(error)
         }
     }
 }
@@ -815,41 +1108,25 @@ pub async fn binance_open_orders() -> Result<Value, String> {
 ///
 #[async_recursion]
 pub async fn exchange_info() -> String {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!("symbol=BTCUSDT&timestamp={}&recvWindow=50000", timestamp);
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/exchangeInfo?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    // let request = "{}/fapi/v1/exchangeInfo".to_string();
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/exchangeInfo").send().await;
     if result.status() == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
         //println!("{}", data);
         data.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             exchange_info().await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
@@ -868,40 +1145,191 @@ pub async fn exchange_info() -> String {
 ///
 #[async_recursion]
 pub async fn price_ticker(symbol: String) -> String {
-    let client: reqwest::Client = get_client().await;
-
-    let params = format!("symbol={}", symbol);
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/ticker/price?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/ticker/price")
+        .param("symbol", &symbol)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
         // println!("{}", data);
         // println!("{}", data["price"]);
 
         data["price"].to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             error
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             price_ticker(symbol).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
+        }
+    }
+}
+
+/// Retrieves the last price for a specific trading symbol on the Binance exchange.
+///
+/// # Arguments
+/// * `symbol`: A string representing the trading symbol (e.g., "BTCUSDT").
+///
+/// # Returns
+/// The last price of the symbol or the mapped error.
+///
+pub async fn last_price(symbol: &str) -> Result<f64, String> {
+    let data: Value = BinanceRequest::get("/fapi/v1/ticker/price")
+        .param("symbol", symbol)
+        .fetch()
+        .await?;
+    data["price"]
+        .as_str()
+        .and_then(|p| p.parse::<f64>().ok())
+        .ok_or_else(|| ERROR_MALFORMED_RESPONSE.to_string())
+}
+
+/// Retrieves the information of a symbol (status and filters) from /fapi/v1/exchangeInfo.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT").
+///
+/// # Returns
+/// The `SymbolInfo` of the symbol, E23 when the exchange does not list it, or the mapped error.
+///
+pub async fn symbol_info(symbol: &str) -> Result<SymbolInfo, String> {
+    BinanceRequest::get("/fapi/v1/exchangeInfo")
+        .fetch::<ExchangeInfo>()
+        .await?
+        .symbols
+        .into_iter()
+        .find(|s| s.symbol == symbol)
+        .ok_or_else(|| UNKNOWN_SYMBOL.to_string())
+}
+
+/// Retrieves the best bid and ask for a specific trading symbol on the Binance exchange.
+///
+/// # Arguments
+/// * `symbol`: A string representing the trading symbol (e.g., "BTCUSDT").
+///
+/// # Returns
+/// The `BookTicker` of the symbol or the mapped error.
+///
+#[async_recursion]
+pub async fn book_ticker(symbol: &str) -> Result<BookTicker, String> {
+    let result = BinanceRequest::get("/fapi/v1/ticker/bookTicker")
+        .param("symbol", symbol)
+        .send()
+        .await;
+    if result.status() == StatusCode::OK {
+        let data: BookTicker = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
+        {
+            book_ticker(symbol).await
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Retrieves the premium index of a symbol: its mark price, index price and funding.
+///
+/// # Arguments
+/// * `symbol`: A string representing the trading symbol (e.g., "BTCUSDT").
+///
+/// # Returns
+/// The `PremiumIndex` of the symbol or the mapped error.
+///
+pub async fn premium_index(symbol: &str) -> Result<PremiumIndex, String> {
+    BinanceRequest::get("/fapi/v1/premiumIndex")
+        .param("symbol", symbol)
+        .fetch()
+        .await
+}
+
+/// Retrieves the mark price for a specific trading symbol on the Binance exchange.
+///
+/// The mark price is the price used by the exchange to calculate the unrealized pnl and the liquidations.
+///
+/// # Arguments
+/// * `symbol`: A string representing the trading symbol (e.g., "BTCUSDT").
+///
+/// # Returns
+/// The mark price of the symbol or the mapped error.
+///
+pub async fn mark_price(symbol: &str) -> Result<f64, String> {
+    Ok(premium_index(symbol).await?.mark_price)
+}
+
+/// Retrieves one page of the funding rate history.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT"), or None for every symbol.
+/// * `start_time`, `end_time`: The range, in milliseconds since the epoch (None to leave it open).
+/// * `limit`: The number of rates (100 by default, 1000 at most).
+///
+/// # Returns
+/// The first `limit` rates from the start time (the last ones when there is no start time) sorted by time, or the
+/// mapped error.
+///
+pub async fn funding_rate(
+    symbol: Option<&str>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<FundingRate>, String> {
+    BinanceRequest::get("/fapi/v1/fundingRate")
+        .param_opt("symbol", symbol)
+        .param_opt("startTime", start_time)
+        .param_opt("endTime", end_time)
+        .param_opt("limit", limit)
+        .fetch()
+        .await
+}
+
+/// Retrieves every funding rate of a symbol in a time range, reading pages of 1000 rates.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT").
+/// * `start_time`, `end_time`: The range, in milliseconds since the epoch. Without start time, the history starts
+///   at the first funding of the symbol.
+///
+/// # Returns
+/// The funding rates sorted by time or the error of the first page that failed.
+///
+pub async fn funding_rate_history(
+    symbol: &str,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+) -> Result<Vec<FundingRate>, String> {
+    let mut start_time = start_time.unwrap_or(0);
+    let mut history = Vec::new();
+    loop {
+        let page = funding_rate(
+            Some(symbol),
+            Some(start_time),
+            end_time,
+            Some(MAX_FUNDING_RATE_LIMIT),
+        )
+        .await?;
+        let is_last_page = (page.len() as u32) < MAX_FUNDING_RATE_LIMIT;
+        // A symbol has one funding at a time, so the next page starts after the last one.
+        if let Some(last) = page.last() {
+            start_time = last.funding_time + 1;
+        }
+        history.extend(page);
+        if is_last_page {
+            return Ok(history);
         }
     }
 }
@@ -917,290 +1345,655 @@ pub async fn price_ticker(symbol: String) -> String {
 /// A `String` containing the JSON response with position information.
 #[async_recursion]
 pub async fn position_info() -> Result<serde_json::Value, String> {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!("symbol=BTCUSDT&timestamp={}&recvWindow=50000", timestamp);
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v2/positionRisk?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v2/positionRisk")
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
     let status = result.status();
 
     if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         //println!("Response: {}", data);
         Ok(data)
         //status.to_string()
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             position_info().await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            tracing::error!(%error, "Unexpected error in the order request.");
+            Err(error)
         }
     }
 }
 
-/// Closes a position on the Binance exchange.
+/// Retrieves the positions of a symbol, or of every symbol, from /fapi/v2/positionRisk.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT"), or None for every symbol.
 ///
-/// This function sends a request to the Binance exchange server to close a position. The order type is determined
-/// by the `is_buy_order` parameter: if `true`, it's a market buy order; if `false`, it's a market sell order.
+/// # Returns
+/// One `PositionRisk` per symbol and position side, or the mapped error.
 ///
-/// You can specify the `position_side` to indicate whether you want to close a "LONG" or "SHORT" position.
+#[async_recursion]
+pub async fn position_risk(symbol: Option<&str>) -> Result<Vec<PositionRisk>, String> {
+    let result = BinanceRequest::get("/fapi/v2/positionRisk")
+        .param_opt("symbol", symbol)
+        .signed()
+        .send()
+        .await;
+
+    if result.status() == StatusCode::OK {
+        let data: Vec<PositionRisk> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
+        {
+            position_risk(symbol).await
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Retrieves the open orders of a symbol, or of every symbol.
 ///
 /// # Arguments
-/// * `is_buy_order`: A boolean indicating whether it's a buy (true) or sell (false) order.
-/// * `position_side`: An optional string indicating the position side to close ("LONG," "SHORT," or "BOTH").
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT"), or None for every symbol (weight 40).
 ///
 /// # Returns
-/// A `String` containing the status of the order execution.
+/// The typed open orders, or the mapped error.
 ///
 #[async_recursion]
-pub async fn close_position(is_buy_order: bool, position_side: Option<String>) -> String {
-    let mut buy_or_sell: String = "SELL".to_string();
-
-    //Getting quantity in BTC.
-    let quantity = calculate_quantity_in_btc(true).await * 100_f64;
+pub async fn open_orders(symbol: Option<&str>) -> Result<Vec<OpenOrder>, String> {
+    let result = BinanceRequest::get("/fapi/v1/openOrders")
+        .param_opt("symbol", symbol)
+        .signed()
+        .send()
+        .await;
 
-    if is_buy_order {
-        buy_or_sell = "BUY".to_string();
+    if result.status() == StatusCode::OK {
+        let data: Vec<OpenOrder> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
+        {
+            open_orders(symbol).await
+        } else {
+            Err(error)
+        }
     }
+}
 
-    let mut p_side = "BOTH".to_string();
+/// Cancels every open order of a symbol.
+///
+/// # Returns
+/// Ok when the orders were canceled, or the mapped error.
+///
+#[async_recursion]
+pub async fn cancel_symbol_open_orders(symbol: &str) -> Result<(), String> {
+    let result = BinanceRequest::delete("/fapi/v1/allOpenOrders")
+        .param("symbol", symbol)
+        .signed()
+        .send()
+        .await;
 
-    let clone_position_side = position_side.clone();
-    if clone_position_side.is_some() {
-        let unwrapped_position_side = clone_position_side.unwrap();
-        if unwrapped_position_side == "LONG" {
-            p_side = "LONG".to_string();
-        } else if unwrapped_position_side == "SHORT" {
-            p_side = "SHORT".to_string();
+    if result.status() == StatusCode::OK {
+        Ok(())
+    } else {
+        let error = error_handler(result, None).await;
+        if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
+        {
+            cancel_symbol_open_orders(symbol).await
+        } else {
+            Err(error)
         }
     }
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
+}
 
-    let mut params = format!(
-            "symbol=BTCUSDT&side={}&type=MARKET&quantity={}&timestamp={}&recvWindow=50000&positionSide={}",
-            buy_or_sell, quantity, timestamp, p_side
-        );
-    if p_side == "BOTH" {
-        params = format!(
-            "symbol=BTCUSDT&side={}&type=STOP_MARKET&timeInForce=GTC&quantity={}&timestamp={}&recvWindow=50000&positionSide={}",
-            buy_or_sell, quantity, timestamp, p_side
-        );
+/// Checks if a position is one of the positions selected by a position side.
+///
+/// LONG and SHORT select the position of that side in hedge mode, and the one-way position when its amount has that
+/// direction. BOTH selects every position of the symbol.
+///
+fn is_selected_position(position: &PositionRisk, position_side: PositionSide) -> bool {
+    match position_side {
+        PositionSide::Both => true,
+        PositionSide::Long => {
+            position.position_side == PositionSide::Long
+                || (position.position_side == PositionSide::Both && position.position_amt > 0.0)
+        }
+        PositionSide::Short => {
+            position.position_side == PositionSide::Short
+                || (position.position_side == PositionSide::Both && position.position_amt < 0.0)
+        }
     }
+}
 
-    let signature = get_signature(params.clone()).await;
+/// Get the quantity that closes a percentage of a position.
+///
+/// The quantity is truncated to the number of decimals of the position amount, which already follows the step size
+/// of the symbol, so a partial close never closes more than the percentage.
+///
+fn close_quantity(position_amount: f64, percent: f64) -> Option<f64> {
+    if percent == 100.0 {
+        return Some(position_amount.abs());
+    }
 
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    let result = match client.post(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "POST").await,
-    };
-    let status = result.status();
+    let amount: Decimal = position_amount.abs().to_string().parse().ok()?;
+    let percent = Decimal::from_f64_retain(percent)?;
+    (amount * percent / dec!(100))
+        .round_dp_with_strategy(amount.scale(), RoundingStrategy::ToZero)
+        .to_f64()
+}
 
-    if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        println!("Order data: {}", data);
-        let temp = data["updateTime"].to_string().parse::<u128>().unwrap();
-        let time = convert_to_formatted_string(temp).await;
-        println!("{}", time);
-        status.to_string()
+/// Closes a percentage of the positions of a symbol with reduce only MARKET orders.
+///
+/// The amount of each position is read from /fapi/v2/positionRisk, so the orders close exactly that amount (or its
+/// percentage) and never open a position in the other direction.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT").
+/// * `position_side`: The positions to close: LONG or SHORT, or BOTH for every position of the symbol.
+/// * `percent`: The percentage of each position to close, in (0, 100].
+///
+/// # Returns
+/// One `OrderAck` per position closed (none when there was nothing to close), or the mapped error.
+///
+pub async fn close_position_percent(
+    symbol: &str,
+    position_side: PositionSide,
+    percent: f64,
+) -> Result<Vec<OrderAck>, OrderError> {
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(ERROR_NOT_VALID_QUANTITY.into());
+    }
+
+    let mut acks = Vec::new();
+    for position in position_risk(Some(symbol))
+        .await?
+        .iter()
+        .filter(|p| p.position_amt != 0.0 && is_selected_position(p, position_side))
+    {
+        let quantity = match close_quantity(position.position_amt, percent) {
+            Some(quantity) if quantity > 0.0 => quantity,
+            _ => continue,
+        };
+        let order = OrderRequest {
+            symbol: symbol.to_string(),
+            side: position.closing_side(),
+            order_type: OrderType::Market,
+            quantity,
+            price: None,
+            stop_price: None,
+            position_side: position.position_side,
+            reduce_only: true,
+            client_order_id: None,
+            working_type: None,
+        };
+        match place_order(&order).await {
+            Ok(ack) => acks.push(ack),
+            // The position was closed by another order in the meantime.
+            Err(error) if error == ERROR_NOTHING_TO_CLOSE => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(acks)
+}
+
+/// Places a STOP_MARKET with closePosition=true, that closes the whole position of a symbol when it triggers,
+/// whatever its amount is at that time.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT").
+/// * `position_side`: The position to close (LONG or SHORT, see `close_position_percent`).
+/// * `stop_price`: The price that triggers the order.
+///
+/// # Returns
+/// The `OrderAck` of the stop, E05 when there is no position to close, or the mapped error.
+///
+pub async fn close_position_on_stop(
+    symbol: &str,
+    position_side: PositionSide,
+    stop_price: f64,
+) -> Result<OrderAck, OrderError> {
+    let position = position_risk(Some(symbol))
+        .await?
+        .into_iter()
+        .find(|p| p.position_amt != 0.0 && is_selected_position(p, position_side))
+        .ok_or_else(|| ERROR_NOTHING_TO_CLOSE.to_string())?;
+
+    send_close_position_stop(
+        symbol,
+        position.closing_side(),
+        position.position_side,
+        stop_price,
+    )
+    .await
+}
+
+#[async_recursion]
+async fn send_close_position_stop(
+    symbol: &str,
+    side: Side,
+    position_side: PositionSide,
+    stop_price: f64,
+) -> Result<OrderAck, OrderError> {
+    // closePosition can not be sent with quantity nor reduceOnly.
+    let result = BinanceRequest::post("/fapi/v1/order")
+        .param("symbol", symbol)
+        .param("side", side)
+        .param("type", OrderType::StopMarket)
+        .param("stopPrice", stop_price)
+        .param("closePosition", true)
+        .param("positionSide", position_side)
+        .signed()
+        .send_order()
+        .await?;
+
+    if result.status() == StatusCode::OK {
+        let data: OrderAck = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.into()),
+        };
+        Ok(data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
-            error
-        } else if error == "E05: ReduceOnly Order is rejected." {
-            "No position to close. Everything ok.".to_string()
-        } else if error == "E07: Dns error: No such host is known."
+        if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
-            close_position(is_buy_order, position_side).await
+            send_close_position_stop(symbol, side, position_side, stop_price).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            Err(error.into())
+        }
+    }
+}
+
+/// Panic mode: cancels the open orders and closes the positions of every symbol.
+///
+/// The open orders are canceled first, so no stop or limit order opens a position again after it was closed.
+///
+/// # Returns
+/// One `OrderAck` per position closed, or the first error (the remaining symbols are not closed then).
+///
+pub async fn close_all_positions() -> Result<Vec<OrderAck>, String> {
+    let positions = position_risk(None).await?;
+    let mut symbols: Vec<String> = open_orders(None)
+        .await?
+        .into_iter()
+        .map(|o| o.symbol)
+        .chain(
+            positions
+                .iter()
+                .filter(|p| p.position_amt != 0.0)
+                .map(|p| p.symbol.clone()),
+        )
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+
+    let mut acks = Vec::new();
+    for symbol in &symbols {
+        cancel_symbol_open_orders(symbol).await?;
+        acks.extend(close_position_percent(symbol, PositionSide::Both, 100.0).await?);
+    }
+    warn!(
+        symbols = ?symbols,
+        orders = acks.len(),
+        "Closed every position of the account"
+    );
+    Ok(acks)
+}
+
+/// Closes a position of BTCUSDT on the Binance exchange.
+///
+/// This function reads the amount of the position and closes it with a reduce only MARKET order (see
+/// `close_position_percent`).
+///
+/// # Arguments
+/// * `position_side`: The position side to close ("LONG," "SHORT," or "BOTH" for every position).
+///
+/// # Returns
+/// A `String` containing the status of the order execution.
+///
+pub async fn close_position(position_side: PositionSide) -> String {
+    match close_position_percent("BTCUSDT", position_side, 100.0)
+        .await
+        .map_err(String::from)
+    {
+        Ok(acks) if acks.is_empty() => "No position to close. Everything ok.".to_string(),
+        Ok(acks) => {
+            for ack in &acks {
+                debug!(order_id = ack.order_id, status = %ack.status, "Position closed");
+            }
+            StatusCode::OK.to_string()
+        }
+        Err(error) if is_exchange_unavailable(&error) || is_risk_rejection(&error) => error,
+        Err(error) => {
+            tracing::error!(%error, "Unexpected error in the order request.");
+            error
         }
     }
 }
 
 /// Activates the dual side position mode on the Binance exchange.
 ///
-/// This function sends a request to the Binance exchange server to activate dual side position mode.
+/// This function sends a request to the Binance exchange server to activate dual side position mode, only when the
+/// cached position mode (see position_mode.rs) is not already the requested one.
 ///
 /// # Returns
-/// A `String` containing the status of the activation.
+/// A `String` containing the status of the activation (E06 when the account was already in the mode).
 ///
-#[async_recursion]
 pub async fn activate_hedge_mode() -> String {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!("dualSidePosition=true&timestamp={}", timestamp);
+    match position_mode::set_position_mode(PositionMode::Hedge).await {
+        Ok(true) => StatusCode::OK.to_string(),
+        Ok(false) => NO_NEED_TO_CHANGE_PS.to_string(),
+        Err(error) => error,
+    }
+}
 
-    let signature = get_signature(params.clone()).await;
+/// Deactivates the dual side position mode on the Binance exchange.
+///
+/// This function sends a request to the Binance exchange server to deactivate dual side position mode, only when the
+/// cached position mode (see position_mode.rs) is not already the requested one.
+///
+/// # Returns
+/// A `String` containing the status of the deactivation (E06 when the account was already in the mode).
+///
+pub async fn deactivate_hedge_mode() -> String {
+    match position_mode::set_position_mode(PositionMode::OneWay).await {
+        Ok(true) => StatusCode::OK.to_string(),
+        Ok(false) => NO_NEED_TO_CHANGE_PS.to_string(),
+        Err(error) => error,
+    }
+}
 
-    let request = format!(
-        "{}/fapi/v1/positionSide/dual?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    let result = match client.post(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "POST").await,
-    };
-    let status = result.status();
+/// Retrieves detailed information about an order with the given order ID.
+///
+/// This function sends a request to the Binance exchange server to fetch detailed information
+/// about an order identified by the provided `order_id`. It returns a string representation of
+/// the JSON response containing order details.
+///
+/// # Arguments
+/// * `order_id`: A unique identifier for the order.
+///
+/// # Returns
+/// A `String` containing the JSON response with order details.
+///
+#[async_recursion]
+pub async fn get_order(order_id: u64) -> String {
+    if order_id == 0 {
+        return "Invalid Order ID.".to_string();
+    }
 
-    if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        println!("Order data: {}", data);
-        status.to_string()
+    let result = BinanceRequest::get("/fapi/v1/order")
+        .param("orderId", order_id)
+        .param("symbol", "BTCUSDT")
+        .signed()
+        .send()
+        .await;
+    if result.status() == StatusCode::OK {
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return ERROR_MALFORMED_RESPONSE.to_string(),
+        };
+        //println!("data :{}", data);
+        data.to_string()
+        //data["status"].to_string().replace('\"', "")
     } else {
         let error = error_handler(result, None).await;
-        if (error == "E03: Error 502, exchange server is in trouble.")
-            || error.contains("No need to change")
+        if is_exchange_unavailable(&error) {
+            error
+        } else if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
+            get_order(order_id).await
+        } else {
+            tracing::error!(%error, "Unexpected error in the order request.");
             error
+        }
+    }
+}
+
+/// Places an order described by an `OrderRequest` in the exchange.
+///
+/// Unlike `new_order`, no price offset or quantity calculation is done here: the order is sent
+/// exactly as described. It is the entry point used by the `Exchange` implementation of Binance.
+///
+/// # Arguments
+/// * `order`: The order that will be placed.
+///
+/// # Returns
+/// The `OrderAck` returned by the exchange or the mapped error.
+///
+pub async fn place_order(order: &OrderRequest) -> Result<OrderAck, OrderError> {
+    send_order_request(order, 1).await
+}
+
+/// Sends the order of `place_order`. `attempt` counts the attempts rejected with E17, at most
+/// MAX_POSITION_SIDE_ATTEMPTS.
+#[async_recursion]
+async fn send_order_request(order: &OrderRequest, attempt: u32) -> Result<OrderAck, OrderError> {
+    let (position_side, reduce_only) =
+        position_mode::order_position_side(order.side, order.position_side, order.reduce_only)
+            .await?;
+
+    let result = BinanceRequest::post("/fapi/v1/order")
+        .param("symbol", &order.symbol)
+        .param("side", order.side)
+        .param("type", order.order_type)
+        .param("quantity", order.quantity)
+        .param("positionSide", position_side)
+        .param_opt("price", order.price)
+        .param_opt("timeInForce", order.price.map(|_| "GTC"))
+        .param_opt("stopPrice", order.stop_price)
+        .param_opt("newClientOrderId", order.client_order_id.as_ref())
+        .param_opt("reduceOnly", reduce_only)
+        .param_opt("workingType", order.working_type)
+        .param("newOrderRespType", "RESULT")
+        .signed()
+        .send_order()
+        .await?;
+
+    if result.status() == StatusCode::OK {
+        let data: OrderAck = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.into()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if error == POSITION_SIDE_MISMATCH && attempt == MAX_POSITION_SIDE_ATTEMPTS {
+            // The order was already sent again with the position mode read from the exchange.
+            Err(error.into())
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
+            || error == POSITION_SIDE_MISMATCH
         {
-            activate_hedge_mode().await
+            send_order_request(order, attempt + u32::from(error == POSITION_SIDE_MISMATCH)).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            Err(error.into())
         }
     }
 }
 
-/// Deactivates the dual side position mode on the Binance exchange.
+/// Modifies the price and quantity of a resting LIMIT order.
 ///
-/// This function sends a request to the Binance exchange server to deactivate dual side position mode.
+/// # Arguments
+/// * `symbol`: The trading symbol of the order (e.g., "BTCUSDT").
+/// * `order_id`: The id of the order that will be modified.
+/// * `side`: The side of the order, required by the exchange.
+/// * `quantity`: The new quantity of the order.
+/// * `price`: The new price of the order.
 ///
 /// # Returns
-/// A `String` containing the status of the deactivation.
+/// The `OrderAck` returned by the exchange or the mapped error.
 ///
 #[async_recursion]
-pub async fn deactivate_hedge_mode() -> String {
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
+pub async fn amend_order(
+    symbol: &str,
+    order_id: u64,
+    side: Side,
+    quantity: f64,
+    price: f64,
+) -> Result<OrderAck, OrderError> {
+    let result = BinanceRequest::put("/fapi/v1/order")
+        .param("symbol", symbol)
+        .param("orderId", order_id)
+        .param("side", side)
+        .param("quantity", quantity)
+        .param("price", price)
+        .signed()
+        .send_order()
+        .await?;
+
+    if result.status() == StatusCode::OK {
+        let data: OrderAck = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.into()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
+        {
+            amend_order(symbol, order_id, side, quantity, price).await
+        } else {
+            Err(error.into())
+        }
+    }
+}
 
-    let params = format!("dualSidePosition=false&timestamp={}", timestamp);
+/// Cancels an order of any symbol.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol of the order (e.g., "BTCUSDT").
+/// * `order_id`: The id of the order that will be canceled.
+///
+/// # Returns
+/// The `OrderAck` returned by the exchange or the mapped error.
+///
+#[async_recursion]
+pub async fn cancel_order(symbol: &str, order_id: u64) -> Result<OrderAck, String> {
+    let result = BinanceRequest::delete("/fapi/v1/order")
+        .param("symbol", symbol)
+        .param("orderId", order_id)
+        .signed()
+        .send()
+        .await;
 
-    let signature = get_signature(params.clone()).await;
+    if result.status() == StatusCode::OK {
+        let data: OrderAck = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow"
+        {
+            cancel_order(symbol, order_id).await
+        } else {
+            Err(error)
+        }
+    }
+}
 
-    let request = format!(
-        "{}/fapi/v1/positionSide/dual?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-    let result = match client.post(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "POST").await,
-    };
-    let status = result.status();
+/// Retrieves an order by the id chosen by the client.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol of the order (e.g., "BTCUSDT").
+/// * `client_order_id`: The newClientOrderId sent with the order.
+///
+/// # Returns
+/// The `OrderInfo` returned by the exchange or the mapped error.
+///
+#[async_recursion]
+pub async fn query_order(symbol: &str, client_order_id: &str) -> Result<OrderInfo, String> {
+    let result = BinanceRequest::get("/fapi/v1/order")
+        .param("symbol", symbol)
+        .param("origClientOrderId", client_order_id)
+        .signed()
+        .send()
+        .await;
 
-    if status == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        println!("Order data: {}", data);
-        status.to_string()
+    if result.status() == StatusCode::OK {
+        let data: OrderInfo = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
     } else {
         let error = error_handler(result, None).await;
-        if (error == "E03: Error 502, exchange server is in trouble.")
-            || error.contains("No need to change")
-        {
-            error
-        } else if error == "E07: Dns error: No such host is known."
+        if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
-            deactivate_hedge_mode().await
+            query_order(symbol, client_order_id).await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            Err(error)
         }
     }
 }
 
-/// Retrieves detailed information about an order with the given order ID.
-///
-/// This function sends a request to the Binance exchange server to fetch detailed information
-/// about an order identified by the provided `order_id`. It returns a string representation of
-/// the JSON response containing order details.
-///
-/// # Arguments
-/// * `order_id`: A unique identifier for the order.
+/// Retrieves the balances of the futures wallet.
 ///
 /// # Returns
-/// A `String` containing the JSON response with order details.
+/// A vector with one `Balance` per asset or the mapped error.
 ///
 #[async_recursion]
-pub async fn get_order(order_id: u64) -> String {
-    if order_id == 0 {
-        return "Invalid Order ID.".to_string();
-    }
-
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!(
-        "orderId={}&symbol=BTCUSDT&timestamp={}&recvWindow=50000",
-        order_id, timestamp
-    );
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/order?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
+pub async fn balance_info() -> Result<Vec<Balance>, String> {
+    let result = BinanceRequest::get("/fapi/v2/balance")
+        .signed()
+        .send()
+        .await;
 
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
     if result.status() == StatusCode::OK {
-        let data: serde_json::Value = result.json().await.unwrap();
-        //println!("data :{}", data);
-        data.to_string()
-        //data["status"].to_string().replace('\"', "")
+        let data: Vec<Balance> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
-            error
-        } else if error == "E07: Dns error: No such host is known."
+        if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
-            get_order(order_id).await
+            balance_info().await
         } else {
-            println!("{}", error);
-            std::process::exit(1);
+            Err(error)
         }
     }
 }
 
+/// Retrieves the price that triggers the stop orders of BTCUSDT: the last price or the mark price.
+async fn trigger_price(working_type: WorkingType) -> Option<f64> {
+    match working_type {
+        WorkingType::ContractPrice => last_price("BTCUSDT").await.ok(),
+        WorkingType::MarkPrice => mark_price("BTCUSDT").await.ok(),
+    }
+}
+
 /// Checks if a stop order can be placed for a "LONG" position.
 ///
 /// This function determines whether a stop order can be placed for a "LONG" position based on the
@@ -1209,14 +2002,18 @@ pub async fn get_order(order_id: u64) -> String {
 ///
 /// # Arguments
 /// * `price_order`: The price at which the stop order is intended to trigger.
+/// * `working_type`: The price compared with `price_order`: the last price or the mark price (it should be the
+///   working type of the stop order).
 ///
 /// # Returns
-/// A boolean value indicating whether the stop order can be placed for a "LONG" position.
+/// A boolean value indicating whether the stop order can be placed for a "LONG" position. It is false when the
+/// price cannot be read.
 ///
-pub async fn can_place_stop_order_long(price_order: f64) -> bool {
+pub async fn can_place_stop_order_long(price_order: f64, working_type: WorkingType) -> bool {
     //Get current market price
-    let res: String = price_ticker("BTCUSDT".to_string()).await.replace('\"', "");
-    let market_price = res.parse::<f64>().unwrap();
+    let Some(market_price) = trigger_price(working_type).await else {
+        return false;
+    };
 
     //Update trailing if it will not trigger
     price_order < market_price
@@ -1230,14 +2027,18 @@ pub async fn can_place_stop_order_long(price_order: f64) -> bool {
 ///
 /// # Arguments
 /// * `price_order`: The price at which the stop order is intended to trigger.
+/// * `working_type`: The price compared with `price_order`: the last price or the mark price (it should be the
+///   working type of the stop order).
 ///
 /// # Returns
-/// A boolean value indicating whether the stop order can be placed for a "SHORT" position.
+/// A boolean value indicating whether the stop order can be placed for a "SHORT" position. It is false when the
+/// price cannot be read.
 ///
-pub async fn can_place_stop_order_short(price_order: f64) -> bool {
+pub async fn can_place_stop_order_short(price_order: f64, working_type: WorkingType) -> bool {
     //Get current market price
-    let res: String = price_ticker("BTCUSDT".to_string()).await.replace('\"', "");
-    let market_price = res.parse::<f64>().unwrap();
+    let Some(market_price) = trigger_price(working_type).await else {
+        return false;
+    };
 
     //Update trailing if it will not trigger
     price_order > market_price
@@ -1246,78 +2047,257 @@ pub async fn can_place_stop_order_short(price_order: f64) -> bool {
 // let data: serde_json::Value = result.json().await.unwrap();
 // let data_string = data["stopPrice"].to_string();
 
+/// Logs the positions and the number of open orders of BTCUSDT.
+///
+/// The order functions do not call it, so placing or canceling an order sends no other request. See account.rs for the
+/// snapshots and their subscribers.
+///
+/// # Returns
+/// The snapshot of the account, or the error of the requests.
+///
+pub async fn account_trade_info() -> Result<account::AccountSnapshot, String> {
+    account::account_snapshot("BTCUSDT").await
+}
+
+/// Sends a request to the exchange through the rate limiter of the server (see rate_limiter.rs).
+///
+/// The request waits until it fits in the request weight and order limits. When it can not be sent, the response is
+/// the one the exchange would return (HTTP 429 or 418 with a Retry-After header), so `error_handler` maps it to the
+/// E11 and E12 errors. Requests that fail before reaching the exchange are sent again with `re_send_request`, so this
+/// must only be used for the idempotent requests (the new orders are sent by `BinanceRequest::send`, that looks for
+/// a lost order before sending it again).
 ///
+/// Each request runs in a `binance_request` tracing span with the method, the endpoint, the symbol, the client order id,
+/// the number of attempts, the HTTP status, the latency and the Binance error code. Only the path of the url is logged,
+/// so the API key and the signature never reach the logs.
 ///
+/// # Arguments
+/// * `client`: The client returned by `get_client`.
+/// * `request`: The complete url of the request.
+/// * `method`: The HTTP method ("GET", "POST", "PUT" or "DELETE").
+///
+/// # Returns
+/// The response of the exchange, an E21 error response when the url is not valid, an E24 error response when the
+/// request can not be signed, or an E13 error response when the request did not reach the exchange after
+/// MAX_SEND_ATTEMPTS attempts.
 ///
-pub async fn account_trade_info() {
-    let position_info: serde_json::Value = position_info().await.unwrap();
-    //let json: serde_json::Value = position_info.into();
-    //println!("ble {} ", position_info);
-    let temp1 = position_info.get(1).unwrap();
-    let temp0 = position_info.get(0).unwrap();
+pub async fn send_request(client: Client, request: String, method: &str) -> Response {
+    send_signed_request(client, request, method, None).await
+}
 
-    let pside = temp0["positionSide"].clone();
+/// Completes the url of a SIGNED request (timestamp, recvWindow and signature) once the rate limiter let it through,
+/// so the time waited in the limiter does not count in its recvWindow. It is called at each attempt, and returns E24
+/// when the request can not be signed.
+pub type SignUrl = dyn Fn(String) -> BoxFuture<'static, Result<String, String>> + Send + Sync;
 
-    if pside == "SHORT" {
-        println!(
-            "- Short position has: {} amount in operation.",
-            temp0["positionAmt"]
-        );
-        println!(
-            "- Long position has: {} amount in operation.",
-            temp1["positionAmt"]
-        );
-    } else if pside == "LONG" {
-        println!(
-            "- Short position has: {} amount in operation.",
-            temp1["positionAmt"]
-        );
-        println!(
-            "- Long position has: {} amount in operation.",
-            temp0["positionAmt"]
-        );
-    } else if pside == "BOTH" {
-        println!(
-            "- Both position has: {} amount in operation.",
-            temp0["positionAmt"]
-        );
-    } else {
-        println!("- Problem in the code with account_trade_info.");
-        std::process::exit(1);
+/// Sends a request like `send_request`, signing its url with `sign` right before each attempt is sent.
+///
+/// # Arguments
+/// * `request`: The url of the request, without the timestamp, the recvWindow and the signature when `sign` is set.
+/// * `sign`: Completes the url of a SIGNED request.
+///
+pub async fn send_signed_request(
+    client: Client,
+    request: String,
+    method: &str,
+    sign: Option<&SignUrl>,
+) -> Response {
+    match send_attempt(client.clone(), request.clone(), method, 1, sign).await {
+        Ok(response) => response,
+        Err(_) => re_send_attempts(client, request, method, sign).await,
     }
+}
 
-    println!(
-        "- The number of open orders now is: {}",
-        binance_open_orders()
-            .await
-            .unwrap()
-            .as_array()
-            .unwrap()
-            .len()
-    )
+/// Sends a request once through the rate limiter, like `send_signed_request`.
+///
+/// # Returns
+/// The response (an E21 error response when the url is not valid), or the error of a request that did not reach the
+/// exchange or whose response was lost (e.g., a timeout): the exchange may have executed it.
+///
+pub async fn try_send_request(
+    client: Client,
+    request: String,
+    method: &str,
+    sign: Option<&SignUrl>,
+) -> Result<Response, String> {
+    send_attempt(client, request, method, 1, sign).await
 }
 
-#[async_recursion]
+/// Sends a request again until it reaches the exchange, at most MAX_SEND_ATTEMPTS times in total, waiting longer
+/// after each failed attempt. The attempts go through the rate limiter and are counted in the span of the request.
 pub async fn re_send_request(client: Client, request: String, method: &str) -> Response {
-    println!("Re-sending the request!");
+    re_send_attempts(client, request, method, None).await
+}
 
-    if method == "GET" {
-        match client.get(request.clone()).send().await {
-            Ok(response) => response,
-            Err(_) => re_send_request(client, request, method).await,
+async fn re_send_attempts(
+    client: Client,
+    request: String,
+    method: &str,
+    sign: Option<&SignUrl>,
+) -> Response {
+    for attempt in 2..=MAX_SEND_ATTEMPTS {
+        tokio::time::sleep(resend_backoff(attempt)).await;
+        debug!(attempt, "Re-sending the request");
+        if let Ok(response) =
+            send_attempt(client.clone(), request.clone(), method, attempt, sign).await
+        {
+            return response;
         }
-    } else if method == "POST" {
-        match client.get(request.clone()).send().await {
-            Ok(response) => response,
-            Err(_) => re_send_request(client, request, method).await,
+    }
+    unreachable_response()
+}
+
+/// Time waited before an attempt: RESEND_BACKOFF, doubled after each failed attempt, up to MAX_RESEND_BACKOFF.
+pub fn resend_backoff(attempt: u32) -> Duration {
+    (RESEND_BACKOFF * 2u32.pow(attempt.saturating_sub(2).min(8))).min(MAX_RESEND_BACKOFF)
+}
+
+/// Response returned for a request that did not reach the exchange after every attempt (mapped to E13).
+pub fn unreachable_response() -> Response {
+    Response::from(
+        http::Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE.as_u16())
+            .body(format!(
+                "The request did not reach the exchange after {} attempts.",
+                MAX_SEND_ATTEMPTS
+            ))
+            .unwrap(),
+    )
+}
+
+/// Sends one attempt of a request, in its `binance_request` span.
+async fn send_attempt(
+    client: Client,
+    request: String,
+    method: &str,
+    attempt: u32,
+    sign: Option<&SignUrl>,
+) -> Result<Response, String> {
+    let url = match reqwest::Url::parse(&request) {
+        Ok(url) => url,
+        Err(error) => {
+            warn!(%error, "The url of the request is not valid");
+            let body = serde_json::json!({ "code": -1100, "msg": INVALID_REQUEST_URL });
+            return Ok(Response::from(
+                http::Response::builder()
+                    .status(StatusCode::BAD_REQUEST.as_u16())
+                    .body(body.to_string())
+                    .unwrap(),
+            ));
         }
-    } else if method == "DELETE" {
-        match client.delete(request.clone()).send().await {
-            Ok(response) => response,
-            Err(_) => re_send_request(client, request, method).await,
+    };
+
+    let span = info_span!(
+        "binance_request",
+        method,
+        endpoint = url.path(),
+        symbol = Empty,
+        client_order_id = Empty,
+        attempt,
+        status = Empty,
+        latency_ms = Empty,
+        error_code = Empty,
+    );
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "symbol" => span.record("symbol", value.as_ref()),
+            "newClientOrderId" | "origClientOrderId" => {
+                span.record("client_order_id", value.as_ref())
+            }
+            _ => &span,
+        };
+    }
+
+    send_limited_request(client, request, method, url, sign, span.clone())
+        .instrument(span)
+        .await
+}
+
+async fn send_limited_request(
+    client: Client,
+    request: String,
+    method: &str,
+    url: reqwest::Url,
+    sign: Option<&SignUrl>,
+    span: Span,
+) -> Result<Response, String> {
+    let limiter = rate_limiter::rate_limiter(&url.origin().ascii_serialization());
+
+    if let Err(rejection) = limiter
+        .acquire(method, url.path(), url.query().unwrap_or(""))
+        .await
+    {
+        span.record("status", rejection.status.as_u16());
+        span.record("error_code", -1003);
+        warn!(
+            retry_after = rejection.retry_after,
+            "Request rejected by the local rate limiter"
+        );
+        let body = format!(
+            "{{\"code\":-1003,\"msg\":\"Request rejected by the local rate limiter, retry after {} seconds.\"}}",
+            rejection.retry_after
+        );
+        return Ok(Response::from(
+            http::Response::builder()
+                .status(rejection.status.as_u16())
+                .header("Retry-After", rejection.retry_after.to_string())
+                .body(body)
+                .unwrap(),
+        ));
+    }
+
+    // Signed only now, so the time waited in the limiter does not count in the recvWindow of the request.
+    let request = match sign {
+        Some(sign) => match sign(request).await {
+            Ok(request) => request,
+            Err(_) => return Ok(credentials_error_response()),
+        },
+        None => request,
+    };
+    let started = Instant::now();
+    let response = match send_once(&client, &request, method).await {
+        Ok(response) => response,
+        Err(error) => {
+            let error = error.without_url().to_string();
+            warn!(%error, "The request did not reach the exchange");
+            return Err(error);
         }
-    } else {
-        panic!("Invalid method in the re-send request.")
+    };
+    let status = response.status();
+    span.record("status", status.as_u16());
+    span.record("latency_ms", started.elapsed().as_millis() as u64);
+    limiter.record(status, response.headers());
+
+    if status.is_success() {
+        debug!("Request completed");
+        return Ok(response);
+    }
+
+    // The body of the error is read here to log its code, and put back in the response for `error_handler`.
+    let headers = response.headers().clone();
+    let body = response.bytes().await.unwrap_or_default();
+    let code = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|data| data["code"].as_i64());
+    if let Some(code) = code {
+        span.record("error_code", code);
+    }
+    warn!(error_code = code, "Request failed");
+
+    let mut builder = http::Response::builder().status(status);
+    if let Some(builder_headers) = builder.headers_mut() {
+        *builder_headers = headers;
+    }
+    Ok(Response::from(builder.body(body).unwrap()))
+}
+
+async fn send_once(client: &Client, request: &str, method: &str) -> reqwest::Result<Response> {
+    match method {
+        "GET" => client.get(request).send().await,
+        "POST" => client.post(request).send().await,
+        "DELETE" => client.delete(request).send().await,
+        "PUT" => client.put(request).send().await,
+        _ => panic!("Invalid method in the send request."),
     }
 }
 
@@ -1325,46 +2305,49 @@ pub async fn re_send_request(client: Client, request: String, method: &str) -> R
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server;
     use http;
     use reqwest::Response;
     use serde::__private::de::IdentifierDeserializer;
-    use std::thread::sleep;
     use std::time::Duration;
     use tokio::test;
 
-    /// Reset the environment for testing.
-    ///
-    /// This function sets up a clean testing environment for other test cases.
-    ///
-    async fn reset_for_test() {
-        cancel_all_open_orders().await;
-
-        activate_hedge_mode().await;
-        close_position(false, Some("LONG".to_string())).await;
-        close_position(true, Some("SHORT".to_string())).await;
-        //activate_hedge_mode().await;
+    /// Starts a mock server private to the test, in hedge mode like the account the legacy tests were written for, so
+    /// the orders and positions of the other tests running in parallel are not seen nor changed.
+    fn hedge_mode_server() -> mock_server::MockServer {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server
     }
 
     /// Test closing a short position.
     ///
     /// This test function checks the functionality of closing a short position by calling the `close_position`
-    /// function with the "SHORT" position side. It first resets the testing environment using `reset_for_test`.
+    /// function with the "SHORT" position side. It runs on a mock server of its own (see `hedge_mode_server`).
     ///
     #[test]
     async fn close_short_position_test() {
-        reset_for_test().await;
-        close_position(true, Some("SHORT".to_string())).await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                close_position(PositionSide::Short).await;
+            })
+            .await;
     }
 
     /// Test closing a long position.
     ///
     /// This test function checks the functionality of closing a long position by calling the `close_position`
-    /// function with the "LONG" position side. It first resets the testing environment using `reset_for_test`.
+    /// function with the "LONG" position side. It runs on a mock server of its own (see `hedge_mode_server`).
     ///
     #[test]
     async fn close_long_position_test() {
-        reset_for_test().await;
-        close_position(false, Some("LONG".to_string())).await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                close_position(PositionSide::Long).await;
+            })
+            .await;
     }
 
     /// Test calculating quantity in BTC.
@@ -1373,11 +2356,12 @@ mod tests {
     ///
     #[test]
     async fn calculate_quantity_in_btc_test() {
+        mock_server::shared();
         let res = calculate_quantity_in_btc(true).await;
-        assert_eq!(res, 0.001);
+        assert_eq!(res, Ok(0.001));
 
         let res = calculate_quantity_in_btc(false).await;
-        assert_eq!(res, 0.002);
+        assert_eq!(res, Ok(0.002));
     }
 
     /// Test getting an HTTP client.
@@ -1386,7 +2370,8 @@ mod tests {
     ///
     #[test]
     async fn get_client_test() {
-        get_client().await;
+        mock_server::shared();
+        assert!(get_client().await.is_ok());
     }
 
     /// Test getting a timestamp.
@@ -1395,6 +2380,7 @@ mod tests {
     ///
     #[test]
     async fn get_timestamp_test() {
+        mock_server::shared();
         let time_now = SystemTime::now();
         let res = get_timestamp(time_now).await;
         assert!(res > 1672531200); // > 2023
@@ -1407,19 +2393,23 @@ mod tests {
     ///
     #[test]
     async fn new_order_long_test() {
-        reset_for_test().await;
-
-        let truncated_price: f64 = 20000.0;
-        let res = new_order(
-            truncated_price,
-            &mut 0,
-            true,
-            false,
-            Some("LONG".to_string()),
-        )
-        .await;
-        assert_eq!(res, "200 OK".to_string());
-        close_position(false, Some("LONG".to_string())).await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let truncated_price: f64 = 20000.0;
+                let res = new_order(
+                    truncated_price,
+                    &mut 0,
+                    Side::Buy,
+                    false,
+                    PositionSide::Long,
+                    WorkingType::ContractPrice,
+                )
+                .await;
+                assert_eq!(res, "200 OK".to_string());
+                close_position(PositionSide::Long).await;
+            })
+            .await;
     }
 
     /// Test placing a new order for a short position.
@@ -1429,19 +2419,23 @@ mod tests {
     ///
     #[test]
     async fn new_order_short_test() {
-        reset_for_test().await;
-
-        let truncated_price: f64 = 200000.0;
-        let res = new_order(
-            truncated_price,
-            &mut 0,
-            false,
-            false,
-            Some("SHORT".to_string()),
-        )
-        .await;
-        assert_eq!(res, "200 OK".to_string());
-        close_position(true, Some("SHORT".to_string())).await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let truncated_price: f64 = 200000.0;
+                let res = new_order(
+                    truncated_price,
+                    &mut 0,
+                    Side::Sell,
+                    false,
+                    PositionSide::Short,
+                    WorkingType::ContractPrice,
+                )
+                .await;
+                assert_eq!(res, "200 OK".to_string());
+                close_position(PositionSide::Short).await;
+            })
+            .await;
     }
 
     /// Test placing a stop order for a long position.
@@ -1452,15 +2446,18 @@ mod tests {
     ///
     #[test]
     async fn can_place_stop_order_long_test() {
-        reset_for_test().await;
-
-        //Try to place a long order in a higher price (should work);
-        let res = can_place_stop_order_long(1.0).await;
-        assert!(res, "Can't place the stop order long.");
-
-        //Try to place a long order in a higher price (should not work);
-        let res = can_place_stop_order_long(f64::MAX).await;
-        assert!(!res, "Can't place the stop order long.");
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                //Try to place a long order in a higher price (should work);
+                let res = can_place_stop_order_long(1.0, WorkingType::ContractPrice).await;
+                assert!(res, "Can't place the stop order long.");
+
+                //Try to place a long order in a higher price (should not work);
+                let res = can_place_stop_order_long(f64::MAX, WorkingType::ContractPrice).await;
+                assert!(!res, "Can't place the stop order long.");
+            })
+            .await;
     }
 
     /// Test placing a stop order for a short position.
@@ -1471,15 +2468,140 @@ mod tests {
     ///
     #[test]
     async fn can_place_stop_order_short_test() {
-        reset_for_test().await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                //Try to place a short order in a lower price (should work);
+                let res = can_place_stop_order_short(f64::MAX, WorkingType::ContractPrice).await;
+                assert!(res, "Can't place the stop order short.");
+
+                //Try to place a short order in a higher price (should not work);
+                let res = can_place_stop_order_short(1.0, WorkingType::ContractPrice).await;
+                assert!(!res, "Can't place the stop order short.");
+            })
+            .await;
+    }
+
+    /// Test that the stop checks compare with the mark price when it is asked for, and not with the last price.
+    #[test]
+    async fn can_place_stop_order_mark_price_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        server.set_mark_price(29500.0);
+        let (long_last, long_mark, short_last, short_mark, index) = server
+            .run(async {
+                (
+                    can_place_stop_order_long(29800.0, WorkingType::ContractPrice).await,
+                    can_place_stop_order_long(29800.0, WorkingType::MarkPrice).await,
+                    can_place_stop_order_short(29800.0, WorkingType::ContractPrice).await,
+                    can_place_stop_order_short(29800.0, WorkingType::MarkPrice).await,
+                    premium_index("BTCUSDT").await.unwrap(),
+                )
+            })
+            .await;
+
+        assert!(long_last && !long_mark);
+        assert!(!short_last && short_mark);
+        assert_eq!(index.mark_price, 29500.0);
+        assert_eq!(index.index_price, 30000.0);
+        assert_eq!(index.next_funding_time % 28_800_000, 0);
+    }
+
+    /// Test that the stop orders are sent with their working type, and that the stop checks return false when the
+    /// last price cannot be read.
+    #[test]
+    async fn stop_order_working_type_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        server.set_mark_price(29500.0);
+        let handle = server.clone();
+        let stop = |working_type: Option<WorkingType>| OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::StopMarket,
+            quantity: 0.001,
+            price: None,
+            stop_price: Some(29800.0),
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type,
+        };
 
-        //Try to place a short order in a lower price (should work);
-        let res = can_place_stop_order_short(f64::MAX).await;
-        assert!(res, "Can't place the stop order short.");
+        let (mark, last, legacy, unreadable) = server
+            .run(async move {
+                let mark = place_order(&stop(Some(WorkingType::MarkPrice))).await;
+                let last = place_order(&stop(None)).await;
+                let legacy = new_order(
+                    29800.0,
+                    &mut 0,
+                    Side::Buy,
+                    false,
+                    PositionSide::Both,
+                    WorkingType::MarkPrice,
+                )
+                .await;
+                handle.inject_error("/fapi/v1/ticker/price", 400, -1121, "Invalid symbol.");
+                let unreadable =
+                    can_place_stop_order_long(29800.0, WorkingType::ContractPrice).await;
+                (mark, last, legacy, unreadable)
+            })
+            .await;
+
+        assert!(mark.is_ok());
+        assert_eq!(last.unwrap_err(), ORDER_WOULD_TRIGGER_IMMEDIATELY);
+        assert_eq!(legacy, "200 OK");
+        assert!(!unreadable);
+        let orders: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .collect();
+        assert!(orders[0].contains("workingType=MARK_PRICE"));
+        assert!(!orders[1].contains("workingType"));
+        assert!(orders[2].contains("workingType=MARK_PRICE"));
+    }
 
-        //Try to place a short order in a higher price (should not work);
-        let res = can_place_stop_order_short(1.0).await;
-        assert!(!res, "Can't place the stop order short.");
+    /// Test that the funding rate history reads every page of 1000 rates.
+    #[test]
+    async fn funding_rate_history_test() {
+        let server = mock_server::isolated();
+        const EIGHT_HOURS: u64 = 28_800_000;
+        for i in 0..2500u64 {
+            server.add_funding_rate("BTCUSDT", 0.0001 * (i % 3) as f64, (i + 1) * EIGHT_HOURS);
+        }
+        server.add_funding_rate("ETHUSDT", 0.0002, EIGHT_HOURS);
+
+        let (history, range, last) = server
+            .run(async {
+                (
+                    funding_rate_history("BTCUSDT", None, None).await.unwrap(),
+                    funding_rate_history("BTCUSDT", Some(10 * EIGHT_HOURS), Some(20 * EIGHT_HOURS))
+                        .await
+                        .unwrap(),
+                    funding_rate(Some("BTCUSDT"), None, None, None)
+                        .await
+                        .unwrap(),
+                )
+            })
+            .await;
+
+        assert_eq!(history.len(), 2500);
+        assert!(history.iter().all(|r| r.symbol == "BTCUSDT"));
+        assert!(history
+            .windows(2)
+            .all(|w| w[0].funding_time < w[1].funding_time));
+        assert_eq!(history[1].funding_rate, 0.0001);
+        assert_eq!(range.len(), 11);
+        // Without start time, a page has the last 100 rates.
+        assert_eq!(last.len(), 100);
+        assert_eq!(last[99].funding_time, 2500 * EIGHT_HOURS);
+        let pages = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("GET /fapi/v1/fundingRate"))
+            .count();
+        assert_eq!(pages, 5);
     }
 
     /// Test placing a new order with a limit price.
@@ -1489,25 +2611,25 @@ mod tests {
     ///
     #[test]
     async fn new_order_limit_test() {
-        reset_for_test().await;
-
-        //Get current market price
-        let res: String = price_ticker("BTCUSDT".to_string()).await.replace('\"', "");
-        let market_price = res.parse::<f64>().unwrap();
-        let res =
-            new_order_limit(market_price * 1.05, &mut 0, true, Some("LONG".to_string())).await;
-        assert_eq!(res, "200 OK".to_string());
-
-        let res = new_order_limit(
-            market_price * 0.95,
-            &mut 0,
-            false,
-            Some("SHORT".to_string()),
-        )
-        .await;
-        assert_eq!(res, "200 OK".to_string());
-
-        cancel_all_open_orders().await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                //Get current market price
+                let res: String = price_ticker("BTCUSDT".to_string()).await.replace('\"', "");
+                let market_price = res.parse::<f64>().unwrap();
+                let res =
+                    new_order_limit(market_price * 1.05, &mut 0, Side::Buy, PositionSide::Long)
+                        .await;
+                assert_eq!(res, "200 OK".to_string());
+
+                let res =
+                    new_order_limit(market_price * 0.95, &mut 0, Side::Sell, PositionSide::Short)
+                        .await;
+                assert_eq!(res, "200 OK".to_string());
+
+                cancel_all_open_orders().await;
+            })
+            .await;
     }
 
     /// Test placing a new order with a market price.
@@ -1517,47 +2639,52 @@ mod tests {
     ///
     #[test]
     async fn new_order_market_test() {
-        reset_for_test().await;
-
-        let res = new_order_market(&mut 0, true, "LONG".to_string()).await;
-        assert_eq!(res, "200 OK".to_string());
-
-        let res = new_order_market(&mut 0, false, "SHORT".to_string()).await;
-        assert_eq!(res, "200 OK".to_string());
-
-        cancel_all_open_orders().await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let res = new_order_market(&mut 0, Side::Buy, PositionSide::Long).await;
+                assert_eq!(res, "200 OK".to_string());
+
+                let res = new_order_market(&mut 0, Side::Sell, PositionSide::Short).await;
+                assert_eq!(res, "200 OK".to_string());
+
+                cancel_all_open_orders().await;
+            })
+            .await;
     }
 
     /// Test getting the stop price of an order.
     ///
     /// This test function checks the functionality of getting the stop price of an order by calling the `get_stop_price`
-    /// function. It creates a new order with a specific price, waits for a short duration, and then verifies that the
-    /// retrieved stop price matches the expected value.
+    /// function. It creates a new order with a specific price and then verifies that the retrieved stop price
+    /// matches the expected value.
     ///
     #[test]
     async fn get_stop_price_test() {
-        reset_for_test().await;
-
-        let res: String = price_ticker("BTCUSDT".to_string()).await.replace('\"', "");
-        let market_price = res.parse::<f64>().unwrap();
-        let mut order_id: u64 = 0;
-        let res = new_order(
-            (market_price * 1.05 * 100.0) / 100.0,
-            &mut order_id,
-            true,
-            false,
-            Some("LONG".to_string()),
-        )
-        .await;
-
-        sleep(Duration::from_secs(1));
-
-        let status = get_stop_price(order_id).await;
-        // Arredondar o valor de market_price * 1.05 para a primeira casa decimal
-        let expected_status = (market_price * 1.05 * 100.0).trunc() / 100.0 + 1.0;
-
-        // Use assert_approx_eq para verificar se os valores são aproximadamente iguais com uma tolerância de 0.1
-        assert_eq!(status, expected_status.to_string());
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let res: String = price_ticker("BTCUSDT".to_string()).await.replace('\"', "");
+                let market_price = res.parse::<f64>().unwrap();
+                let mut order_id: u64 = 0;
+                let res = new_order(
+                    (market_price * 1.05 * 100.0) / 100.0,
+                    &mut order_id,
+                    Side::Buy,
+                    false,
+                    PositionSide::Long,
+                    WorkingType::ContractPrice,
+                )
+                .await;
+
+                let status = get_stop_price(order_id).await;
+                // Arredondar o valor de market_price * 1.05 para a primeira casa decimal
+                let expected_status = (market_price * 1.05 * 100.0).trunc() / 100.0 + 1.0;
+
+                // Use assert_approx_eq para verificar se os valores são aproximadamente iguais com uma tolerância de 0.1
+                assert_eq!(status, expected_status.to_string());
+            })
+            .await;
     }
 
     /// Test checking the connection to the Binance server.
@@ -1568,6 +2695,7 @@ mod tests {
     ///
     #[test]
     async fn connection_test() {
+        mock_server::shared();
         let res = test_binance_connection().await;
         assert_eq!(res, "200 OK".to_string());
     }
@@ -1579,6 +2707,7 @@ mod tests {
     ///
     #[test]
     async fn exchange_info_test() {
+        mock_server::shared();
         let res = exchange_info().await;
 
         assert!(res.contains("assets"));
@@ -1595,6 +2724,7 @@ mod tests {
     ///
     #[test]
     async fn price_ticker_test() {
+        mock_server::shared();
         let res = price_ticker("BTCUSDT".to_string()).await;
         let mut has_num = false;
         for c in res.chars() {
@@ -1623,62 +2753,637 @@ mod tests {
     ///
     #[test]
     async fn activate_hedge_mode_test() {
-        reset_for_test().await;
-        let mut res = activate_hedge_mode().await;
-
-        if res == "200 OK" || res == "E06: No need to change position side." {
-            res = "ok".to_string();
-        }
-        assert_eq!(res, "ok");
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let mut res = activate_hedge_mode().await;
+
+                if res == "200 OK" || res == "E06: No need to change position side." {
+                    res = "ok".to_string();
+                }
+                assert_eq!(res, "ok");
+            })
+            .await;
     }
 
     /// Test retrieving information about a specific order.
     ///
     /// This test function checks the functionality of getting information about a specific order by calling the `get_order`
-    /// function. It creates a new order, waits briefly, and then verifies that the response contains the order ID.
+    /// function. It creates a new order and then verifies that the response contains the order ID.
     ///
     #[test]
     async fn get_order_test() {
-        reset_for_test().await;
-
-        activate_hedge_mode().await;
-        let mut order_id: u64 = 0;
-        let truncated_price: f64 = 200000.0;
-        let res = new_order(
-            truncated_price,
-            &mut order_id,
-            false,
-            false,
-            Some("SHORT".to_string()),
-        )
-        .await;
-        assert_eq!(res, "200 OK".to_string());
-        sleep(Duration::from_secs(1));
-
-        let res = get_order(order_id).await;
-        assert!(res.contains(&order_id.to_string()));
-        close_position(true, Some("SHORT".to_string())).await;
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let mut order_id: u64 = 0;
+                let truncated_price: f64 = 200000.0;
+                let res = new_order(
+                    truncated_price,
+                    &mut order_id,
+                    Side::Sell,
+                    false,
+                    PositionSide::Short,
+                    WorkingType::ContractPrice,
+                )
+                .await;
+                assert_eq!(res, "200 OK".to_string());
+                let res = get_order(order_id).await;
+                assert!(res.contains(&order_id.to_string()));
+                close_position(PositionSide::Short).await;
+            })
+            .await;
     }
 
     #[test]
     async fn re_send_request_test() {
-        reset_for_test().await;
-        let client: reqwest::Client = get_client().await;
-        // Cancel the order
-        let timestamp = get_timestamp(SystemTime::now()).await;
-        let params = format!("symbol=BTCUSDT&timestamp={}&recvWindow=50000", timestamp);
-        let signature = get_signature(params.clone()).await;
-        let request = format!(
-            "{}/fapi/v1/openOrders?{}&signature={}",
-            exchange_url().await,
-            params.clone(),
-            signature.clone()
+        let server = hedge_mode_server();
+        server
+            .run(async {
+                let client: reqwest::Client = get_client().await.unwrap();
+                // Cancel the order
+                let timestamp = get_timestamp(SystemTime::now()).await;
+                let params = format!("symbol=BTCUSDT&timestamp={}&recvWindow=50000", timestamp);
+                let signature = get_signature(params.clone()).await.unwrap();
+                let request = format!(
+                    "{}/fapi/v1/openOrders?{}&signature={}",
+                    exchange_url().await,
+                    params.clone(),
+                    signature.clone()
+                );
+                // Sending HTTP delete will cancel the order
+                let result = match client.get(request.clone()).send().await {
+                    Ok(response) => response,
+                    Err(_) => re_send_request(client, "bad request".to_string(), "GET").await,
+                };
+                assert!(result.status().is_success());
+            })
+            .await;
+    }
+
+    /// Test that a request with an invalid url is not sent, and that a request that never reaches the exchange is
+    /// given up after MAX_SEND_ATTEMPTS attempts.
+    #[test]
+    async fn send_request_gives_up_test() {
+        mock_server::shared();
+        let client = get_public_client().await;
+
+        let result = send_request(client.clone(), "bad request".to_string(), "GET").await;
+        assert_eq!(error_handler(result, None).await, INVALID_REQUEST_URL);
+
+        // Nothing listens on port 9 of the local host.
+        let started = Instant::now();
+        let result =
+            send_request(client, "http://127.0.0.1:9/fapi/v1/ping".to_string(), "GET").await;
+        assert_eq!(error_handler(result, None).await, ERROR_SERVER_UNAVAILABLE);
+        assert!(started.elapsed() >= resend_backoff(2) + resend_backoff(3) + resend_backoff(4));
+    }
+
+    /// Test that a request that could not be signed is mapped to E24.
+    #[test]
+    async fn credentials_error_response_test() {
+        let result = credentials_error_response();
+        assert_eq!(result.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(error_handler(result, None).await, CREDENTIALS_ERROR);
+    }
+
+    /// Places a long stop order in a private mock server, after setting it in hedge mode.
+    async fn new_order_in(server: &mock_server::MockServer, price_order: f64) -> String {
+        server
+            .run(new_order(
+                price_order,
+                &mut 0,
+                Side::Buy,
+                false,
+                PositionSide::Long,
+                WorkingType::ContractPrice,
+            ))
+            .await
+    }
+
+    /// Counts the orders sent to a mock server.
+    fn order_requests(server: &mock_server::MockServer, method: &str) -> usize {
+        server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with(&format!("{} /fapi/v1/order?", method)))
+            .count()
+    }
+
+    /// Test that an order rejected for being outside of the recvWindow is sent again.
+    #[test]
+    async fn new_order_recv_window_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_error(
+            "/fapi/v1/order",
+            400,
+            -1021,
+            "Timestamp for this request is outside of the recvWindow.",
+        );
+
+        let res = new_order_in(&server, 30000.0).await;
+        assert_eq!(res, "200 OK");
+        assert_eq!(order_requests(&server, "POST"), 2);
+    }
+
+    /// Test that an order answered with a 502 html page returns the error instead of stopping the program.
+    #[test]
+    async fn new_order_bad_gateway_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_fault(
+            "/fapi/v1/order",
+            mock_server::Fault::HttpStatus {
+                status: 502,
+                body: "<html><body><h1>502 Bad Gateway</h1></body></html>".to_string(),
+                retry_after: None,
+            },
+        );
+
+        let res = new_order_in(&server, 30000.0).await;
+        assert_eq!(res, ERROR_SERVER_502);
+    }
+
+    /// Test that an order rejected because the local clock is ahead of the server synchronises the clock and is
+    /// sent again with the corrected timestamp.
+    #[test]
+    async fn new_order_clock_drift_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_clock_offset(-1500);
+
+        let res = new_order_in(&server, 30000.0).await;
+        assert_eq!(res, "200 OK");
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("GET /fapi/v1/time")));
+    }
+
+    /// Test that an order rejected by a dns error of the exchange is sent again.
+    #[test]
+    async fn new_order_dns_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_error("/fapi/v1/order", 400, -1000, "No such host is known.");
+
+        let res = new_order_in(&server, 30000.0).await;
+        assert_eq!(res, "200 OK");
+        assert_eq!(order_requests(&server, "POST"), 2);
+    }
+
+    /// Test that an order whose connection is reset is looked up, and sent again once it can not be executed any
+    /// more, with the same client order id.
+    #[test]
+    async fn new_order_connection_reset_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_fault("/fapi/v1/order", mock_server::Fault::ConnectionReset);
+
+        let res = new_order_in(&server, 30000.0).await;
+        assert_eq!(res, "200 OK");
+        assert_eq!(order_requests(&server, "POST"), 2);
+        assert!(order_requests(&server, "GET") > 0);
+        let client_ids: Vec<String> = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order?"))
+            .map(|r| {
+                r.split("newClientOrderId=")
+                    .nth(1)
+                    .unwrap()
+                    .split('&')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(client_ids[0], client_ids[1]);
+    }
+
+    /// Test that an order that takes longer than the request timeout is found by its client order id, and is not
+    /// placed twice.
+    #[test]
+    async fn new_order_latency_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_fault(
+            "/fapi/v1/order",
+            mock_server::Fault::Latency(Duration::from_secs(3)),
+        );
+
+        set_request_timeout(Duration::from_secs(1));
+        let res = new_order_in(&server, 30000.0).await;
+        set_request_timeout(Duration::from_secs(10));
+
+        assert_eq!(res, "200 OK");
+        assert_eq!(order_requests(&server, "POST"), 1);
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("GET /fapi/v1/order?") && r.contains("origClientOrderId=")));
+        let orders = server.run(open_orders(Some("BTCUSDT"))).await.unwrap();
+        assert_eq!(orders.len(), 1);
+    }
+
+    /// Test that the rate limit (429) and the IP ban (418) are returned to the caller.
+    #[test]
+    async fn new_order_rate_limit_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_fault(
+            "/fapi/v1/order",
+            mock_server::Fault::HttpStatus {
+                status: 429,
+                body: String::new(),
+                retry_after: Some(1),
+            },
+        );
+        server.inject_fault(
+            "/fapi/v1/order",
+            mock_server::Fault::HttpStatus {
+                status: 418,
+                body: String::new(),
+                retry_after: Some(120),
+            },
+        );
+
+        assert_eq!(new_order_in(&server, 30000.0).await, ERROR_RATE_LIMITED);
+        assert_eq!(new_order_in(&server, 30000.0).await, ERROR_IP_BANNED);
+    }
+
+    /// Test that after an IP ban (418) the requests are rejected by the rate limiter without reaching the exchange,
+    /// and that the used weight of the response headers is tracked.
+    #[test]
+    async fn rate_limiter_ban_test() {
+        let server = mock_server::isolated();
+        server.run(book_ticker("BTCUSDT")).await.unwrap();
+        let limiter = rate_limiter::rate_limiter(&server.url);
+        assert_eq!(limiter.usage().used_weight_1m, 2);
+
+        server.inject_fault(
+            "/fapi/v1/ticker/bookTicker",
+            mock_server::Fault::HttpStatus {
+                status: 418,
+                body: String::new(),
+                retry_after: Some(120),
+            },
+        );
+        let res = server.run(book_ticker("BTCUSDT")).await;
+        assert_eq!(res.unwrap_err(), ERROR_IP_BANNED);
+        let res = server.run(book_ticker("BTCUSDT")).await;
+        assert_eq!(res.unwrap_err(), ERROR_IP_BANNED);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    /// Test that a cancel answered with an IP ban (418) returns the error instead of sending the cancel again, since
+    /// the rate limiter rejects every request until the ban expires.
+    #[test]
+    async fn cancel_open_order_ban_fault_test() {
+        let server = mock_server::isolated();
+        server.inject_fault(
+            "/fapi/v1/order",
+            mock_server::Fault::HttpStatus {
+                status: 418,
+                body: String::new(),
+                retry_after: Some(120),
+            },
+        );
+
+        assert_eq!(server.run(cancel_open_order(1)).await, ERROR_IP_BANNED);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    /// Test that placing and canceling an order do not read the positions or the open orders.
+    #[test]
+    async fn new_order_no_side_requests_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        let mut order_id = 0;
+
+        let res = server
+            .run(new_order(
+                30000.0,
+                &mut order_id,
+                Side::Buy,
+                false,
+                PositionSide::Long,
+                WorkingType::ContractPrice,
+            ))
+            .await;
+        assert_eq!(res, "200 OK");
+        assert_eq!(server.run(cancel_open_order(order_id)).await, "200 OK");
+
+        let requests = server.requests();
+        assert!(!requests
+            .iter()
+            .any(|r| r.contains("/fapi/v2/positionRisk") || r.contains("/fapi/v1/openOrders")));
+    }
+
+    /// Subscriber that keeps the fields of the spans and events, as "name=value".
+    #[derive(Clone, Default)]
+    struct FieldRecorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl tracing::field::Visit for FieldRecorder {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={}", field.name(), value));
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl tracing::Subscriber for FieldRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            span.record(&mut self.clone());
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            values.record(&mut self.clone());
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            event.record(&mut self.clone());
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    /// Test the fields of the span of a failed request, and that the signature is not logged.
+    #[test]
+    async fn send_request_tracing_test() {
+        use tracing::instrument::WithSubscriber;
+
+        let server = mock_server::isolated();
+        server.inject_fault(
+            "/fapi/v1/order",
+            mock_server::Fault::HttpStatus {
+                status: 400,
+                body: r#"{"code":-2019,"msg":"Margin is insufficient."}"#.to_string(),
+                retry_after: None,
+            },
+        );
+        let recorder = FieldRecorder::default();
+
+        let request = BinanceRequest::post("/fapi/v1/order")
+            .param("symbol", "BTCUSDT")
+            .param("newClientOrderId", "trace-1")
+            .signed();
+        let result = server
+            .run(request.send())
+            .with_subscriber(recorder.clone())
+            .await;
+        assert_eq!(result.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_handler(result, None).await,
+            ERROR_NOT_MAPPED.to_string()
+        );
+
+        let fields = recorder.0.lock().unwrap().clone();
+        for field in [
+            "method=POST",
+            "endpoint=/fapi/v1/order",
+            "symbol=BTCUSDT",
+            "client_order_id=trace-1",
+            "attempt=1",
+            "status=400",
+            "error_code=-2019",
+        ] {
+            assert!(
+                fields.contains(&field.to_string()),
+                "{} in {:?}",
+                field,
+                fields
+            );
+        }
+        assert!(fields.iter().any(|f| f.starts_with("latency_ms=")));
+        assert!(!fields.iter().any(|f| f.contains("signature")));
+    }
+
+    /// Test that an order answered with a truncated body returns the error instead of panicking.
+    #[test]
+    async fn new_order_malformed_body_fault_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.inject_fault("/fapi/v1/order", mock_server::Fault::MalformedBody);
+
+        let res = new_order_in(&server, 30000.0).await;
+        assert_eq!(res, ERROR_MALFORMED_RESPONSE);
+    }
+
+    /// Test that a stop order that would trigger immediately is replaced by a market order.
+    #[test]
+    async fn new_order_would_trigger_fallback_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+
+        let res = new_order_in(&server, 20000.0).await;
+        assert_eq!(res, "200 OK");
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("POST /fapi/v1/order?") && r.contains("type=MARKET")));
+    }
+
+    /// Test every fallback policy of a stop order that would trigger immediately, and that the decision is reported.
+    #[test]
+    async fn new_order_with_fallback_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+
+        let (error, placed, market, limit_ioc, adjusted) = server
+            .run(async {
+                let long = PositionSide::Long;
+                let mut id = 0;
+                (
+                    new_order_with_fallback(
+                        29000.0,
+                        &mut id,
+                        Side::Buy,
+                        false,
+                        long,
+                        None,
+                        StopFallback::Error,
+                    )
+                    .await,
+                    new_order_with_fallback(
+                        31000.0,
+                        &mut id,
+                        Side::Buy,
+                        false,
+                        long,
+                        Some(WorkingType::MarkPrice),
+                        StopFallback::Error,
+                    )
+                    .await
+                    .unwrap(),
+                    new_order_with_fallback(
+                        29000.0,
+                        &mut id,
+                        Side::Buy,
+                        false,
+                        long,
+                        None,
+                        StopFallback::Market,
+                    )
+                    .await
+                    .unwrap(),
+                    new_order_with_fallback(
+                        29990.0,
+                        &mut id,
+                        Side::Buy,
+                        false,
+                        long,
+                        None,
+                        StopFallback::LimitIoc {
+                            max_slippage: 0.001,
+                        },
+                    )
+                    .await
+                    .unwrap(),
+                    new_order_with_fallback(
+                        29000.0,
+                        &mut id,
+                        Side::Buy,
+                        false,
+                        long,
+                        None,
+                        StopFallback::AdjustStop {
+                            offset: 50.0,
+                            max_attempts: 2,
+                        },
+                    )
+                    .await
+                    .unwrap(),
+                )
+            })
+            .await;
+
+        assert_eq!(error.unwrap_err(), ORDER_WOULD_TRIGGER_IMMEDIATELY);
+        assert!(
+            matches!(placed, StopOrderOutcome::Placed { stop_price, .. } if stop_price == 31001.0)
         );
-        // Sending HTTP delete will cancel the order
-        let result = match client.get(request.clone()).send().await {
-            Ok(response) => response,
-            Err(_) => re_send_request(client, "bad request".to_string(), "GET").await,
+        assert!(matches!(
+            market,
+            StopOrderOutcome::Market { slippage: None, .. }
+        ));
+        match limit_ioc {
+            StopOrderOutcome::LimitIoc(report) => {
+                assert_eq!(report.protected_price, Some(30020.9));
+                assert_eq!(report.status, "FILLED");
+            }
+            outcome => panic!("unexpected outcome {outcome:?}"),
+        }
+        assert!(matches!(
+            adjusted,
+            StopOrderOutcome::StopAdjusted { stop_price, attempts: 1, .. } if stop_price == 30050.0
+        ));
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("POST /fapi/v1/order?")
+                && r.contains("stopPrice=31001")
+                && r.contains("workingType=MARK_PRICE")));
+    }
+
+    /// Test partial closes with the real position amount, the close position stop and the validation of the
+    /// percentage.
+    #[test]
+    async fn close_position_percent_test() {
+        let server = mock_server::isolated();
+        server.set_price(31000.0);
+        server.set_position("BTCUSDT", "BOTH", 0.003, 30000.0);
+
+        let acks = server
+            .run(close_position_percent("BTCUSDT", PositionSide::Both, 50.0))
+            .await
+            .unwrap();
+        assert_eq!(acks.len(), 1);
+        let request = server.requests().pop().unwrap();
+        assert!(request.contains("type=MARKET"));
+        assert!(request.contains("side=SELL"));
+        assert!(request.contains("quantity=0.001"));
+        assert!(request.contains("reduceOnly=true"));
+
+        // A long one-way position is not a short one.
+        let acks = server
+            .run(close_position_percent(
+                "BTCUSDT",
+                PositionSide::Short,
+                100.0,
+            ))
+            .await
+            .unwrap();
+        assert!(acks.is_empty());
+        assert!(server
+            .run(close_position_percent("BTCUSDT", PositionSide::Long, 0.0))
+            .await
+            .is_err());
+
+        server
+            .run(close_position_on_stop(
+                "BTCUSDT",
+                PositionSide::Long,
+                29000.0,
+            ))
+            .await
+            .unwrap();
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.contains("closePosition=true") && !r.contains("quantity=")));
+        server.set_price(28900.0);
+        let positions = server.run(position_risk(Some("BTCUSDT"))).await.unwrap();
+        assert_eq!(positions[0].position_amt, 0.0);
+    }
+
+    /// Test that the panic mode cancels the open orders and closes the positions of every symbol.
+    #[test]
+    async fn close_all_positions_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_position("BTCUSDT", "SHORT", -0.002, 30000.0);
+        server.set_position("ETHUSDT", "LONG", 0.5, 2000.0);
+        let order = OrderRequest {
+            symbol: "ETHUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            quantity: 0.5,
+            price: Some(1000.0),
+            stop_price: None,
+            position_side: PositionSide::Long,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
         };
-        assert!(result.status().is_success());
+        server.run(place_order(&order)).await.unwrap();
+
+        let acks = server.run(close_all_positions()).await.unwrap();
+        assert_eq!(acks.len(), 2);
+        assert!(server.run(open_orders(None)).await.unwrap().is_empty());
+        assert!(server
+            .run(position_risk(None))
+            .await
+            .unwrap()
+            .iter()
+            .all(|p| p.position_amt == 0.0));
     }
 }
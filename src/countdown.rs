@@ -0,0 +1,218 @@
+// countdown.rs - Countdown Cancel All (Dead Man's Switch)
+
+// This file contains the dead man's switch of the open orders: POST /fapi/v1/countdownCancelAll starts a countdown
+// in the exchange, and the open orders of the symbol are canceled by Binance when it reaches zero. Every request
+// starts the countdown again, so the orders only stay live while the bot keeps sending it.
+
+// `spawn_countdown_heartbeat` arms the switch and spawns a task that refreshes the countdown every `interval`. If
+// the process crashes or hangs, the heartbeat stops and the resting STOP_MARKET and LIMIT orders placed by
+// `new_order` and `new_order_limit` are canceled when the countdown expires, instead of staying live without a bot
+// behind them. `CountdownHeartbeat::stop` disarms the switch when the bot shuts down on purpose; dropping the
+// `CountdownHeartbeat` stops the refreshes without disarming it.
+
+use crate::binance_orders::spawn_scoped;
+use crate::request::BinanceRequest;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Starts (or starts again) the countdown after which the open orders of a symbol are canceled.
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `countdown`: The time before the orders are canceled. Zero disarms the countdown.
+///
+/// # Returns
+/// Ok when the countdown was set, or the mapped error.
+///
+pub async fn countdown_cancel_all(symbol: &str, countdown: Duration) -> Result<(), String> {
+    BinanceRequest::post("/fapi/v1/countdownCancelAll")
+        .param("symbol", symbol)
+        .param("countdownTime", countdown.as_millis())
+        .signed()
+        .fetch::<serde_json::Value>()
+        .await
+        .map(|_| ())
+}
+
+/// Task that refreshes the countdown of a symbol. Dropping it stops the task, like `abort`.
+#[derive(Debug)]
+pub struct CountdownHeartbeat {
+    symbol: String,
+    handle: JoinHandle<()>,
+}
+
+impl CountdownHeartbeat {
+    /// Get the symbol whose orders are protected by the heartbeat.
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Checks if the task is still refreshing the countdown.
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+
+    /// Stops the heartbeat and disarms the countdown, so the open orders stay live.
+    ///
+    /// # Returns
+    /// Ok when the countdown was disarmed, or the mapped error (the countdown is then still running).
+    ///
+    pub async fn stop(self) -> Result<(), String> {
+        self.handle.abort();
+        countdown_cancel_all(&self.symbol, Duration::ZERO).await?;
+        info!(symbol = %self.symbol, "Countdown cancel all disarmed");
+        Ok(())
+    }
+
+    /// Stops the heartbeat without disarming the countdown, so the open orders are canceled when it expires.
+    pub fn abort(self) {
+        self.handle.abort();
+    }
+}
+
+impl Drop for CountdownHeartbeat {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Arms the countdown of a symbol and spawns a task that refreshes it every `interval`.
+///
+/// The interval should be a fraction of the countdown (e.g., a third), so a slow or failed refresh does not let
+/// the countdown expire while the bot is healthy; it is reduced to half of the countdown when it is longer. The
+/// failed refreshes are logged and the next ones are still sent. The task sends the requests to the exchange and
+/// the account of the caller (see `spawn_scoped`).
+///
+/// # Arguments
+/// * `symbol`: The symbol (e.g., "BTCUSDT").
+/// * `countdown`: The time without refresh after which the open orders are canceled.
+/// * `interval`: The time between two refreshes.
+///
+/// # Returns
+/// The `CountdownHeartbeat`, or the error of the first request (the task is not spawned then).
+///
+pub async fn spawn_countdown_heartbeat(
+    symbol: &str,
+    countdown: Duration,
+    interval: Duration,
+) -> Result<CountdownHeartbeat, String> {
+    countdown_cancel_all(symbol, countdown).await?;
+    info!(
+        symbol,
+        countdown_ms = countdown.as_millis() as u64,
+        "Countdown cancel all armed"
+    );
+
+    let interval = interval.min(countdown / 2);
+    let task_symbol = symbol.to_string();
+    let handle = spawn_scoped(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(error) = countdown_cancel_all(&task_symbol, countdown).await {
+                warn!(symbol = %task_symbol, %error, "Failed to refresh the countdown cancel all");
+            }
+        }
+    });
+
+    Ok(CountdownHeartbeat {
+        symbol: symbol.to_string(),
+        handle,
+    })
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_orders::{new_order_limit, open_orders};
+    use crate::mock_server::{self, MockServer};
+    use crate::models::{PositionSide, Side};
+    use tokio::test;
+
+    /// Waits until the server has received `count` countdown requests. The task only yields, so the paused clock
+    /// does not move while the requests are sent.
+    async fn wait_for_countdowns(server: &MockServer, count: usize) {
+        while countdown_requests(server).len() < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn countdown_requests(server: &MockServer) -> Vec<String> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/countdownCancelAll"))
+            .collect()
+    }
+
+    /// Test that the heartbeat refreshes the countdown every interval, and that it is disarmed when the heartbeat
+    /// is stopped, so the orders stay live.
+    #[test]
+    async fn countdown_heartbeat_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+        let countdown = Duration::from_secs(60);
+        let interval = Duration::from_secs(20);
+
+        let open = server
+            .run({
+                let server = server.clone();
+                async move {
+                    new_order_limit(29000.0, &mut 0, Side::Buy, PositionSide::Long).await;
+                    let heartbeat = spawn_countdown_heartbeat("BTCUSDT", countdown, interval)
+                        .await
+                        .unwrap();
+
+                    // The clock is paused once the countdown is armed: a request waiting for the server would
+                    // otherwise let the runtime jump to its timeout.
+                    tokio::time::pause();
+                    for refreshes in 1..=3 {
+                        tokio::time::advance(interval).await;
+                        wait_for_countdowns(&server, 1 + refreshes).await;
+                    }
+                    assert!(heartbeat.is_running());
+                    tokio::time::resume();
+
+                    heartbeat.stop().await.unwrap();
+                    open_orders(Some("BTCUSDT")).await.unwrap().len()
+                }
+            })
+            .await;
+
+        assert_eq!(open, 1);
+        let requests = countdown_requests(&server);
+        assert_eq!(requests.len(), 5);
+        assert!(requests[..4]
+            .iter()
+            .all(|r| r.contains("countdownTime=60000")));
+        assert!(requests[4].contains("countdownTime=0"));
+    }
+
+    /// Test that dropping the heartbeat stops the refreshes without disarming the countdown.
+    #[test]
+    async fn countdown_heartbeat_drop_test() {
+        let server = mock_server::isolated();
+        let countdown = Duration::from_secs(60);
+        let interval = Duration::from_secs(20);
+
+        server
+            .run(async move {
+                let heartbeat = spawn_countdown_heartbeat("BTCUSDT", countdown, interval)
+                    .await
+                    .unwrap();
+                tokio::time::pause();
+                drop(heartbeat);
+                tokio::time::advance(countdown * 2).await;
+                tokio::time::resume();
+                // A request of our own, so a refresh sent by a task that is still alive reaches the server first.
+                open_orders(Some("BTCUSDT")).await.unwrap();
+            })
+            .await;
+
+        let requests = countdown_requests(&server);
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("countdownTime=60000"));
+    }
+}
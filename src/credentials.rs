@@ -0,0 +1,325 @@
+// credentials.rs - API Credentials
+
+// This file contains the loading and the storage of the credentials of the Binance API key.
+
+// The credentials are loaded once, in the first request that needs them, from one of the sources of
+// `CredentialSource`:
+// - File: A KEY=VALUE file (same keys as the environment) that only the owner can read (e.g., chmod 600).
+// - Command: The output (KEY=VALUE lines) of a command, e.g. the client of a secret manager.
+// - Env: The environment variables (and the .env file, read once).
+// The default source is chosen by BINANCE_CREDENTIALS_FILE or BINANCE_CREDENTIALS_COMMAND, falling back to the
+// environment.
+
+// Keys: BINANCE_API_KEY, BINANCE_SECRET_KEY (HMAC keys), BINANCE_KEY_TYPE and BINANCE_PRIVATE_KEY_PATH (Ed25519 and
+// RSA keys, see signer.rs).
+
+// Inside `accounts::with_account`, the credentials of the account are used instead (see accounts.rs).
+
+// The secrets are held in `Secret`, which is erased from memory when dropped and is never printed: its `Debug` and
+// `Display` show "***".
+
+use crate::signer::KeyType;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::process::Command;
+use std::sync::{Arc, Once, RwLock};
+use zeroize::Zeroizing;
+
+/// A secret value, erased from memory when dropped and redacted when printed.
+#[derive(Clone)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(Zeroizing::new(value))
+    }
+
+    /// Get the value of the secret. It must not be logged.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+/// Source of the credentials.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialSource {
+    /// KEY=VALUE file readable only by its owner.
+    File(String),
+    /// Command whose output has KEY=VALUE lines (run with `sh -c`).
+    Command(String),
+    /// Environment variables and .env file.
+    Env,
+}
+
+/// Credentials of the Binance API key.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub api_key: Secret,
+    /// Secret key of the HMAC API keys.
+    pub secret_key: Option<Secret>,
+    pub key_type: KeyType,
+    /// PEM file of the private key of the Ed25519 and RSA API keys.
+    pub private_key_path: Option<String>,
+}
+
+/// Reads the .env file into the environment, only in the first call.
+pub fn load_dotenv() {
+    static DOTENV: Once = Once::new();
+    DOTENV.call_once(|| {
+        dotenv::dotenv().ok();
+    });
+}
+
+impl Credentials {
+    /// Loads the credentials from a source.
+    pub fn load(source: &CredentialSource) -> Result<Self, String> {
+        match source {
+            CredentialSource::File(path) => Credentials::from_file(path),
+            CredentialSource::Command(command) => Credentials::from_command(command),
+            CredentialSource::Env => Credentials::from_env(),
+        }
+    }
+
+    /// Loads the credentials from the environment variables (and the .env file).
+    pub fn from_env() -> Result<Self, String> {
+        load_dotenv();
+        let mut values = HashMap::new();
+        for key in [
+            "BINANCE_API_KEY",
+            "BINANCE_SECRET_KEY",
+            "BINANCE_KEY_TYPE",
+            "BINANCE_PRIVATE_KEY_PATH",
+        ] {
+            if let Ok(value) = env::var(key) {
+                values.insert(key.to_string(), Zeroizing::new(value));
+            }
+        }
+        Credentials::from_values(values)
+    }
+
+    /// Loads the credentials from a KEY=VALUE file.
+    ///
+    /// The file is refused when the group or other users can access it (on unix systems).
+    ///
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(path)
+                .map_err(|e| format!("Could not read the credentials file {}: {}", path, e))?;
+            if metadata.permissions().mode() & 0o077 != 0 {
+                return Err(format!(
+                    "The credentials file {} can be read by other users, restrict it with chmod 600.",
+                    path
+                ));
+            }
+        }
+        let content = Zeroizing::new(
+            fs::read_to_string(path)
+                .map_err(|e| format!("Could not read the credentials file {}: {}", path, e))?,
+        );
+        Credentials::from_values(parse_values(&content))
+    }
+
+    /// Loads the credentials from the output of a command (e.g., the client of a secret manager).
+    pub fn from_command(command: &str) -> Result<Self, String> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .map_err(|e| format!("Could not run the credentials command: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "The credentials command failed with {}.",
+                output.status
+            ));
+        }
+        let content = Zeroizing::new(String::from_utf8_lossy(&output.stdout).into_owned());
+        Credentials::from_values(parse_values(&content))
+    }
+
+    fn from_values(mut values: HashMap<String, Zeroizing<String>>) -> Result<Self, String> {
+        let mut take = |key: &str| values.remove(key).map(|v| Secret::new(v.to_string()));
+
+        let api_key = take("BINANCE_API_KEY").ok_or("BINANCE_API_KEY is not set.")?;
+        let secret_key = take("BINANCE_SECRET_KEY");
+        let private_key_path = take("BINANCE_PRIVATE_KEY_PATH").map(|p| p.expose().to_string());
+        let key_type = match take("BINANCE_KEY_TYPE")
+            .map(|t| t.expose().to_uppercase())
+            .as_deref()
+        {
+            None | Some("HMAC") => KeyType::Hmac,
+            Some("ED25519") => KeyType::Ed25519,
+            Some("RSA") => KeyType::Rsa,
+            Some(other) => return Err(format!("Unknown BINANCE_KEY_TYPE: {}", other)),
+        };
+
+        if key_type == KeyType::Hmac && secret_key.is_none() {
+            return Err("BINANCE_SECRET_KEY is not set.".to_string());
+        }
+        if key_type != KeyType::Hmac && private_key_path.is_none() {
+            return Err("BINANCE_PRIVATE_KEY_PATH is not set.".to_string());
+        }
+
+        Ok(Credentials {
+            api_key,
+            secret_key,
+            key_type,
+            private_key_path,
+        })
+    }
+}
+
+/// Parses KEY=VALUE lines, ignoring empty lines and comments (#).
+fn parse_values(content: &str) -> HashMap<String, Zeroizing<String>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            (
+                key.trim().to_string(),
+                Zeroizing::new(value.trim().trim_matches('"').to_string()),
+            )
+        })
+        .collect()
+}
+
+/// Get the default source of the credentials (see the header of this file).
+pub fn default_source() -> CredentialSource {
+    load_dotenv();
+    if let Ok(path) = env::var("BINANCE_CREDENTIALS_FILE") {
+        CredentialSource::File(path)
+    } else if let Ok(command) = env::var("BINANCE_CREDENTIALS_COMMAND") {
+        CredentialSource::Command(command)
+    } else {
+        CredentialSource::Env
+    }
+}
+
+static CREDENTIALS: RwLock<Option<Arc<Credentials>>> = RwLock::new(None);
+
+/// Sets the credentials used by the requests, instead of the ones of the default source. The signer created from the
+/// previous credentials is dropped (see signer.rs).
+pub fn set_credentials(credentials: Credentials) {
+    *CREDENTIALS.write().unwrap() = Some(Arc::new(credentials));
+    crate::signer::reset_signer();
+}
+
+/// Get the credentials used by the requests: the ones of the current account (see accounts.rs), or the default ones,
+/// loaded from the default source in the first call.
+pub fn credentials() -> Result<Arc<Credentials>, String> {
+    if let Some(account) = crate::accounts::current_account() {
+        return Ok(account.credentials());
+    }
+    if let Some(credentials) = CREDENTIALS.read().unwrap().as_ref() {
+        return Ok(credentials.clone());
+    }
+    let credentials = Arc::new(Credentials::load(&default_source())?);
+    *CREDENTIALS.write().unwrap() = Some(credentials.clone());
+    Ok(credentials)
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::{signer_from_credentials, HmacSigner, Signer};
+    use tokio::test;
+
+    /// Test that the secrets are never printed.
+    #[test]
+    async fn credentials_debug_redacted_test() {
+        let values = parse_values("BINANCE_API_KEY=my-api-key\nBINANCE_SECRET_KEY=\"my-secret\"\n");
+        let credentials = Credentials::from_values(values).unwrap();
+        assert_eq!(
+            credentials.secret_key.as_ref().unwrap().expose(),
+            "my-secret"
+        );
+
+        let printed = format!("{:?} {}", credentials, credentials.api_key);
+        assert!(!printed.contains("my-api-key"));
+        assert!(!printed.contains("my-secret"));
+    }
+
+    /// Test loading the credentials from a file, that must be readable only by its owner.
+    #[cfg(unix)]
+    #[test]
+    async fn credentials_from_file_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join("quan_credentials_test.env");
+        fs::write(
+            &path,
+            "# Binance key\nBINANCE_API_KEY=file-key\nBINANCE_KEY_TYPE=ed25519\nBINANCE_PRIVATE_KEY_PATH=/keys/ed.pem\n",
+        )
+        .unwrap();
+        let path_str = path.to_str().unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(Credentials::from_file(path_str).is_err());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        let credentials = Credentials::from_file(path_str).unwrap();
+        assert_eq!(credentials.api_key.expose(), "file-key");
+        assert_eq!(credentials.key_type, KeyType::Ed25519);
+        assert_eq!(
+            credentials.private_key_path.as_deref(),
+            Some("/keys/ed.pem")
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    /// Test loading the credentials from the output of a command.
+    #[cfg(unix)]
+    #[test]
+    async fn credentials_from_command_test() {
+        let credentials = Credentials::load(&CredentialSource::Command(
+            "printf 'BINANCE_API_KEY=cmd-key\\nBINANCE_SECRET_KEY=cmd-secret\\n'".to_string(),
+        ))
+        .unwrap();
+        assert_eq!(credentials.api_key.expose(), "cmd-key");
+        assert_eq!(credentials.key_type, KeyType::Hmac);
+
+        assert!(Credentials::from_command("exit 1").is_err());
+    }
+
+    /// Test that the signer created from a set of credentials signs with its secret, so the signer of the previous
+    /// credentials is not used once they are swapped. The process-wide credentials are not changed, since the other
+    /// tests are signing requests in parallel.
+    #[test]
+    async fn signer_from_credentials_test() {
+        let payload = "symbol=BTCUSDT&timestamp=1591702613943";
+        let hmac_credentials = |secret: &str| {
+            let values = parse_values(&format!(
+                "BINANCE_API_KEY=swap-key\nBINANCE_SECRET_KEY={}\n",
+                secret
+            ));
+            Credentials::from_values(values).unwrap()
+        };
+
+        let first = signer_from_credentials(&hmac_credentials("first-secret"))
+            .unwrap()
+            .sign(payload);
+        let second = signer_from_credentials(&hmac_credentials("second-secret"))
+            .unwrap()
+            .sign(payload);
+        assert_ne!(first, second);
+        assert_eq!(second, HmacSigner::new("second-secret").sign(payload));
+    }
+}
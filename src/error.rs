@@ -18,10 +18,13 @@
 // it is possible to have a clear overview of the functionality and simplify code reuse.
 
 use crate::binance_orders;
+use crate::risk::RiskRejection;
+use crate::time_sync;
 use binance_orders::*;
 use reqwest::{header, Response, StatusCode};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
 
 pub const ORDER_WOULD_TRIGGER_IMMEDIATELY: &str = "E01: Order would immediately trigger.";
 pub const ERROR_NOT_MAPPED: &str = "E02: Error not mapped.";
@@ -31,6 +34,27 @@ pub const ERROR_NOTHING_TO_CLOSE: &str = "E05: ReduceOnly Order is rejected.";
 pub const NO_NEED_TO_CHANGE_PS: &str = "E06: No need to change position side.";
 pub const DNS_ERROR: &str = "E07: Dns error: No such host is known.";
 pub const RECVWINDOW_ERROR: &str = "E08: Timestamp for this request is outside of the recvWindow";
+pub const UNKNOWN_ORDER: &str = "E09: Unknown order sent.";
+pub const NO_MARKET_PRICE: &str = "E10: There is no market price for the symbol yet.";
+pub const ERROR_RATE_LIMITED: &str = "E11: Too many requests, the rate limit was exceeded.";
+pub const ERROR_IP_BANNED: &str =
+    "E12: The IP was banned by the exchange for breaking the rate limit.";
+pub const ERROR_SERVER_UNAVAILABLE: &str = "E13: Exchange server is unavailable.";
+pub const ERROR_MALFORMED_RESPONSE: &str = "E14: The exchange response is not a valid JSON.";
+pub const NO_NEED_TO_CHANGE_MARGIN_TYPE: &str = "E15: No need to change margin type.";
+pub const NO_NEED_TO_CHANGE_ASSETS_MODE: &str = "E16: No need to change multi-assets mode.";
+pub const POSITION_SIDE_MISMATCH: &str =
+    "E17: Order's position side does not match the position mode.";
+/// Prefix of the rejections of the risk checks, followed by the rule and the detail (see risk.rs).
+pub const RISK_CHECK_REJECTED: &str = "E18: Order rejected by the risk check";
+pub const SLIPPAGE_TOO_HIGH: &str =
+    "E19: The slippage of the market order is above the maximum of the slippage guard.";
+pub const UNKNOWN_ACCOUNT: &str = "E20: The account is not registered.";
+pub const INVALID_REQUEST_URL: &str = "E21: The url of the request is not valid.";
+pub const REDUCE_ONLY_SIDE_MISMATCH: &str =
+    "E22: The side of the reduce only order does not close its position side.";
+pub const UNKNOWN_SYMBOL: &str = "E23: The symbol is not listed by the exchange.";
+pub const CREDENTIALS_ERROR: &str = "E24: The credentials of the API key could not be loaded.";
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ResultResponseBinance {
@@ -53,7 +77,11 @@ pub struct ResultResponseBinance {
 /// - Orders that would immediately trigger.
 /// - 502 Bad Gateway errors.
 /// - Errors related to "ReduceOnly" orders.
-/// - Errors indicating that there is no need to change the position side.
+/// - Errors indicating that there is no need to change the position side, the margin type or the multi-assets mode.
+/// - Timestamps outside of the recvWindow, that synchronise the clock with the server again (see time_sync.rs).
+/// - Position sides that do not match the position mode, that clear the cached mode (see position_mode.rs).
+/// - Orders rejected by the risk checks (see risk.rs), whose message is returned unchanged.
+/// - HTTP 429 (rate limit), 418 (IP banned) and 5xx responses, that may not have a JSON body.
 ///
 /// If none of the specific error conditions are met, a generic error message is returned.
 ///
@@ -61,33 +89,152 @@ pub async fn error_handler(
     result: Response,
     _needed_parameters: Option<HashMap<String, String>>,
 ) -> String {
-    let result_string = &result.text().await.unwrap();
+    let status = result.status();
+    let result_string = &result.text().await.unwrap_or_default();
     //println!(" rs: {}", result_string);
-    let result_json: ResultResponseBinance = serde_json::from_str(result_string).unwrap();
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return ERROR_RATE_LIMITED.to_string();
+    } else if status == StatusCode::IM_A_TEAPOT {
+        return ERROR_IP_BANNED.to_string();
+    }
+
+    let result_json: ResultResponseBinance = match serde_json::from_str(result_string) {
+        Ok(result_json) => result_json,
+        Err(_) if status == StatusCode::BAD_GATEWAY => return ERROR_SERVER_502.to_string(),
+        Err(_) if status.is_server_error() => return ERROR_SERVER_UNAVAILABLE.to_string(),
+        Err(_) => {
+            tracing::warn!(response = %result_string, "Order: an error occurred");
+            return ERROR_MALFORMED_RESPONSE.to_string();
+        }
+    };
     //println!("Order: result text {}", result_string);
 
     if result_json.msg == "Order would immediately trigger." {
         ORDER_WOULD_TRIGGER_IMMEDIATELY.to_string()
     } else if result_json.msg.contains("502 Bad Gateway") {
-        println!("Order: an error occurred: {:?}", result_string);
+        tracing::warn!(response = %result_string, "Order: an error occurred");
         ERROR_SERVER_502.to_string()
     } else if result_json.msg.contains("ReduceOnly Order is rejected") {
         ERROR_NOTHING_TO_CLOSE.to_string()
     } else if result_json.msg.contains("No need to change position side") {
         NO_NEED_TO_CHANGE_PS.to_string()
+    } else if result_json.msg.contains("No need to change margin type") {
+        NO_NEED_TO_CHANGE_MARGIN_TYPE.to_string()
+    } else if result_json.msg.contains("does not need to be adjusted") {
+        NO_NEED_TO_CHANGE_ASSETS_MODE.to_string()
+    } else if result_json.msg.contains("Unknown order sent.") {
+        UNKNOWN_ORDER.to_string()
     } else if result_json.msg.contains("No such host is known.") {
         DNS_ERROR.to_string()
     } else if result_json
         .msg
         .contains("Timestamp for this request is outside of the recvWindow.")
     {
+        // The local clock drifted away from the server one: synchronise it before the request is retried.
+        let _ = time_sync::sync_server_time().await;
         RECVWINDOW_ERROR.to_string()
+    } else if result_json.msg == INVALID_REQUEST_URL || result_json.msg == CREDENTIALS_ERROR {
+        // The request was not sent (see `send_request` and `get_client`).
+        result_json.msg
+    } else if result_json.msg.starts_with(RISK_CHECK_REJECTED) {
+        // Rejected by the risk checks before it was sent: the message names the rule.
+        result_json.msg
+    } else if result_json.msg.contains("position side does not match") {
+        // The mode was changed outside of the crate: read it again before the order is retried.
+        crate::position_mode::invalidate_position_mode().await;
+        POSITION_SIDE_MISMATCH.to_string()
     } else {
-        println!("Order: an error occurred: {:?}", result_string);
+        tracing::warn!(response = %result_string, "Order: an error occurred");
         ERROR_NOT_MAPPED.to_string()
     }
 }
 
+/// Checks if an error means that the exchange can not be used right now (server errors, rate limits or
+/// unreadable responses).
+///
+/// These errors are returned to the caller instead of stopping the program, so it can wait and try again.
+///
+pub fn is_exchange_unavailable(error: &str) -> bool {
+    error == ERROR_SERVER_502
+        || error == ERROR_RATE_LIMITED
+        || error == ERROR_IP_BANNED
+        || error == ERROR_SERVER_UNAVAILABLE
+        || error == ERROR_MALFORMED_RESPONSE
+}
+
+/// Checks if an error is a rejection of the risk checks (see risk.rs).
+///
+/// These errors are returned to the caller instead of stopping the program: the order was not sent.
+///
+pub fn is_risk_rejection(error: &str) -> bool {
+    error.starts_with(RISK_CHECK_REJECTED)
+}
+
+/// Error of the order functions that return a `Result` (e.g., `place_order`).
+///
+/// It compares equal to the constants of this file (`error == ERROR_NOTHING_TO_CLOSE`) and converts into the
+/// `String` errors of the other functions, so it can be returned with `?`.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderError {
+    /// The order was rejected by the risk checks (see risk.rs) and was not sent.
+    Rejected(RiskRejection),
+    /// The mapped error of the exchange or of the crate (one of the constants of this file).
+    Exchange(String),
+}
+
+impl OrderError {
+    /// Get the rejection of the risk checks, or None when the order was not rejected by them.
+    pub fn rejection(&self) -> Option<&RiskRejection> {
+        match self {
+            OrderError::Rejected(rejection) => Some(rejection),
+            OrderError::Exchange(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::Rejected(rejection) => write!(f, "{}", rejection),
+            OrderError::Exchange(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+impl From<RiskRejection> for OrderError {
+    fn from(rejection: RiskRejection) -> Self {
+        OrderError::Rejected(rejection)
+    }
+}
+
+impl From<String> for OrderError {
+    fn from(error: String) -> Self {
+        OrderError::Exchange(error)
+    }
+}
+
+impl From<&str> for OrderError {
+    fn from(error: &str) -> Self {
+        OrderError::Exchange(error.to_string())
+    }
+}
+
+impl From<OrderError> for String {
+    fn from(error: OrderError) -> Self {
+        error.to_string()
+    }
+}
+
+impl PartialEq<&str> for OrderError {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, OrderError::Exchange(error) if error == other)
+    }
+}
+
 //Functions tests
 #[cfg(test)]
 mod tests {
@@ -164,4 +311,28 @@ mod tests {
             "E05: ReduceOnly Order is rejected."
         );
     }
+
+    /// Test handling HTTP errors without a JSON body.
+    ///
+    /// This test function simulates 429, 418, 502 and 503 responses with plain text bodies and verifies that the
+    /// status is mapped without trying to read the body as JSON.
+    ///
+    #[test]
+    async fn test_error_handler_http_status() {
+        let cases = [
+            (429, ERROR_RATE_LIMITED),
+            (418, ERROR_IP_BANNED),
+            (502, ERROR_SERVER_502),
+            (503, ERROR_SERVER_UNAVAILABLE),
+            (400, ERROR_MALFORMED_RESPONSE),
+        ];
+        for (status, expected) in cases {
+            let mut http_response = http::Response::new("<html>error</html>");
+            *http_response.status_mut() = StatusCode::from_u16(status).unwrap();
+            let response: Response = Response::from(http_response);
+
+            assert_eq!(error_handler(response, None).await, expected);
+            assert!(is_exchange_unavailable(expected));
+        }
+    }
 }
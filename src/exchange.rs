@@ -0,0 +1,204 @@
+// exchange.rs - Exchange Abstraction
+
+// This file contains the `Exchange` trait, the common interface that the strategies use to talk with a trading venue,
+// and its implementation for the Binance futures exchange.
+
+// Implementations:
+// - BinanceExchange: Sends every call to the Binance futures api (functions of binance_orders.rs and get_candles.rs).
+// - PaperExchange (paper.rs): Simulates the orders against live market data.
+// - BacktestExchange (backtest.rs): Simulates the orders against historical candles.
+
+// By writing the strategies against the `Exchange` trait instead of calling the free functions directly, the same
+// strategy code can run unchanged in live trading, paper trading and backtests.
+
+use crate::binance_orders;
+use crate::get_candles;
+use crate::models::{Balance, KlineData, OpenOrder, OrderAck, OrderRequest, PositionRisk};
+use crate::paper::{PaperExchange, PriceSource};
+use crate::simulator::SimulatorConfig;
+use async_trait::async_trait;
+use binance_orders::{
+    amend_order, balance_info, cancel_order, open_orders, place_order, position_risk,
+};
+use get_candles::get_klines;
+use std::env;
+
+/// Common interface of a trading venue.
+///
+/// Every method returns the same errors (`Err(String)` with the constants of error.rs) regardless of the
+/// implementation, so the error handling of the strategies does not depend on the venue.
+///
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    /// Places a new order.
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderAck, String>;
+
+    /// Cancels a resting order.
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<OrderAck, String>;
+
+    /// Modifies the price and quantity of a resting limit order.
+    async fn amend_order(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        quantity: f64,
+        price: f64,
+    ) -> Result<OrderAck, String>;
+
+    /// Lists the resting orders of a symbol.
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<OpenOrder>, String>;
+
+    /// Lists the positions of a symbol (one entry per position side).
+    async fn positions(&self, symbol: &str) -> Result<Vec<PositionRisk>, String>;
+
+    /// Lists the balances of the wallet.
+    async fn balances(&self) -> Result<Vec<Balance>, String>;
+
+    /// Gets the last `limit` candles of a symbol, from the oldest to the newest.
+    async fn candles(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: usize,
+    ) -> Result<Vec<KlineData>, String>;
+}
+
+/// `Exchange` implementation that trades in the Binance futures exchange.
+#[derive(Debug, Default, Clone)]
+pub struct BinanceExchange;
+
+impl BinanceExchange {
+    pub fn new() -> Self {
+        BinanceExchange
+    }
+}
+
+#[async_trait]
+impl Exchange for BinanceExchange {
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderAck, String> {
+        Ok(place_order(order).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<OrderAck, String> {
+        cancel_order(symbol, order_id).await
+    }
+
+    async fn amend_order(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        quantity: f64,
+        price: f64,
+    ) -> Result<OrderAck, String> {
+        // The exchange requires the side of the order to modify it.
+        let order = self
+            .open_orders(symbol)
+            .await?
+            .into_iter()
+            .find(|o| o.order_id == order_id)
+            .ok_or_else(|| format!("Order {} is not open.", order_id))?;
+
+        Ok(amend_order(symbol, order_id, order.side, quantity, price).await?)
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<OpenOrder>, String> {
+        open_orders(Some(symbol)).await
+    }
+
+    async fn positions(&self, symbol: &str) -> Result<Vec<PositionRisk>, String> {
+        position_risk(Some(symbol)).await
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>, String> {
+        balance_info().await
+    }
+
+    async fn candles(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: usize,
+    ) -> Result<Vec<KlineData>, String> {
+        get_klines(symbol, interval, limit).await
+    }
+}
+
+/// Get the exchange selected by the `TRADING_MODE` environment variable.
+///
+/// - "paper": a `PaperExchange` driven by the best bid/ask of the exchange.
+/// - anything else (or not set): the `BinanceExchange`, that sends real orders.
+///
+pub fn exchange_from_env() -> Box<dyn Exchange> {
+    match env::var("TRADING_MODE") {
+        Ok(mode) if mode == "paper" => Box::new(PaperExchange::new(
+            SimulatorConfig::default(),
+            PriceSource::BookTicker,
+        )),
+        _ => Box::new(BinanceExchange::new()),
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server;
+    use crate::models::{OrderType, PositionSide, Side};
+    use tokio::test;
+
+    /// Test getting candles through the `Exchange` trait.
+    ///
+    /// This test verifies that `BinanceExchange` returns the requested number of candles, ordered in time.
+    ///
+    #[test]
+    async fn binance_exchange_candles_test() {
+        mock_server::shared();
+        let exchange: Box<dyn Exchange> = Box::new(BinanceExchange::new());
+        let res = exchange.candles("BTCUSDT", "1m", 5).await;
+        assert!(res.is_ok());
+
+        let candles = res.unwrap();
+        assert_eq!(candles.len(), 5);
+        for pair in candles.windows(2) {
+            assert!(pair[1].open_time > pair[0].open_time);
+        }
+    }
+
+    /// Test that the open orders are read for the requested symbol.
+    #[test]
+    async fn binance_exchange_open_orders_test() {
+        let server = mock_server::isolated();
+        server.set_price(2000.0);
+        let exchange = BinanceExchange::new();
+        let order = OrderRequest {
+            symbol: "ETHUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            quantity: 0.1,
+            price: Some(1900.0),
+            stop_price: None,
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+
+        let (eth, btc) = server
+            .run(async {
+                exchange.place_order(&order).await.unwrap();
+                (
+                    exchange.open_orders("ETHUSDT").await.unwrap(),
+                    exchange.open_orders("BTCUSDT").await.unwrap(),
+                )
+            })
+            .await;
+
+        assert_eq!(eth.len(), 1);
+        assert_eq!(eth[0].symbol, "ETHUSDT");
+        assert!(btc.is_empty());
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.starts_with("GET /fapi/v1/openOrders?symbol=ETHUSDT&")));
+    }
+}
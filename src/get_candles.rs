@@ -20,9 +20,10 @@ use std::collections::hash_map;
 use std::io::Error;
 use std::time::{Duration, Instant};
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+use crate::binance_orders;
 use crate::error;
-use crate::{binance_orders, re_send_request};
-use binance_orders::{exchange_url, get_client, get_signature, get_timestamp};
+use crate::request::BinanceRequest;
+
 use error::*;
 use hmac::{Hmac, Mac, NewMac};
 use reqwest::{header, StatusCode};
@@ -45,40 +46,31 @@ pub async fn get_candle_last_min() -> Result<f64, String> {
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - 2 * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1m&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1m")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data.iter().rev().take(2).map(|f| f.close).collect();
         let last_closed_price: f64 = price_data[1];
         Ok(last_closed_price)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_candle_last_min().await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -102,27 +94,18 @@ pub async fn get_some_1m_candle(quantity: i64) -> Result<BTreeMap<i64, f64>, Str
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((quantity + 1) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1m&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1m")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data
             .iter()
             .take(quantity as usize)
@@ -146,14 +129,14 @@ pub async fn get_some_1m_candle(quantity: i64) -> Result<BTreeMap<i64, f64>, Str
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_1m_candle(quantity).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -227,7 +210,7 @@ pub async fn get_candle_info(
         Ok(candles)
     } else {
         // Handle the error from retrieving the 1-hour candle data
-        eprintln!("Failed to retrieve candles: {:?}", candle_1m_result);
+        tracing::error!(result = ?candle_1m_result, "Failed to retrieve candles");
         Err("Failed to retrieve candles".to_string())
     }
 }
@@ -273,29 +256,18 @@ pub async fn get_some_candles_from_binance(
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((one_min_quantity + 1) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!(
-        "symbol=BTCUSDT&interval={}&startTime={}&endTime={}",
-        interval, start_time, time_now
-    );
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", interval)
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data
             .iter()
             .take(quantity as usize)
@@ -318,14 +290,14 @@ pub async fn get_some_candles_from_binance(
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_candles_from_binance(quantity, interval).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -422,27 +394,18 @@ pub async fn get_some_1hr_candle(quantity: i64) -> Result<BTreeMap<i64, f64>, St
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((quantity * 60) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1h&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1h")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data
             .iter()
             .take(quantity as usize)
@@ -468,23 +431,82 @@ pub async fn get_some_1hr_candle(quantity: i64) -> Result<BTreeMap<i64, f64>, St
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_1hr_candle(quantity).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
 }
 
+/// Get the last 'limit' candles of a trading pair, with all their fields.
+///
+/// This function retrieves the raw candles of any trading pair and Binance interval. It is used by the
+/// `Exchange` implementations, that need the whole candle and not only one price of it.
+///
+/// # Arguments
+///
+/// - `symbol`: The trading pair symbol (e.g., "BTCUSDT").
+/// - `interval`: The candle interval as a string (e.g., "1h").
+/// - `limit`: The number of candles to retrieve (max 1500).
+///
+/// # Returns
+///
+/// - `Ok(Vec<KlineData>)`: The candles ordered from the oldest to the newest.
+/// - `Err(String)`: An error message if the request fails. A request rejected with E07 or E08 is sent again, at most
+///   `MAX_SEND_ATTEMPTS` times in total.
+///
+pub async fn get_klines(
+    symbol: &str,
+    interval: &str,
+    limit: usize,
+) -> Result<Vec<KlineData>, String> {
+    get_klines_attempt(symbol, interval, limit, 1).await
+}
+
+#[async_recursion]
+async fn get_klines_attempt(
+    symbol: &str,
+    interval: &str,
+    limit: usize,
+    attempt: u32,
+) -> Result<Vec<KlineData>, String> {
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", symbol)
+        .param("interval", interval)
+        .param("limit", limit)
+        .send()
+        .await;
+    if result.status() == StatusCode::OK {
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        Ok(data)
+    } else {
+        let error = error_handler(result, None).await;
+        if (error == "E07: Dns error: No such host is known."
+            || error == "E08: Timestamp for this request is outside of the recvWindow")
+            && attempt < binance_orders::MAX_SEND_ATTEMPTS
+        {
+            tokio::time::sleep(binance_orders::resend_backoff(attempt + 1)).await;
+            get_klines_attempt(symbol, interval, limit, attempt + 1).await
+        } else {
+            Err(error)
+        }
+    }
+}
+
 //Functions tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server;
     use tokio::test;
 
     /// Test function for the `get_candle_last_min` function.
@@ -494,6 +516,7 @@ mod tests {
     ///
     #[test]
     async fn get_candle_last_min_test() {
+        mock_server::shared();
         let res = get_candle_last_min().await;
         assert!(res.is_ok());
         let res_unwrapped = res.unwrap();
@@ -508,6 +531,7 @@ mod tests {
     ///
     #[test]
     async fn get_some_1m_candle_test() {
+        mock_server::shared();
         let res = get_some_1m_candle(10).await;
         assert!(res.is_ok());
 
@@ -537,6 +561,7 @@ mod tests {
     ///
     #[test]
     async fn get_candle_info_test() {
+        mock_server::shared();
         let res = get_candle_info(7, "BTCUSDT", "30m".to_string()).await;
         assert!(res.is_ok());
 
@@ -559,6 +584,7 @@ mod tests {
     ///
     #[test]
     async fn get_candle_info_hours_test() {
+        mock_server::shared();
         let res = get_candle_info(7, "BTCUSDT", "1h".to_string()).await;
         assert!(res.is_ok());
 
@@ -581,6 +607,7 @@ mod tests {
     ///
     #[test]
     async fn get_some_candles_from_binance_hours_test() {
+        mock_server::shared();
         let res = get_some_candles_from_binance(7, "1h").await;
         assert!(res.is_ok());
 
@@ -608,6 +635,7 @@ mod tests {
     ///
     #[test]
     async fn get_some_candles_from_binance_minutes_test() {
+        mock_server::shared();
         let res = get_some_candles_from_binance(7, "30m").await;
         assert!(res.is_ok());
 
@@ -22,6 +22,7 @@ use std::time::{Duration, Instant};
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 use crate::binance_orders::*;
 use crate::error;
+use crate::request::BinanceRequest;
 use error::*;
 use hmac::{Hmac, Mac, NewMac};
 use reqwest::{header, StatusCode};
@@ -44,41 +45,32 @@ pub async fn get_candle_last_minute_max_value() -> Result<f64, String> {
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - 2 * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1m&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1m")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
 
         let price_data: Vec<f64> = data.iter().take(1).map(|f| f.high).collect();
         let last_closed_price: f64 = price_data[0];
         Ok(last_closed_price)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_candle_last_minute_max_value().await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -101,27 +93,19 @@ pub async fn get_some_1m_candle_max_value(quantity: i64) -> Result<BTreeMap<i64,
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((quantity + 1) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1m&startTime={}&endTime={}&limit=1500",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1m")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .param("limit", 1500)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data
             .iter()
             .take(quantity as usize)
@@ -145,14 +129,14 @@ pub async fn get_some_1m_candle_max_value(quantity: i64) -> Result<BTreeMap<i64,
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_1m_candle_max_value(quantity).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -237,7 +221,7 @@ pub async fn get_candle_info_max_value(
         Ok(candles)
     } else {
         // Handle the error from retrieving the 1-hour candle data
-        eprintln!("Failed to retrieve candles: {:?}", candle_1m_result);
+        tracing::error!(result = ?candle_1m_result, "Failed to retrieve candles");
         Err("Failed to retrieve candles".to_string())
     }
 }
@@ -283,29 +267,18 @@ pub async fn get_some_candles_from_binance_max_value(
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((one_min_quantity) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!(
-        "symbol=BTCUSDT&interval={}&startTime={}&endTime={}",
-        interval, start_time, time_now
-    );
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", interval)
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data
             .iter()
             .take(quantity as usize)
@@ -328,14 +301,14 @@ pub async fn get_some_candles_from_binance_max_value(
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_candles_from_binance_max_value(quantity, interval).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -418,7 +391,7 @@ pub async fn build_candle_w_1hr_max_price(
         Ok(candles)
     } else {
         // Handle the error from retrieving the 1-hour candle data
-        eprintln!("Failed to retrieve 1-hour candles: {:?}", candle_1m_result);
+        tracing::error!(result = ?candle_1m_result, "Failed to retrieve 1-hour candles");
         Err("Failed to retrieve 1-hour candles".to_string())
     }
 }
@@ -440,27 +413,18 @@ pub async fn get_some_1hr_candle_max_value(quantity: i64) -> Result<BTreeMap<i64
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((quantity) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1h&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1h")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data
             .iter()
             .take(quantity as usize)
@@ -486,14 +450,14 @@ pub async fn get_some_1hr_candle_max_value(quantity: i64) -> Result<BTreeMap<i64
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_1hr_candle_max_value(quantity).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -529,6 +493,7 @@ pub async fn get_biggest_candle(quantity: i64, interval: &str) -> f64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server;
     use tokio::test;
 
     /// Test the `get_candle_last_minute_max_value` function.
@@ -536,6 +501,7 @@ mod tests {
     /// This test verifies that the `get_candle_last_minute_max_value` function returns a result with a maximum value greater than 0.0.
     #[test]
     async fn get_candle_last_minute_max_value_test() {
+        mock_server::shared();
         let res = get_candle_last_minute_max_value().await;
         assert!(res.is_ok());
         let res_unwrapped = res.unwrap();
@@ -547,6 +513,7 @@ mod tests {
     /// This test verifies that the `get_some_1m_candle_max_value` function returns a valid result with the correct number of candles and ordinate timestamps.
     #[test]
     async fn get_some_1m_candle_max_value_test() {
+        mock_server::shared();
         let res = get_some_1m_candle_max_value(10).await;
         assert!(res.is_ok());
 
@@ -573,6 +540,7 @@ mod tests {
     /// This test verifies that the `get_candle_info_max_value` function returns a valid result with the correct number of candles and valid values for a minutes interval.
     #[test]
     async fn get_candle_info_max_value_minutes_test() {
+        mock_server::shared();
         let res = get_candle_info_max_value(7, "BTCUSDT", "30m".to_string()).await;
         assert!(res.is_ok());
 
@@ -592,6 +560,7 @@ mod tests {
     /// This test verifies that the `get_candle_info_max_value` function returns a valid result with the correct number of candles and valid values for an hours interval.
     #[test]
     async fn get_candle_info_max_value_hours_test() {
+        mock_server::shared();
         let res = get_candle_info_max_value(7, "BTCUSDT", "1h".to_string()).await;
         assert!(res.is_ok());
 
@@ -611,6 +580,7 @@ mod tests {
     /// This test verifies that the `get_some_candles_from_binance_max_value` function returns a valid result with the correct number of candles and ordinate timestamps for an hours interval.
     #[test]
     async fn get_some_candles_from_binance_max_value_hours_test() {
+        mock_server::shared();
         let res = get_some_candles_from_binance_max_value(7, "1h").await;
         assert!(res.is_ok());
 
@@ -635,6 +605,7 @@ mod tests {
     /// This test verifies that the `get_some_candles_from_binance_max_value` function returns a valid result with the correct number of candles, valid values, and ordinate timestamps for a minutes interval.
     #[test]
     async fn get_some_candles_from_binance_max_value_minutes_test() {
+        mock_server::shared();
         let res = get_some_candles_from_binance_max_value(7, "30m").await;
         assert!(res.is_ok());
 
@@ -660,6 +631,7 @@ mod tests {
     /// This test verifies that the `build_candle_w_1hr_max_price` function returns a valid result with the correct number of candles and valid values for a custom interval.
     #[tokio::test]
     async fn test_build_candle_w_1hr_max_price() {
+        mock_server::shared();
         // Chame a função que você está testando
         let result = build_candle_w_1hr_max_price(16, "BTCUSDT", "3h".to_string()).await;
 
@@ -684,6 +656,7 @@ mod tests {
     /// This test verifies that the `get_biggest_candle` function returns a maximum value greater than 0.0 for a specified quantity and interval.
     #[test]
     async fn get_biggest_candle_test() {
+        mock_server::shared();
         let res: f64 = get_biggest_candle(3, "30m").await;
         assert!(res > 0.0);
     }
@@ -20,8 +20,9 @@ use std::collections::hash_map;
 use std::io::Error;
 use std::time::{Duration, Instant};
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+use crate::request::BinanceRequest;
 use crate::*;
-use binance_orders::{exchange_url, get_client, get_signature, get_timestamp};
+
 use error::*;
 use hmac::{Hmac, Mac, NewMac};
 use reqwest::{header, StatusCode};
@@ -44,40 +45,31 @@ pub async fn get_candle_last_min_min_value() -> Result<f64, String> {
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - 2 * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1m&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1m")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data.iter().take(1).map(|f| f.low).collect();
         let last_closed_price: f64 = price_data[0];
         Ok(last_closed_price)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_candle_last_min_min_value().await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -101,44 +93,29 @@ pub async fn get_some_1m_candle_min_value(quantity: i64) -> Result<BTreeMap<i64,
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((quantity + 1) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1m&startTime={}&endTime={}&limit=1500",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => {
-            response
-            // if verify_response_body(response).await.is_ok() {
-            //     response
-            // } else {
-            //     re_send_request(client, request, "GET").await
-            // }
-        }
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1m")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .param("limit", 1500)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
-        println!("data len: {}", data.len());
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        tracing::debug!(len = data.len(), "Length of data");
         let price_data: Vec<f64> = data.iter().take(quantity as usize).map(|f| f.low).collect();
-        println!("price_data len: {}", price_data.len());
+        tracing::debug!(len = price_data.len(), "Length of price_data");
 
         let date_data: Vec<i64> = data
             .iter()
             .take(quantity as usize)
             .map(|f| f.open_time)
             .collect();
-        println!("date_data len: {}", date_data.len());
+        tracing::debug!(len = date_data.len(), "Length of date_data");
 
         //let mut info_data: HashMap::new();
         let mut info_data: BTreeMap<i64, f64> = BTreeMap::new();
@@ -153,14 +130,14 @@ pub async fn get_some_1m_candle_min_value(quantity: i64) -> Result<BTreeMap<i64,
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_1m_candle_min_value(quantity).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -204,7 +181,7 @@ pub async fn get_candle_info_min_value(
         //if the interval is not valid, the number of candles requested will be "quantity".
         panic!("get_candle_info: Interval not implemented.");
     }
-    println!("quantity: {}", one_min_quantity);
+    tracing::debug!(quantity = one_min_quantity, "Quantity of 1 minute candles");
 
     // Getting exchange candles
     let candle_1m_result = get_some_1m_candle_min_value(one_min_quantity).await;
@@ -260,7 +237,7 @@ pub async fn get_candle_info_min_value(
         Ok(candles)
     } else {
         // Handle the error from retrieving the 1-hour candle data
-        eprintln!("Failed to retrieve  candles: {:?}", candle_1m_result);
+        tracing::error!(result = ?candle_1m_result, "Failed to retrieve candles");
         Err("Failed to retrieve 1-hour candles".to_string())
     }
 }
@@ -306,29 +283,18 @@ pub async fn get_some_candles_from_binance_min_value(
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((one_min_quantity) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-
-    let params = format!(
-        "symbol=BTCUSDT&interval={}&startTime={}&endTime={}",
-        interval, start_time, time_now
-    );
-
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", interval)
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data.iter().take(quantity as usize).map(|f| f.low).collect();
 
         //price_data.pop();
@@ -350,14 +316,14 @@ pub async fn get_some_candles_from_binance_min_value(
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_candles_from_binance_min_value(quantity, interval).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -381,27 +347,18 @@ pub async fn get_some_1hr_candle_min_value(quantity: i64) -> Result<BTreeMap<i64
     let time_now = Utc::now().timestamp_millis() as u64;
     let start_time = time_now - ((quantity) as u64) * ONE_MIN_IN_MILLISECONDS;
 
-    let client: reqwest::Client = get_client().await;
-    let timestamp = get_timestamp(SystemTime::now()).await;
-    let params = format!(
-        "symbol=BTCUSDT&interval=1h&startTime={}&endTime={}",
-        start_time, time_now
-    );
-    let signature = get_signature(params.clone()).await;
-
-    let request = format!(
-        "{}/fapi/v1/klines?{}&signature={}",
-        exchange_url().await,
-        params.clone(),
-        signature.clone()
-    );
-
-    let result = match client.get(request.clone()).send().await {
-        Ok(response) => response,
-        Err(_) => re_send_request(client, request, "GET").await,
-    };
+    let result = BinanceRequest::get("/fapi/v1/klines")
+        .param("symbol", "BTCUSDT")
+        .param("interval", "1h")
+        .param("startTime", start_time)
+        .param("endTime", time_now)
+        .send()
+        .await;
     if result.status() == StatusCode::OK {
-        let data: Vec<KlineData> = result.json().await.unwrap();
+        let data: Vec<KlineData> = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
         let price_data: Vec<f64> = data.iter().take(quantity as usize).map(|f| f.low).collect();
         //price_data.pop();
 
@@ -424,14 +381,14 @@ pub async fn get_some_1hr_candle_min_value(quantity: i64) -> Result<BTreeMap<i64
         Ok(info_data)
     } else {
         let error = error_handler(result, None).await;
-        if error == "E03: Error 502, exchange server is in trouble." {
+        if is_exchange_unavailable(&error) {
             Err(error)
         } else if error == "E07: Dns error: No such host is known."
             || error == "E08: Timestamp for this request is outside of the recvWindow"
         {
             get_some_1hr_candle_min_value(quantity).await
         } else {
-            println!("{}", error);
+            tracing::error!(%error, "Unexpected error in the candles request.");
             std::process::exit(1);
         }
     }
@@ -512,7 +469,7 @@ pub async fn build_candle_w_1hr_min_price(
         Ok(candles)
     } else {
         // Handle the error from retrieving the 1-hour candle data
-        eprintln!("Failed to retrieve 1-hour candles: {:?}", candle_1m_result);
+        tracing::error!(result = ?candle_1m_result, "Failed to retrieve 1-hour candles");
         Err("Failed to retrieve 1-hour candles".to_string())
     }
 }
@@ -576,6 +533,7 @@ pub async fn get_lowest_candle(quantity: i64, interval: &str) -> f64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server;
     use tokio::test;
 
     /// Test for the `get_candle_last_minute_min_value` function.
@@ -588,6 +546,7 @@ mod tests {
     ///
     #[test]
     async fn get_candle_last_minute_min_value_test() {
+        mock_server::shared();
         let res = get_candle_last_min_min_value().await;
         assert!(res.is_ok());
 
@@ -607,6 +566,7 @@ mod tests {
     ///
     #[test]
     async fn get_some_1m_candle_min_value_test() {
+        mock_server::shared();
         let res = get_some_1m_candle_min_value(10).await;
         assert!(res.is_ok());
 
@@ -639,6 +599,7 @@ mod tests {
     ///
     #[test]
     async fn get_candle_info_min_value_minutes_test() {
+        mock_server::shared();
         let res = get_candle_info_min_value(7, "BTCUSDT", "6m".to_string()).await;
         assert!(res.is_ok());
 
@@ -664,6 +625,7 @@ mod tests {
     ///
     #[test]
     async fn get_candle_info_min_value_hours_test() {
+        mock_server::shared();
         let res = get_candle_info_min_value(7, "BTCUSDT", "1h".to_string()).await;
         assert!(res.is_ok());
 
@@ -689,6 +651,7 @@ mod tests {
     ///
     #[test]
     async fn get_some_candles_from_binance_min_value_hours_test() {
+        mock_server::shared();
         let res = get_some_candles_from_binance_min_value(7, "1h").await;
         assert!(res.is_ok());
 
@@ -720,6 +683,7 @@ mod tests {
     ///
     #[test]
     async fn get_some_candles_from_binance_min_value_minutes_test() {
+        mock_server::shared();
         let res = get_some_candles_from_binance_min_value(7, "30m").await;
         assert!(res.is_ok());
 
@@ -752,6 +716,7 @@ mod tests {
     ///
     #[test]
     async fn test_build_candle_w_1hr_min_price() {
+        mock_server::shared();
         // Chame a função que você está testando
         let result = build_candle_w_1hr_min_price(16, "BTCUSDT", "3h".to_string()).await;
 
@@ -780,6 +745,7 @@ mod tests {
     ///
     #[test]
     async fn get_lowest_candle_from_binance_candles_test() {
+        mock_server::shared();
         let res: f64 = get_lowest_candle_from_binance_candles(2, "3m").await;
         assert!(res > 0.0);
     }
@@ -789,12 +755,13 @@ mod tests {
     /// This test verifies that the function successfully retrieves the lowest price among a specified number of candles for a given interval.
     ///
     /// The test asserts that:
-    /// - The retrieved lowest price is greater than 0.0.
+    /// - The retrieved lowest price is a finite number greater than 0.0.
     ///
     #[test]
     async fn get_lowest_candle_building_candles_test() {
+        mock_server::shared();
         let res: f64 = get_lowest_candle(15, "6m").await;
-        println!("{}", res);
+        assert!(res.is_finite());
         assert!(res > 0.0);
     }
 }
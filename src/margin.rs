@@ -0,0 +1,244 @@
+// margin.rs - Leverage and Margin Settings
+
+// This file contains the functions that change the leverage and margin settings of the account, that otherwise have
+// to be set by hand in the Binance UI:
+// - POST /fapi/v1/leverage: leverage of a symbol.
+// - POST /fapi/v1/marginType: isolated or cross margin of a symbol.
+// - POST /fapi/v1/positionMargin: margin added to or removed from an isolated position.
+// - GET/POST /fapi/v1/multiAssetsMargin: multi-assets mode of the account.
+// - GET /fapi/v1/leverageBracket: notional brackets of the leverage of the symbols.
+
+// The exchange rejects a change to the current margin type or multi-assets mode ("No need to change"). Like the E06
+// error of the position side (see `activate_hedge_mode`), these replies (E15 and E16) mean that the account already
+// has the requested setting, so they are returned as a success.
+
+// The requests are sent with `BinanceRequest::fetch`, that reads the typed responses and sends the request again
+// after a DNS or recvWindow error.
+
+use crate::error::{
+    ERROR_MALFORMED_RESPONSE, NO_NEED_TO_CHANGE_ASSETS_MODE, NO_NEED_TO_CHANGE_MARGIN_TYPE,
+};
+use crate::models::{LeverageBrackets, LeverageResponse, PositionMarginResponse, PositionSide};
+use crate::request::BinanceRequest;
+use serde_json::Value;
+
+/// Margin type of a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarginType {
+    Isolated,
+    Crossed,
+}
+
+impl MarginType {
+    /// Get the Binance name of the margin type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MarginType::Isolated => "ISOLATED",
+            MarginType::Crossed => "CROSSED",
+        }
+    }
+}
+
+/// Change of the margin of an isolated position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionMarginAction {
+    Add,
+    Reduce,
+}
+
+/// Changes the leverage of a symbol.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT").
+/// * `leverage`: The new leverage (1 to 125, limited by the brackets of the symbol).
+///
+/// # Returns
+/// The leverage and the maximum notional value of the positions of the symbol, or the mapped error.
+///
+pub async fn set_leverage(symbol: &str, leverage: u32) -> Result<LeverageResponse, String> {
+    BinanceRequest::post("/fapi/v1/leverage")
+        .param("symbol", symbol)
+        .param("leverage", leverage)
+        .signed()
+        .fetch()
+        .await
+}
+
+/// Changes the margin type of a symbol.
+///
+/// # Returns
+/// Ok when the symbol has the margin type, including when it already had it (E15), or the mapped error.
+///
+pub async fn set_margin_type(symbol: &str, margin_type: MarginType) -> Result<(), String> {
+    let result: Result<Value, String> = BinanceRequest::post("/fapi/v1/marginType")
+        .param("symbol", symbol)
+        .param("marginType", margin_type.as_str())
+        .signed()
+        .fetch()
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) if error == NO_NEED_TO_CHANGE_MARGIN_TYPE => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Adds margin to or removes margin from an isolated position.
+///
+/// # Arguments
+/// * `symbol`: The trading symbol (e.g., "BTCUSDT").
+/// * `position_side`: The position side of the position (BOTH in one-way mode).
+/// * `amount`: The margin (in the margin asset) to add or remove.
+/// * `action`: Whether the margin is added or removed.
+///
+/// # Returns
+/// The response of the exchange, or the mapped error.
+///
+pub async fn modify_position_margin(
+    symbol: &str,
+    position_side: PositionSide,
+    amount: f64,
+    action: PositionMarginAction,
+) -> Result<PositionMarginResponse, String> {
+    BinanceRequest::post("/fapi/v1/positionMargin")
+        .param("symbol", symbol)
+        .param("positionSide", position_side)
+        .param("amount", amount)
+        .param(
+            "type",
+            match action {
+                PositionMarginAction::Add => 1,
+                PositionMarginAction::Reduce => 2,
+            },
+        )
+        .signed()
+        .fetch()
+        .await
+}
+
+/// Get the multi-assets mode of the account.
+///
+/// # Returns
+/// True when the account is in multi-assets mode, or the mapped error.
+///
+pub async fn multi_assets_margin() -> Result<bool, String> {
+    let data: Value = BinanceRequest::get("/fapi/v1/multiAssetsMargin")
+        .signed()
+        .fetch()
+        .await?;
+    data["multiAssetsMargin"]
+        .as_bool()
+        .ok_or_else(|| ERROR_MALFORMED_RESPONSE.to_string())
+}
+
+/// Changes the multi-assets mode of the account.
+///
+/// # Returns
+/// Ok when the account is in the mode, including when it already was (E16), or the mapped error.
+///
+pub async fn set_multi_assets_margin(multi_assets_margin: bool) -> Result<(), String> {
+    let result: Result<Value, String> = BinanceRequest::post("/fapi/v1/multiAssetsMargin")
+        .param("multiAssetsMargin", multi_assets_margin)
+        .signed()
+        .fetch()
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(error) if error == NO_NEED_TO_CHANGE_ASSETS_MODE => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Get the notional brackets of the leverage of a symbol, or of every symbol.
+///
+/// # Returns
+/// One `LeverageBrackets` per symbol, or the mapped error.
+///
+pub async fn leverage_brackets(symbol: Option<&str>) -> Result<Vec<LeverageBrackets>, String> {
+    let data: Value = BinanceRequest::get("/fapi/v1/leverageBracket")
+        .param_opt("symbol", symbol)
+        .signed()
+        .fetch()
+        .await?;
+
+    // The exchange answers with an object when the symbol is sent and with an array otherwise.
+    let data = match data {
+        Value::Array(_) => data,
+        object => Value::Array(vec![object]),
+    };
+    serde_json::from_value(data).map_err(|_| ERROR_MALFORMED_RESPONSE.to_string())
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::{BinanceExchange, Exchange};
+    use crate::mock_server;
+    use tokio::test;
+
+    /// Test changing the leverage and the margin type, and that repeating a margin type change is a success.
+    #[test]
+    async fn leverage_and_margin_type_test() {
+        let server = mock_server::isolated();
+
+        let res = server.run(set_leverage("BTCUSDT", 10)).await.unwrap();
+        assert_eq!(res.leverage, 10);
+        assert!(server.run(set_leverage("BTCUSDT", 0)).await.is_err());
+
+        for _ in 0..2 {
+            server
+                .run(set_margin_type("BTCUSDT", MarginType::Isolated))
+                .await
+                .unwrap();
+        }
+        let positions = server
+            .run(BinanceExchange::new().positions("BTCUSDT"))
+            .await
+            .unwrap();
+        assert_eq!(positions[0].leverage, 10.0);
+        assert_eq!(positions[0].margin_type, "isolated");
+
+        let res = server
+            .run(modify_position_margin(
+                "BTCUSDT",
+                PositionSide::Both,
+                100.0,
+                PositionMarginAction::Add,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.amount, 100.0);
+        assert_eq!(res.action, 1);
+        assert!(server
+            .run(modify_position_margin(
+                "BTCUSDT",
+                PositionSide::Both,
+                200.0,
+                PositionMarginAction::Reduce,
+            ))
+            .await
+            .is_err());
+    }
+
+    /// Test the multi-assets mode and the leverage brackets.
+    #[test]
+    async fn multi_assets_margin_test() {
+        let server = mock_server::isolated();
+
+        assert!(!server.run(multi_assets_margin()).await.unwrap());
+        server.run(set_multi_assets_margin(true)).await.unwrap();
+        server.run(set_multi_assets_margin(true)).await.unwrap();
+        assert!(server.run(multi_assets_margin()).await.unwrap());
+
+        let brackets = server
+            .run(leverage_brackets(Some("BTCUSDT")))
+            .await
+            .unwrap();
+        assert_eq!(brackets.len(), 1);
+        assert_eq!(brackets[0].brackets[0].initial_leverage, 125);
+        assert_eq!(server.run(leverage_brackets(None)).await.unwrap().len(), 1);
+    }
+}
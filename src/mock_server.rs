@@ -0,0 +1,1490 @@
+// mock_server.rs - Local Mock of the Binance Futures Api
+
+// This file contains an in-process HTTP server that implements the endpoints of the Binance futures api used by this
+// crate, so the tests can run offline, without credentials and with deterministic results.
+
+// Endpoints:
+// - /fapi/v1/order (GET, POST, PUT, DELETE), /fapi/v1/allOpenOrders, /fapi/v1/openOrders
+// - /fapi/v2/positionRisk, /fapi/v2/balance, /fapi/v2/account, /fapi/v1/positionSide/dual (GET, POST)
+// - /fapi/v1/income, /fapi/v1/userTrades, /fapi/v1/allOrders, /fapi/v1/countdownCancelAll
+// - /fapi/v1/leverage, /fapi/v1/marginType, /fapi/v1/positionMargin, /fapi/v1/multiAssetsMargin (GET, POST),
+//   /fapi/v1/leverageBracket
+// - /fapi/v1/klines, /fapi/v1/ticker/price, /fapi/v1/ticker/bookTicker, /fapi/v1/premiumIndex, /fapi/v1/fundingRate
+// - /fapi/v1/exchangeInfo, /fapi/v1/ping, /fapi/v1/time
+
+// The state of the server (market price, orders, positions, position mode, leverage and margin type) can be scripted
+// by the tests. Orders are validated like in the exchange: a stop that would trigger immediately and a reduce only
+// order without position are rejected with the same codes and messages. Signed requests whose timestamp is outside of
+// the recvWindow of the server clock (which can be moved away from the local clock with `set_clock_offset`) are
+// rejected with -1021.
+
+// The responses carry the X-MBX-USED-WEIGHT-1M and X-MBX-ORDER-COUNT-1M headers, computed with the weight table of
+// rate_limiter.rs.
+
+// Fault injection: the next requests of an endpoint can be disturbed at the transport layer with `inject_fault`
+// (latency, connection reset, HTTP 418/429/5xx, malformed JSON bodies or Binance errors like -1021), so the retry
+// and fallback behaviour of the functions of the crate can be asserted.
+
+// Usage: `mock_server::shared()` starts one server for the whole test binary and points `exchange_url` to it (through
+// the RUST_TEST and TEST_API_URL environment variables), so the functions of the crate are tested unchanged. Tests
+// that inject faults should use their own server (`mock_server::isolated()`) and run inside `MockServer::run`, so
+// the faults do not reach the tests running in parallel.
+
+use crate::binance_orders::with_exchange_url;
+use crate::rate_limiter::{endpoint_weight, is_order_request};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Market price used by the server until a test changes it.
+pub const MOCK_DEFAULT_PRICE: f64 = 25000.0;
+/// Leverage of the symbols until it is changed.
+pub const MOCK_DEFAULT_LEVERAGE: u32 = 20;
+/// Commission rate of the LIMIT fills.
+pub const MOCK_MAKER_FEE_RATE: f64 = 0.0002;
+/// Commission rate of the other fills.
+pub const MOCK_TAKER_FEE_RATE: f64 = 0.0004;
+
+#[derive(Debug, Clone)]
+struct MockOrder {
+    order_id: u64,
+    client_order_id: String,
+    symbol: String,
+    side: String,
+    order_type: String,
+    price: String,
+    stop_price: String,
+    quantity: f64,
+    executed_qty: f64,
+    avg_price: f64,
+    position_side: String,
+    reduce_only: bool,
+    /// STOP_MARKET that closes the whole position when it triggers (closePosition=true).
+    close_position: bool,
+    working_type: String,
+    time_in_force: String,
+    status: String,
+    time: u64,
+    update_time: u64,
+}
+
+#[derive(Debug, Clone)]
+struct MockTrade {
+    id: u64,
+    order_id: u64,
+    symbol: String,
+    side: String,
+    position_side: String,
+    price: f64,
+    quantity: f64,
+    realized_pnl: f64,
+    commission: f64,
+    maker: bool,
+    time: u64,
+}
+
+#[derive(Debug, Clone)]
+struct MockIncome {
+    tran_id: u64,
+    symbol: String,
+    income_type: String,
+    income: f64,
+    trade_id: String,
+    time: u64,
+}
+
+/// Fault applied to one request of an endpoint.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Waits before handling the request normally.
+    Latency(Duration),
+    /// Closes the connection without answering.
+    ConnectionReset,
+    /// Answers with a raw HTTP status and body, optionally with a Retry-After header (in seconds).
+    HttpStatus {
+        status: u16,
+        body: String,
+        retry_after: Option<u64>,
+    },
+    /// Handles the request normally but answers with a truncated JSON body.
+    MalformedBody,
+    /// Answers with a Binance error (e.g., 400, -1021, "Timestamp for this request is outside of the recvWindow.").
+    BinanceError { status: u16, code: i64, msg: String },
+}
+
+#[derive(Debug)]
+struct MockState {
+    price: f64,
+    /// Mark price, that follows the market price until it is set.
+    mark_price: Option<f64>,
+    /// (symbol, funding rate, funding time), sorted by time.
+    funding_rates: Vec<(String, f64, u64)>,
+    dual_side_position: bool,
+    /// Server clock minus local clock, in milliseconds.
+    clock_offset: i64,
+    balance: f64,
+    orders: Vec<MockOrder>,
+    /// (symbol, position side) -> (signed amount, entry price)
+    positions: HashMap<(String, String), (f64, f64)>,
+    /// symbol -> leverage (MOCK_DEFAULT_LEVERAGE when not set)
+    leverage: HashMap<String, u32>,
+    /// Symbols in isolated margin (the others are in cross margin).
+    isolated: Vec<String>,
+    /// (symbol, position side) -> margin added to the isolated position
+    isolated_margin: HashMap<(String, String), f64>,
+    multi_assets_margin: bool,
+    trades: Vec<MockTrade>,
+    incomes: Vec<MockIncome>,
+    /// symbol -> time when its open orders are canceled (countdownCancelAll).
+    countdowns: HashMap<String, u64>,
+    faults: HashMap<String, VecDeque<Fault>>,
+    requests: Vec<String>,
+    /// X-MBX-APIKEY header of each request (empty when it was not sent).
+    api_keys: Vec<String>,
+    /// (minute, request weight used in the minute, orders sent in the minute)
+    usage: (u64, u32, u32),
+    next_order_id: u64,
+    /// Id of the next trade and of the next income (tranId).
+    next_trade_id: u64,
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        MockState {
+            price: MOCK_DEFAULT_PRICE,
+            mark_price: None,
+            funding_rates: Vec::new(),
+            dual_side_position: false,
+            clock_offset: 0,
+            balance: 10_000.0,
+            orders: Vec::new(),
+            positions: HashMap::new(),
+            leverage: HashMap::new(),
+            isolated: Vec::new(),
+            isolated_margin: HashMap::new(),
+            multi_assets_margin: false,
+            trades: Vec::new(),
+            incomes: Vec::new(),
+            countdowns: HashMap::new(),
+            faults: HashMap::new(),
+            requests: Vec::new(),
+            api_keys: Vec::new(),
+            usage: (0, 0, 0),
+            next_order_id: 1,
+            next_trade_id: 1,
+        }
+    }
+}
+
+/// Handle to a running mock server.
+#[derive(Clone)]
+pub struct MockServer {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+    /// Starts a new server in a background thread, listening in a random local port.
+    pub fn start() -> MockServer {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let server_state = state.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                loop {
+                    if let Ok((socket, _)) = listener.accept().await {
+                        tokio::spawn(handle_connection(socket, server_state.clone()));
+                    }
+                }
+            });
+        });
+
+        MockServer { url, state }
+    }
+
+    /// Changes the market price, triggering the resting orders reached by it.
+    pub fn set_price(&self, price: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.price = price;
+        trigger_orders(&mut state);
+    }
+
+    /// Sets the mark price, that no longer follows the market price.
+    pub fn set_mark_price(&self, mark_price: f64) {
+        self.state.lock().unwrap().mark_price = Some(mark_price);
+    }
+
+    /// Adds a funding rate to the funding history of a symbol.
+    pub fn add_funding_rate(&self, symbol: &str, funding_rate: f64, funding_time: u64) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .funding_rates
+            .push((symbol.to_string(), funding_rate, funding_time));
+        state.funding_rates.sort_by_key(|f| f.2);
+    }
+
+    /// Sets the position mode (hedge mode when `dual_side_position` is true).
+    pub fn set_dual_side_position(&self, dual_side_position: bool) {
+        self.state.lock().unwrap().dual_side_position = dual_side_position;
+    }
+
+    /// Moves the server clock away from the local clock (positive values put the server ahead).
+    pub fn set_clock_offset(&self, offset_ms: i64) {
+        self.state.lock().unwrap().clock_offset = offset_ms;
+    }
+
+    /// Sets the position of a symbol and side (signed amount: negative for short).
+    pub fn set_position(&self, symbol: &str, position_side: &str, amount: f64, entry_price: f64) {
+        self.state.lock().unwrap().positions.insert(
+            (symbol.to_string(), position_side.to_string()),
+            (amount, entry_price),
+        );
+    }
+
+    /// Adds an income to the history of the account (e.g., a FUNDING_FEE), that also changes the balance.
+    pub fn add_income(&self, symbol: &str, income_type: &str, income: f64, time: u64) {
+        let mut state = self.state.lock().unwrap();
+        add_income(&mut state, symbol, income_type, income, String::new(), time);
+    }
+
+    /// Applies a fault to the next request to `path`. Faults of the same path are applied in order, one per request.
+    ///
+    /// # Arguments
+    /// * `path`: The endpoint (e.g., "/fapi/v1/order").
+    /// * `fault`: The fault that will be applied.
+    ///
+    pub fn inject_fault(&self, path: &str, fault: Fault) {
+        self.state
+            .lock()
+            .unwrap()
+            .faults
+            .entry(path.to_string())
+            .or_default()
+            .push_back(fault);
+    }
+
+    /// Makes the next request to `path` fail with a Binance error.
+    ///
+    /// # Arguments
+    /// * `path`: The endpoint (e.g., "/fapi/v1/order").
+    /// * `status`: The HTTP status of the response (e.g., 400).
+    /// * `code`: The Binance error code (e.g., -2021).
+    /// * `msg`: The Binance error message (e.g., "Order would immediately trigger.").
+    ///
+    pub fn inject_error(&self, path: &str, status: u16, code: i64, msg: &str) {
+        self.inject_fault(
+            path,
+            Fault::BinanceError {
+                status,
+                code,
+                msg: msg.to_string(),
+            },
+        );
+    }
+
+    /// Runs a future with `exchange_url` pointing to this server, whatever the environment says.
+    pub async fn run<F: Future>(&self, future: F) -> F::Output {
+        with_exchange_url(self.url.clone(), future).await
+    }
+
+    /// Base url of the server.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// API keys of the requests received by the server, in the order of `requests`.
+    pub fn api_keys(&self) -> Vec<String> {
+        self.state.lock().unwrap().api_keys.clone()
+    }
+
+    /// Requests received by the server ("METHOD /path?query").
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Brings the server back to its initial state.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = MockState::default();
+    }
+}
+
+/// Starts a new server, private to the caller, with the environment of the shared one (credentials).
+pub fn isolated() -> MockServer {
+    shared();
+    MockServer::start()
+}
+
+/// Gets the mock server shared by the tests, starting it in the first call.
+///
+/// The environment is set so that `exchange_url` points to the server and the credentials exist.
+///
+pub fn shared() -> &'static MockServer {
+    static SERVER: OnceLock<MockServer> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let server = MockServer::start();
+        env::set_var("RUST_TEST", "1");
+        env::set_var("TEST_API_URL", &server.url);
+        if env::var("BINANCE_API_KEY").is_err() {
+            env::set_var("BINANCE_API_KEY", "mock-api-key");
+        }
+        if env::var("BINANCE_SECRET_KEY").is_err() {
+            env::set_var("BINANCE_SECRET_KEY", "mock-secret-key");
+        }
+        server
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Serves the HTTP/1.1 requests of one (keep-alive) connection.
+async fn handle_connection(socket: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut reader = BufReader::new(socket);
+    loop {
+        let mut request_line = String::new();
+        match reader.read_line(&mut request_line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+
+        let mut content_length = 0;
+        let mut api_key = String::new();
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                } else if name.eq_ignore_ascii_case("x-mbx-apikey") {
+                    api_key = value.trim().to_string();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("").to_string();
+
+        let path = target.split('?').next().unwrap_or("").to_string();
+        let query = target.split_once('?').map_or("", |(_, q)| q);
+        let (fault, mut headers) = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(format!("{} {}", method, target));
+            state.api_keys.push(api_key);
+
+            let minute = now() / 60_000;
+            if state.usage.0 != minute {
+                state.usage = (minute, 0, 0);
+            }
+            state.usage.1 += endpoint_weight(&method, &path, query);
+            if is_order_request(&method, &path) {
+                state.usage.2 += 1;
+            }
+            let headers = vec![
+                format!("X-MBX-USED-WEIGHT-1M: {}", state.usage.1),
+                format!("X-MBX-ORDER-COUNT-1M: {}", state.usage.2),
+            ];
+
+            (
+                state.faults.get_mut(&path).and_then(|f| f.pop_front()),
+                headers,
+            )
+        };
+
+        let (status, body) = match fault {
+            None => route(&state, &method, &target),
+            Some(Fault::Latency(delay)) => {
+                tokio::time::sleep(delay).await;
+                route(&state, &method, &target)
+            }
+            Some(Fault::ConnectionReset) => return,
+            Some(Fault::HttpStatus {
+                status,
+                body,
+                retry_after,
+            }) => {
+                if let Some(seconds) = retry_after {
+                    headers.push(format!("Retry-After: {}", seconds));
+                }
+                (status, body)
+            }
+            Some(Fault::MalformedBody) => {
+                let (status, body) = route(&state, &method, &target);
+                (status, body[..body.len() / 2].to_string())
+            }
+            Some(Fault::BinanceError { status, code, msg }) => binance_error(status, code, &msg),
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            status,
+            reason(status),
+            body.len()
+        );
+        for header in headers {
+            response.push_str(&header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        response.push_str(&body);
+        if reader
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        418 => "I'm a teapot",
+        429 => "Too Many Requests",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Error",
+    }
+}
+
+fn parse_query(target: &str) -> (String, HashMap<String, String>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let params = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                urlencoding::decode(v)
+                    .map(|v| v.into_owned())
+                    .unwrap_or_else(|_| v.to_string()),
+            )
+        })
+        .collect();
+    (path.to_string(), params)
+}
+
+fn binance_error(status: u16, code: i64, msg: &str) -> (u16, String) {
+    (status, json!({ "code": code, "msg": msg }).to_string())
+}
+
+fn route(state: &Arc<Mutex<MockState>>, method: &str, target: &str) -> (u16, String) {
+    let (path, params) = parse_query(target);
+    let mut state = state.lock().unwrap();
+
+    let symbol = params
+        .get("symbol")
+        .cloned()
+        .unwrap_or_else(|| "BTCUSDT".to_string());
+
+    if let Some(timestamp) = params.get("timestamp").and_then(|t| t.parse::<i64>().ok()) {
+        let server_time = now() as i64 + state.clock_offset;
+        let recv_window = params
+            .get("recvWindow")
+            .and_then(|r| r.parse::<i64>().ok())
+            .unwrap_or(5000);
+        if timestamp >= server_time + 1000 || server_time - timestamp > recv_window {
+            return binance_error(
+                400,
+                -1021,
+                "Timestamp for this request is outside of the recvWindow.",
+            );
+        }
+    }
+
+    expire_countdowns(&mut state);
+
+    match (method, path.as_str()) {
+        ("GET", "/fapi/v1/ping") => (200, "{}".to_string()),
+        ("GET", "/fapi/v1/time") => (
+            200,
+            json!({ "serverTime": now() as i64 + state.clock_offset }).to_string(),
+        ),
+        ("GET", "/fapi/v1/exchangeInfo") => (
+            200,
+            json!({
+                "timezone": "UTC",
+                "serverTime": now(),
+                "assets": [{ "asset": "USDT", "marginAvailable": true }],
+                "symbols": [{
+                    "symbol": "BTCUSDT",
+                    "status": "TRADING",
+                    "pricePrecision": 2,
+                    "quantityPrecision": 3,
+                    "filters": [
+                        { "filterType": "PRICE_FILTER", "minPrice": "556.80", "maxPrice": "4529764", "tickSize": "0.10" },
+                        { "filterType": "LOT_SIZE", "minQty": "0.001", "maxQty": "1000", "stepSize": "0.001" }
+                    ]
+                }, {
+                    "symbol": "ETHUSDT",
+                    "status": "TRADING",
+                    "pricePrecision": 2,
+                    "quantityPrecision": 3,
+                    "filters": [
+                        { "filterType": "PRICE_FILTER", "minPrice": "39.86", "maxPrice": "306177", "tickSize": "0.01" }
+                    ]
+                }]
+            })
+            .to_string(),
+        ),
+        ("GET", "/fapi/v1/ticker/price") => (
+            200,
+            json!({ "symbol": symbol, "price": format!("{:.2}", state.price), "time": now() })
+                .to_string(),
+        ),
+        ("GET", "/fapi/v1/ticker/bookTicker") => (
+            200,
+            json!({
+                "symbol": symbol,
+                "bidPrice": format!("{:.2}", state.price - 0.1),
+                "bidQty": "10.000",
+                "askPrice": format!("{:.2}", state.price + 0.1),
+                "askQty": "10.000",
+                "time": now()
+            })
+            .to_string(),
+        ),
+        ("GET", "/fapi/v1/premiumIndex") => (
+            200,
+            json!({
+                "symbol": symbol,
+                "markPrice": format!("{:.2}", state.mark_price.unwrap_or(state.price)),
+                "indexPrice": format!("{:.2}", state.price),
+                "estimatedSettlePrice": format!("{:.2}", state.price),
+                "lastFundingRate": "0.00010000",
+                "interestRate": "0.00010000",
+                "nextFundingTime": now() - now() % 28_800_000 + 28_800_000,
+                "time": now()
+            })
+            .to_string(),
+        ),
+        ("GET", "/fapi/v1/fundingRate") => {
+            let rates: Vec<(String, f64, u64)> = state
+                .funding_rates
+                .iter()
+                .filter(|f| !params.contains_key("symbol") || f.0 == symbol)
+                .cloned()
+                .collect();
+            let rates: Vec<Value> = history_page(rates, |f| f.2, &params, 100)
+                .iter()
+                .map(|(symbol, rate, time)| {
+                    json!({
+                        "symbol": symbol,
+                        "fundingRate": format!("{:.8}", rate),
+                        "fundingTime": time,
+                        "markPrice": format!("{:.2}", state.price)
+                    })
+                })
+                .collect();
+            (200, Value::Array(rates).to_string())
+        }
+        ("GET", "/fapi/v1/klines") => (200, klines(&state, &params).to_string()),
+        ("GET", "/fapi/v1/positionSide/dual") => (
+            200,
+            json!({ "dualSidePosition": state.dual_side_position }).to_string(),
+        ),
+        ("POST", "/fapi/v1/positionSide/dual") => {
+            let dual_side_position = params.get("dualSidePosition").map(|v| v == "true");
+            if dual_side_position == Some(state.dual_side_position) {
+                binance_error(400, -4059, "No need to change position side.")
+            } else {
+                state.dual_side_position = dual_side_position.unwrap_or(false);
+                (200, json!({ "code": 200, "msg": "success" }).to_string())
+            }
+        }
+        ("GET", "/fapi/v2/positionRisk") if params.contains_key("symbol") => {
+            (200, position_risk(&state, &symbol).to_string())
+        }
+        ("GET", "/fapi/v2/positionRisk") => {
+            // Without symbol, the positions of BTCUSDT and of every symbol that was traded.
+            let mut symbols: Vec<String> = state.positions.keys().map(|(s, _)| s.clone()).collect();
+            symbols.push("BTCUSDT".to_string());
+            symbols.sort();
+            symbols.dedup();
+            let positions: Vec<Value> = symbols
+                .iter()
+                .flat_map(|s| {
+                    position_risk(&state, s)
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .collect();
+            (200, Value::Array(positions).to_string())
+        }
+        ("POST", "/fapi/v1/leverage") => {
+            let leverage: u32 = params
+                .get("leverage")
+                .and_then(|l| l.parse().ok())
+                .unwrap_or(0);
+            if !(1..=125).contains(&leverage) {
+                return binance_error(400, -4028, &format!("Leverage {} is not valid", leverage));
+            }
+            state.leverage.insert(symbol.clone(), leverage);
+            (
+                200,
+                json!({ "leverage": leverage, "maxNotionalValue": "1000000", "symbol": symbol })
+                    .to_string(),
+            )
+        }
+        ("POST", "/fapi/v1/marginType") => {
+            let isolated = match params.get("marginType").map(String::as_str) {
+                Some("ISOLATED") => true,
+                Some("CROSSED") => false,
+                _ => return binance_error(400, -1116, "Invalid marginType."),
+            };
+            if isolated == state.isolated.contains(&symbol) {
+                return binance_error(400, -4046, "No need to change margin type.");
+            }
+            if isolated {
+                state.isolated.push(symbol.clone());
+            } else {
+                state.isolated.retain(|s| s != &symbol);
+            }
+            (200, json!({ "code": 200, "msg": "success" }).to_string())
+        }
+        ("POST", "/fapi/v1/positionMargin") => {
+            if !state.isolated.contains(&symbol) {
+                return binance_error(400, -4048, "Margin type is not isolated.");
+            }
+            let amount: f64 = params
+                .get("amount")
+                .and_then(|a| a.parse().ok())
+                .unwrap_or(0.0);
+            let action = params.get("type").cloned().unwrap_or_default();
+            let position_side = params
+                .get("positionSide")
+                .cloned()
+                .unwrap_or_else(|| "BOTH".to_string());
+            let margin = state
+                .isolated_margin
+                .entry((symbol.clone(), position_side))
+                .or_insert(0.0);
+            if action == "2" && amount > *margin {
+                return binance_error(400, -4051, "Isolated balance insufficient.");
+            }
+            *margin += if action == "2" { -amount } else { amount };
+            (
+                200,
+                json!({
+                    "amount": amount,
+                    "code": 200,
+                    "msg": "Successfully modify position margin.",
+                    "type": action.parse::<u8>().unwrap_or(1)
+                })
+                .to_string(),
+            )
+        }
+        ("GET", "/fapi/v1/multiAssetsMargin") => (
+            200,
+            json!({ "multiAssetsMargin": state.multi_assets_margin }).to_string(),
+        ),
+        ("POST", "/fapi/v1/multiAssetsMargin") => {
+            let multi_assets_margin = params.get("multiAssetsMargin").map(|v| v == "true");
+            if multi_assets_margin == Some(state.multi_assets_margin) {
+                binance_error(
+                    400,
+                    -4171,
+                    "Adjusted asset mode is currently set and does not need to be adjusted.",
+                )
+            } else {
+                state.multi_assets_margin = multi_assets_margin.unwrap_or(false);
+                (200, json!({ "code": 200, "msg": "success" }).to_string())
+            }
+        }
+        ("GET", "/fapi/v1/leverageBracket") => {
+            let brackets = json!({
+                "symbol": symbol,
+                "brackets": [
+                    { "bracket": 1, "initialLeverage": 125, "notionalCap": 50000, "notionalFloor": 0,
+                      "maintMarginRatio": 0.004, "cum": 0.0 },
+                    { "bracket": 2, "initialLeverage": 100, "notionalCap": 250000, "notionalFloor": 50000,
+                      "maintMarginRatio": 0.005, "cum": 50.0 }
+                ]
+            });
+            if params.contains_key("symbol") {
+                (200, brackets.to_string())
+            } else {
+                (200, json!([brackets]).to_string())
+            }
+        }
+        ("GET", "/fapi/v2/balance") => (
+            200,
+            json!([{
+                "accountAlias": "mock",
+                "asset": "USDT",
+                "balance": format!("{:.8}", state.balance),
+                "crossWalletBalance": format!("{:.8}", state.balance),
+                "crossUnPnl": "0.00000000",
+                "availableBalance": format!("{:.8}", state.balance),
+                "maxWithdrawAmount": format!("{:.8}", state.balance),
+                "marginAvailable": true,
+                "updateTime": now()
+            }])
+            .to_string(),
+        ),
+        ("GET", "/fapi/v2/account") => {
+            let positions: Vec<Value> = state
+                .positions
+                .iter()
+                .map(|((s, side), (amount, entry_price))| {
+                    json!({
+                        "symbol": s,
+                        "positionSide": side,
+                        "positionAmt": format!("{}", amount),
+                        "entryPrice": format!("{}", entry_price),
+                        "unrealizedProfit": format!("{}", amount * (state.price - entry_price)),
+                        "initialMargin": "0",
+                        "maintMargin": "0",
+                        "leverage": format!(
+                            "{}",
+                            state.leverage.get(s).copied().unwrap_or(MOCK_DEFAULT_LEVERAGE)
+                        ),
+                        "isolated": state.isolated.contains(s),
+                        "updateTime": now()
+                    })
+                })
+                .collect();
+            let unrealized_profit: f64 = state
+                .positions
+                .values()
+                .map(|(amount, entry_price)| amount * (state.price - entry_price))
+                .sum();
+            let margin_balance = format!("{:.8}", state.balance + unrealized_profit);
+            let balance = format!("{:.8}", state.balance);
+            let unrealized_profit = format!("{:.8}", unrealized_profit);
+            (
+                200,
+                json!({
+                    "feeTier": 0,
+                    "canTrade": true,
+                    "multiAssetsMargin": state.multi_assets_margin,
+                    "totalWalletBalance": balance,
+                    "totalUnrealizedProfit": unrealized_profit,
+                    "totalMarginBalance": margin_balance,
+                    "totalInitialMargin": "0",
+                    "totalMaintMargin": "0",
+                    "availableBalance": margin_balance,
+                    "maxWithdrawAmount": balance,
+                    "assets": [{
+                        "asset": "USDT",
+                        "walletBalance": balance,
+                        "unrealizedProfit": unrealized_profit,
+                        "marginBalance": margin_balance,
+                        "initialMargin": "0",
+                        "maintMargin": "0",
+                        "availableBalance": margin_balance,
+                        "maxWithdrawAmount": balance,
+                        "updateTime": now()
+                    }],
+                    "positions": positions,
+                    "updateTime": now()
+                })
+                .to_string(),
+            )
+        }
+        ("GET", "/fapi/v1/income") => {
+            let incomes: Vec<MockIncome> = state
+                .incomes
+                .iter()
+                .filter(|i| !params.contains_key("symbol") || i.symbol == symbol)
+                .filter(|i| params.get("incomeType").is_none_or(|t| &i.income_type == t))
+                .cloned()
+                .collect();
+            let incomes: Vec<Value> = history_page(incomes, |i| i.time, &params, 100)
+                .iter()
+                .map(|i| {
+                    json!({
+                        "symbol": i.symbol,
+                        "incomeType": i.income_type,
+                        "income": format!("{}", i.income),
+                        "asset": "USDT",
+                        "info": i.income_type,
+                        "time": i.time,
+                        "tranId": i.tran_id,
+                        "tradeId": i.trade_id
+                    })
+                })
+                .collect();
+            (200, Value::Array(incomes).to_string())
+        }
+        ("GET", "/fapi/v1/allOrders") | ("GET", "/fapi/v1/userTrades")
+            if lookup_interval_too_big(&params) =>
+        {
+            binance_error(
+                400,
+                -1127,
+                "More than 168 hours between startTime and endTime.",
+            )
+        }
+        ("GET", "/fapi/v1/allOrders") => {
+            let from_id = params.get("orderId").and_then(|id| id.parse::<u64>().ok());
+            let orders: Vec<MockOrder> = state
+                .orders
+                .iter()
+                .filter(|o| o.symbol == symbol && from_id.is_none_or(|id| o.order_id >= id))
+                .cloned()
+                .collect();
+            // With orderId, the orders are returned from that id like with startTime.
+            let orders = if from_id.is_some() {
+                let limit = params
+                    .get("limit")
+                    .and_then(|l| l.parse().ok())
+                    .unwrap_or(500);
+                orders.into_iter().take(limit).collect()
+            } else {
+                history_page(orders, |o| o.time, &params, 500)
+            };
+            let orders: Vec<Value> = orders.iter().map(order_json).collect();
+            (200, Value::Array(orders).to_string())
+        }
+        ("GET", "/fapi/v1/userTrades") => {
+            let from_id = params.get("fromId").and_then(|id| id.parse::<u64>().ok());
+            let trades: Vec<MockTrade> = state
+                .trades
+                .iter()
+                .filter(|t| t.symbol == symbol && from_id.is_none_or(|id| t.id >= id))
+                .cloned()
+                .collect();
+            // With fromId, the trades are returned from that id like with startTime.
+            let trades = if from_id.is_some() {
+                let limit = params
+                    .get("limit")
+                    .and_then(|l| l.parse().ok())
+                    .unwrap_or(500);
+                trades.into_iter().take(limit).collect()
+            } else {
+                history_page(trades, |t| t.time, &params, 500)
+            };
+            let trades: Vec<Value> = trades
+                .iter()
+                .map(|t| {
+                    json!({
+                        "buyer": t.side == "BUY",
+                        "commission": format!("{}", t.commission),
+                        "commissionAsset": "USDT",
+                        "id": t.id,
+                        "maker": t.maker,
+                        "orderId": t.order_id,
+                        "price": format!("{}", t.price),
+                        "qty": format!("{}", t.quantity),
+                        "quoteQty": format!("{}", t.price * t.quantity),
+                        "realizedPnl": format!("{}", t.realized_pnl),
+                        "side": t.side,
+                        "positionSide": t.position_side,
+                        "symbol": t.symbol,
+                        "time": t.time
+                    })
+                })
+                .collect();
+            (200, Value::Array(trades).to_string())
+        }
+        ("GET", "/fapi/v1/openOrders") => {
+            let orders: Vec<Value> = state
+                .orders
+                .iter()
+                .filter(|o| !params.contains_key("symbol") || o.symbol == symbol)
+                .filter(|o| o.status == "NEW")
+                .map(order_json)
+                .collect();
+            (200, Value::Array(orders).to_string())
+        }
+        ("DELETE", "/fapi/v1/allOpenOrders") => {
+            cancel_open_orders(&mut state, &symbol);
+            (
+                200,
+                json!({ "code": 200, "msg": "The operation of cancel all open order is done." })
+                    .to_string(),
+            )
+        }
+        ("POST", "/fapi/v1/countdownCancelAll") => {
+            let countdown_time = match params
+                .get("countdownTime")
+                .and_then(|c| c.parse::<u64>().ok())
+            {
+                Some(countdown_time) => countdown_time,
+                None => {
+                    return binance_error(
+                        400,
+                        -1102,
+                        "Mandatory parameter 'countdownTime' was not sent, was empty/null, or malformed.",
+                    )
+                }
+            };
+            if countdown_time == 0 {
+                state.countdowns.remove(&symbol);
+            } else {
+                state
+                    .countdowns
+                    .insert(symbol.clone(), now() + countdown_time);
+            }
+            (
+                200,
+                json!({ "symbol": symbol, "countdownTime": countdown_time.to_string() })
+                    .to_string(),
+            )
+        }
+        ("POST", "/fapi/v1/order") => new_order(&mut state, &symbol, &params),
+        ("GET", "/fapi/v1/order") | ("PUT", "/fapi/v1/order") | ("DELETE", "/fapi/v1/order") => {
+            let order_id: u64 = params
+                .get("orderId")
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(0);
+            let client_order_id = params.get("origClientOrderId");
+            let order = match state
+                .orders
+                .iter_mut()
+                .find(|o| o.order_id == order_id || Some(&o.client_order_id) == client_order_id)
+            {
+                Some(order) => order,
+                None if method == "GET" => {
+                    return binance_error(400, -2013, "Order does not exist.")
+                }
+                None => return binance_error(400, -2011, "Unknown order sent."),
+            };
+            if method == "DELETE" {
+                if order.status != "NEW" {
+                    return binance_error(400, -2011, "Unknown order sent.");
+                }
+                order.status = "CANCELED".to_string();
+                order.update_time = now();
+            } else if method == "PUT" {
+                if let Some(quantity) = params.get("quantity").and_then(|q| q.parse().ok()) {
+                    order.quantity = quantity;
+                }
+                if let Some(price) = params.get("price") {
+                    order.price = price.clone();
+                }
+                order.update_time = now();
+            }
+            (200, order_json(order).to_string())
+        }
+        _ => binance_error(404, -1000, "Unknown endpoint of the mock server."),
+    }
+}
+
+/// Validates and stores a new order, filling it right away when it is a market order.
+fn new_order(
+    state: &mut MockState,
+    symbol: &str,
+    params: &HashMap<String, String>,
+) -> (u16, String) {
+    let side = params.get("side").cloned().unwrap_or_default();
+    let order_type = params.get("type").cloned().unwrap_or_default();
+    let quantity: f64 = params
+        .get("quantity")
+        .and_then(|q| q.parse().ok())
+        .unwrap_or(0.0);
+    let position_side = params
+        .get("positionSide")
+        .cloned()
+        .unwrap_or_else(|| "BOTH".to_string());
+    let reduce_only = params
+        .get("reduceOnly")
+        .map(|r| r == "true")
+        .unwrap_or(false);
+    let stop_price = params.get("stopPrice").cloned();
+    let close_position = params
+        .get("closePosition")
+        .map(|c| c == "true")
+        .unwrap_or(false);
+    let working_type = params
+        .get("workingType")
+        .cloned()
+        .unwrap_or_else(|| "CONTRACT_PRICE".to_string());
+
+    if state.dual_side_position == (position_side == "BOTH") {
+        return binance_error(
+            400,
+            -4061,
+            "Order's position side does not match user's setting.",
+        );
+    }
+
+    let closing = match position_side.as_str() {
+        "LONG" => side == "SELL",
+        "SHORT" => side == "BUY",
+        _ => reduce_only,
+    };
+    let position = state
+        .positions
+        .get(&(symbol.to_string(), position_side.clone()))
+        .map(|p| p.0)
+        .unwrap_or(0.0);
+    if closing && position == 0.0 && !close_position {
+        return binance_error(400, -2022, "ReduceOnly Order is rejected.");
+    }
+    if close_position && (reduce_only || params.contains_key("quantity")) {
+        return binance_error(400, -1106, "Parameter 'quantity' sent when not required.");
+    }
+
+    if order_type == "STOP_MARKET" {
+        let stop_price: f64 =
+            match stop_price.as_ref().and_then(|p| p.parse().ok()) {
+                Some(stop_price) => stop_price,
+                None => return binance_error(
+                    400,
+                    -1102,
+                    "Mandatory parameter 'stopPrice' was not sent, was empty/null, or malformed.",
+                ),
+            };
+        let trigger_price = match working_type.as_str() {
+            "MARK_PRICE" => state.mark_price.unwrap_or(state.price),
+            _ => state.price,
+        };
+        let would_trigger = if side == "BUY" {
+            stop_price <= trigger_price
+        } else {
+            stop_price >= trigger_price
+        };
+        if would_trigger {
+            return binance_error(400, -2021, "Order would immediately trigger.");
+        }
+    }
+
+    let mut order = MockOrder {
+        order_id: state.next_order_id,
+        client_order_id: params
+            .get("newClientOrderId")
+            .cloned()
+            .unwrap_or_else(|| format!("mock{}", state.next_order_id)),
+        symbol: symbol.to_string(),
+        side,
+        order_type: order_type.clone(),
+        price: params
+            .get("price")
+            .cloned()
+            .unwrap_or_else(|| "0".to_string()),
+        stop_price: stop_price.unwrap_or_else(|| "0".to_string()),
+        quantity,
+        executed_qty: 0.0,
+        avg_price: 0.0,
+        position_side,
+        reduce_only,
+        close_position,
+        working_type,
+        time_in_force: params
+            .get("timeInForce")
+            .cloned()
+            .unwrap_or_else(|| "GTC".to_string()),
+        status: "NEW".to_string(),
+        time: now(),
+        update_time: now(),
+    };
+    state.next_order_id += 1;
+
+    if order_type == "MARKET" {
+        let price = state.price;
+        fill(state, &mut order, price);
+    } else if order_type == "LIMIT" && order.time_in_force == "IOC" {
+        // Immediate or cancel: filled at the market price when the limit allows it, expired otherwise.
+        let price = state.price;
+        let limit: f64 = order.price.parse().unwrap_or(0.0);
+        if (order.side == "BUY" && price <= limit) || (order.side == "SELL" && price >= limit) {
+            fill(state, &mut order, price);
+        } else {
+            order.status = "EXPIRED".to_string();
+        }
+    }
+    let response = order_json(&order);
+    state.orders.push(order);
+    trigger_orders(state);
+
+    (200, response.to_string())
+}
+
+/// Cancels the open orders of a symbol.
+fn cancel_open_orders(state: &mut MockState, symbol: &str) {
+    let time = now();
+    for order in state.orders.iter_mut() {
+        if order.symbol == symbol && order.status == "NEW" {
+            order.status = "CANCELED".to_string();
+            order.update_time = time;
+        }
+    }
+}
+
+/// Cancels the open orders of the symbols whose countdown is over. The countdowns are checked when a request
+/// arrives, which is enough for the tests, that look at the orders with a request.
+fn expire_countdowns(state: &mut MockState) {
+    let time = now();
+    let expired: Vec<String> = state
+        .countdowns
+        .iter()
+        .filter(|(_, deadline)| **deadline <= time)
+        .map(|(symbol, _)| symbol.clone())
+        .collect();
+    for symbol in expired {
+        state.countdowns.remove(&symbol);
+        cancel_open_orders(state, &symbol);
+    }
+}
+
+/// Fills the resting orders reached by the market price.
+fn trigger_orders(state: &mut MockState) {
+    let price = state.price;
+    let mut orders = std::mem::take(&mut state.orders);
+    for order in orders.iter_mut().filter(|o| o.status == "NEW") {
+        let is_buy = order.side == "BUY";
+        let reached = match order.order_type.as_str() {
+            "LIMIT" => {
+                let limit: f64 = order.price.parse().unwrap_or(0.0);
+                (is_buy && price <= limit) || (!is_buy && price >= limit)
+            }
+            "STOP_MARKET" => {
+                let stop: f64 = order.stop_price.parse().unwrap_or(0.0);
+                (is_buy && price >= stop) || (!is_buy && price <= stop)
+            }
+            _ => false,
+        };
+        if reached {
+            fill(state, order, price);
+        }
+    }
+    state.orders = orders;
+}
+
+/// Applies a fill to the position of the order. Closing orders are limited to the position amount.
+fn fill(state: &mut MockState, order: &mut MockOrder, price: f64) {
+    let key = (order.symbol.clone(), order.position_side.clone());
+    let (amount, entry_price) = state.positions.get(&key).cloned().unwrap_or((0.0, 0.0));
+
+    let direction = if order.side == "BUY" { 1.0 } else { -1.0 };
+    let closing = amount != 0.0 && amount.signum() != direction;
+    let quantity = if order.close_position {
+        // A close position stop closes whatever is open, and nothing when the position is in its direction.
+        if closing {
+            amount.abs()
+        } else {
+            0.0
+        }
+    } else if closing && (order.reduce_only || order.position_side != "BOTH") {
+        order.quantity.min(amount.abs())
+    } else {
+        order.quantity
+    };
+
+    let new_amount = amount + direction * quantity;
+    let new_entry = if new_amount == 0.0 {
+        0.0
+    } else if closing && new_amount.signum() == amount.signum() {
+        entry_price
+    } else if closing {
+        price
+    } else {
+        (entry_price * amount.abs() + price * quantity) / new_amount.abs()
+    };
+    state.positions.insert(key, (new_amount, new_entry));
+
+    if quantity > 0.0 {
+        let realized_pnl = if closing {
+            quantity.min(amount.abs()) * (price - entry_price) * amount.signum()
+        } else {
+            0.0
+        };
+        let maker = order.order_type == "LIMIT";
+        let fee_rate = if maker {
+            MOCK_MAKER_FEE_RATE
+        } else {
+            MOCK_TAKER_FEE_RATE
+        };
+        let trade = MockTrade {
+            id: state.next_trade_id,
+            order_id: order.order_id,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            position_side: order.position_side.clone(),
+            price,
+            quantity,
+            realized_pnl,
+            commission: quantity * price * fee_rate,
+            maker,
+            time: now(),
+        };
+        state.next_trade_id += 1;
+
+        let trade_id = trade.id.to_string();
+        if realized_pnl != 0.0 {
+            add_income(
+                state,
+                &trade.symbol,
+                "REALIZED_PNL",
+                realized_pnl,
+                trade_id.clone(),
+                trade.time,
+            );
+        }
+        add_income(
+            state,
+            &trade.symbol,
+            "COMMISSION",
+            -trade.commission,
+            trade_id,
+            trade.time,
+        );
+        state.trades.push(trade);
+    }
+
+    order.executed_qty = quantity;
+    order.avg_price = price;
+    order.status = "FILLED".to_string();
+    order.update_time = now();
+}
+
+/// Adds an income to the history and to the balance.
+fn add_income(
+    state: &mut MockState,
+    symbol: &str,
+    income_type: &str,
+    income: f64,
+    trade_id: String,
+    time: u64,
+) {
+    state.incomes.push(MockIncome {
+        tran_id: state.next_trade_id,
+        symbol: symbol.to_string(),
+        income_type: income_type.to_string(),
+        income,
+        trade_id,
+        time,
+    });
+    state.next_trade_id += 1;
+    state.balance += income;
+}
+
+/// Checks if the range of a history request is longer than the 7 days accepted by the exchange.
+fn lookup_interval_too_big(params: &HashMap<String, String>) -> bool {
+    let param = |key: &str| params.get(key).and_then(|v| v.parse::<u64>().ok());
+    match (param("startTime"), param("endTime")) {
+        (Some(start), Some(end)) => end.saturating_sub(start) > 7 * 24 * 3_600_000,
+        _ => false,
+    }
+}
+
+/// Filters the records of a history endpoint like the exchange: by startTime and endTime, and limited to the first
+/// `limit` records from startTime, or to the last `limit` records when there is no startTime.
+fn history_page<T: Clone>(
+    records: Vec<T>,
+    time: impl Fn(&T) -> u64,
+    params: &HashMap<String, String>,
+    default_limit: usize,
+) -> Vec<T> {
+    let param = |key: &str| params.get(key).and_then(|v| v.parse::<u64>().ok());
+    let limit = param("limit").map_or(default_limit, |l| l as usize);
+    let records: Vec<T> = records
+        .into_iter()
+        .filter(|r| param("startTime").is_none_or(|start| time(r) >= start))
+        .filter(|r| param("endTime").is_none_or(|end| time(r) <= end))
+        .collect();
+    if param("startTime").is_some() {
+        records.into_iter().take(limit).collect()
+    } else {
+        let skip = records.len().saturating_sub(limit);
+        records.into_iter().skip(skip).collect()
+    }
+}
+
+fn order_json(order: &MockOrder) -> Value {
+    json!({
+        "orderId": order.order_id,
+        "symbol": order.symbol,
+        "status": order.status,
+        "clientOrderId": order.client_order_id,
+        "price": order.price,
+        "avgPrice": format!("{:.2}", order.avg_price),
+        "origQty": format!("{}", order.quantity),
+        "executedQty": format!("{}", order.executed_qty),
+        "cumQuote": "0",
+        "timeInForce": order.time_in_force,
+        "type": order.order_type,
+        "reduceOnly": order.reduce_only,
+        "closePosition": order.close_position,
+        "side": order.side,
+        "positionSide": order.position_side,
+        "stopPrice": order.stop_price,
+        "workingType": order.working_type,
+        "priceProtect": false,
+        "origType": order.order_type,
+        "time": order.time,
+        "updateTime": order.update_time
+    })
+}
+
+fn position_risk(state: &MockState, symbol: &str) -> Value {
+    let sides: &[&str] = if state.dual_side_position {
+        &["LONG", "SHORT"]
+    } else {
+        &["BOTH"]
+    };
+    let positions: Vec<Value> = sides
+        .iter()
+        .map(|side| {
+            let (amount, entry_price) = state
+                .positions
+                .get(&(symbol.to_string(), side.to_string()))
+                .cloned()
+                .unwrap_or((0.0, 0.0));
+            json!({
+                "symbol": symbol,
+                "positionAmt": format!("{}", amount),
+                "entryPrice": format!("{}", entry_price),
+                "markPrice": format!("{:.2}", state.price),
+                "unRealizedProfit": format!("{}", amount * (state.price - entry_price)),
+                "liquidationPrice": "0",
+                "leverage": format!(
+                    "{}",
+                    state.leverage.get(symbol).copied().unwrap_or(MOCK_DEFAULT_LEVERAGE)
+                ),
+                "maxNotionalValue": "5000000",
+                "marginType": if state.isolated.iter().any(|s| s == symbol) { "isolated" } else { "cross" },
+                "isolatedMargin": format!(
+                    "{:.8}",
+                    state
+                        .isolated_margin
+                        .get(&(symbol.to_string(), side.to_string()))
+                        .copied()
+                        .unwrap_or(0.0)
+                ),
+                "isAutoAddMargin": "false",
+                "positionSide": side,
+                "notional": format!("{}", amount * state.price),
+                "isolatedWallet": "0",
+                "updateTime": now()
+            })
+        })
+        .collect();
+    Value::Array(positions)
+}
+
+/// Builds candles aligned to the interval between startTime and endTime, all of them around the market price.
+fn klines(state: &MockState, params: &HashMap<String, String>) -> Value {
+    let interval = params.get("interval").cloned().unwrap_or_default();
+    let interval_ms: u64 = match interval.split_at(interval.len().saturating_sub(1)) {
+        (n, "m") => n.parse::<u64>().unwrap_or(1) * 60_000,
+        (n, "h") => n.parse::<u64>().unwrap_or(1) * 3_600_000,
+        (n, "d") => n.parse::<u64>().unwrap_or(1) * 86_400_000,
+        (n, "w") => n.parse::<u64>().unwrap_or(1) * 604_800_000,
+        _ => 60_000,
+    };
+    let limit: u64 = params
+        .get("limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(500);
+    let end_time: u64 = params
+        .get("endTime")
+        .and_then(|t| t.parse().ok())
+        .unwrap_or_else(now);
+    let start_time: u64 = params
+        .get("startTime")
+        .and_then(|t| t.parse().ok())
+        .unwrap_or_else(|| end_time - end_time % interval_ms - (limit - 1) * interval_ms);
+
+    let mut open_time = start_time + (interval_ms - start_time % interval_ms) % interval_ms;
+    let mut candles = Vec::new();
+    while open_time <= end_time && (candles.len() as u64) < limit {
+        let price = state.price;
+        candles.push(json!([
+            open_time,
+            format!("{:.2}", price),
+            format!("{:.2}", price * 1.001),
+            format!("{:.2}", price * 0.999),
+            format!("{:.2}", price),
+            "100.000",
+            open_time + interval_ms - 1,
+            format!("{:.2}", price * 100.0),
+            1000,
+            "50.000",
+            format!("{:.2}", price * 50.0),
+            "0"
+        ]));
+        open_time += interval_ms;
+    }
+    Value::Array(candles)
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::test;
+
+    /// Test that a stop order reached by a new market price is filled and opens the position.
+    #[test]
+    async fn mock_stop_order_fill_test() {
+        let server = MockServer::start();
+        server.set_dual_side_position(true);
+        let client = reqwest::Client::new();
+
+        let res = client
+            .post(format!(
+                "{}/fapi/v1/order?symbol=BTCUSDT&side=BUY&type=STOP_MARKET&stopPrice=26000&quantity=0.01&positionSide=LONG",
+                server.url
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+
+        server.set_price(26500.0);
+        let res: Value = client
+            .get(format!(
+                "{}/fapi/v2/positionRisk?symbol=BTCUSDT",
+                server.url
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res[0]["positionSide"], "LONG");
+        assert_eq!(res[0]["positionAmt"], "0.01");
+    }
+
+    /// Test that an injected error is returned once and the endpoint recovers after it.
+    #[test]
+    async fn mock_inject_error_test() {
+        let server = MockServer::start();
+        server.inject_error("/fapi/v1/ping", 502, -1000, "502 Bad Gateway");
+        let client = reqwest::Client::new();
+        let url = format!("{}/fapi/v1/ping", server.url);
+
+        let res = client.get(&url).send().await.unwrap();
+        assert_eq!(res.status().as_u16(), 502);
+
+        let res = client.get(&url).send().await.unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(server.requests().len(), 2);
+    }
+}
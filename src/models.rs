@@ -17,6 +17,25 @@
 //     - take_buy_base_asset_volume: The volume of the base asset bought during the candlestick period.
 //     - take_buy_quote_asset_volume: The volume of the quote asset bought during the candlestick period.
 //     - ignore: A property to ignore or discard (e.g., additional information not relevant to the candlestick data).
+// - Side, PositionSide and OrderType: The side, position side and type of an order, with the Binance names.
+// - WorkingType: The price that triggers a stop order (last price or mark price).
+// - OrderRequest: A venue independent description of an order to be placed.
+// - OrderAck: The exchange acknowledgement of a placed, amended or canceled order.
+// - Fill: A (partial) execution of an order.
+// - OpenOrder: An order that is resting in the book (from /fapi/v1/openOrders).
+// - OrderInfo: The state of an order (from GET /fapi/v1/order and /fapi/v1/allOrders).
+// - PositionRisk: A position of the account (from /fapi/v2/positionRisk).
+// - Balance: The balance of one asset of the futures wallet (from /fapi/v2/balance).
+// - AccountInformation: The margins, assets and positions of the account (from /fapi/v2/account).
+// - Income: A change of the wallet balance, e.g., realized pnl, funding fee or commission (from /fapi/v1/income).
+// - UserTrade: A trade of the account (from /fapi/v1/userTrades).
+// - BookTicker: The best bid and ask of a symbol (from /fapi/v1/ticker/bookTicker).
+// - PremiumIndex: The mark price, index price and funding of a symbol (from /fapi/v1/premiumIndex).
+// - FundingRate: A funding rate applied in the past (from /fapi/v1/fundingRate).
+// - LeverageResponse: The leverage of a symbol after changing it (from /fapi/v1/leverage).
+// - PositionMarginResponse: The margin added to or removed from an isolated position (from /fapi/v1/positionMargin).
+// - LeverageBrackets: The notional brackets of the leverage of a symbol (from /fapi/v1/leverageBracket).
+// - ExchangeInfo: The symbols of the exchange and their filters (from /fapi/v1/exchangeInfo).
 
 // The models.rs file serves as a centralized location to define the data structures used throughout your trading bot
 // project. By encapsulating these structures in a separate file, it promotes code reusability, modularity, and
@@ -28,6 +47,7 @@
 // candlestick data in a structured manner.
 
 use serde::{de, Deserialize, Deserializer, Serialize};
+use std::fmt;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct KlineData {
@@ -70,6 +90,582 @@ pub struct KlineDataString {
     pub ignore: String,
 }
 
+/// Side of an order.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    /// Get the side from its Binance name ("BUY" or "SELL").
+    pub fn from_binance(side: &str) -> Option<Self> {
+        match side {
+            "BUY" => Some(Side::Buy),
+            "SELL" => Some(Side::Sell),
+            _ => None,
+        }
+    }
+
+    /// Get the Binance name of the side.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buy => "BUY",
+            Side::Sell => "SELL",
+        }
+    }
+
+    /// Get the other side.
+    pub fn opposite(&self) -> Self {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+
+    /// Get the sign of the change of a position amount after a fill of this side (1 for BUY, -1 for SELL).
+    pub fn sign(&self) -> f64 {
+        match self {
+            Side::Buy => 1.0,
+            Side::Sell => -1.0,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Position side of an order or a position: BOTH in one-way mode, LONG or SHORT in hedge mode.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PositionSide {
+    Both,
+    Long,
+    Short,
+}
+
+impl PositionSide {
+    /// Get the position side from its Binance name ("BOTH", "LONG" or "SHORT").
+    pub fn from_binance(position_side: &str) -> Option<Self> {
+        match position_side {
+            "BOTH" => Some(PositionSide::Both),
+            "LONG" => Some(PositionSide::Long),
+            "SHORT" => Some(PositionSide::Short),
+            _ => None,
+        }
+    }
+
+    /// Get the Binance name of the position side.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PositionSide::Both => "BOTH",
+            PositionSide::Long => "LONG",
+            PositionSide::Short => "SHORT",
+        }
+    }
+
+    /// Get the hedge mode position side opened by an order of a side (BUY opens LONG, SELL opens SHORT).
+    pub fn opened_by(side: Side) -> Self {
+        match side {
+            Side::Buy => PositionSide::Long,
+            Side::Sell => PositionSide::Short,
+        }
+    }
+
+    /// Get the side of the orders that close a position of this position side.
+    ///
+    /// # Arguments
+    /// * `position_amount`: The signed amount of the position (positionAmt), only used for BOTH, where a negative
+    ///   amount is a short position.
+    ///
+    pub fn closing_side(&self, position_amount: f64) -> Side {
+        match self {
+            PositionSide::Long => Side::Sell,
+            PositionSide::Short => Side::Buy,
+            PositionSide::Both if position_amount < 0.0 => Side::Buy,
+            PositionSide::Both => Side::Sell,
+        }
+    }
+}
+
+impl fmt::Display for PositionSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Type of an order (see "Order Types" in the Binance futures api documentation).
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderType {
+    Limit,
+    Market,
+    Stop,
+    StopMarket,
+    TakeProfit,
+    TakeProfitMarket,
+    TrailingStopMarket,
+}
+
+impl OrderType {
+    /// Get the type from its Binance name (e.g., "STOP_MARKET").
+    pub fn from_binance(order_type: &str) -> Option<Self> {
+        match order_type {
+            "LIMIT" => Some(OrderType::Limit),
+            "MARKET" => Some(OrderType::Market),
+            "STOP" => Some(OrderType::Stop),
+            "STOP_MARKET" => Some(OrderType::StopMarket),
+            "TAKE_PROFIT" => Some(OrderType::TakeProfit),
+            "TAKE_PROFIT_MARKET" => Some(OrderType::TakeProfitMarket),
+            "TRAILING_STOP_MARKET" => Some(OrderType::TrailingStopMarket),
+            _ => None,
+        }
+    }
+
+    /// Get the Binance name of the type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Limit => "LIMIT",
+            OrderType::Market => "MARKET",
+            OrderType::Stop => "STOP",
+            OrderType::StopMarket => "STOP_MARKET",
+            OrderType::TakeProfit => "TAKE_PROFIT",
+            OrderType::TakeProfitMarket => "TAKE_PROFIT_MARKET",
+            OrderType::TrailingStopMarket => "TRAILING_STOP_MARKET",
+        }
+    }
+}
+
+impl fmt::Display for OrderType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Price compared with the stop price of an order to trigger it: the last price (CONTRACT_PRICE) or the mark price.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WorkingType {
+    #[default]
+    ContractPrice,
+    MarkPrice,
+}
+
+impl WorkingType {
+    /// Get the Binance name of the working type.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkingType::ContractPrice => "CONTRACT_PRICE",
+            WorkingType::MarkPrice => "MARK_PRICE",
+        }
+    }
+}
+
+impl fmt::Display for WorkingType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Venue independent description of an order.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OrderRequest {
+    pub symbol: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub quantity: f64,
+    pub price: Option<f64>,
+    pub stop_price: Option<f64>,
+    pub position_side: PositionSide,
+    pub reduce_only: bool,
+    /// Id of the order chosen by the client (newClientOrderId). The exchange generates one when it is not set.
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+    /// Price that triggers a stop order (workingType). The exchange uses CONTRACT_PRICE when it is not set.
+    #[serde(default)]
+    pub working_type: Option<WorkingType>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderAck {
+    pub order_id: u64,
+    pub symbol: String,
+    pub status: String,
+    #[serde(default)]
+    pub client_order_id: String,
+    pub update_time: u64,
+    /// Quantity executed when the order was acknowledged (0 when the response has no executedQty).
+    #[serde(default, deserialize_with = "de_float_from_str")]
+    pub executed_qty: f64,
+    /// Average price of the executed quantity.
+    #[serde(default, deserialize_with = "de_float_from_str")]
+    pub avg_price: f64,
+    /// Position side of the order in the exchange: LONG or SHORT in hedge mode, even when it was sent with BOTH.
+    #[serde(default)]
+    pub position_side: Option<PositionSide>,
+}
+
+/// Execution of an order, or of a part of it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Fill {
+    pub order_id: u64,
+    pub symbol: String,
+    pub side: Side,
+    pub position_side: PositionSide,
+    pub price: f64,
+    pub quantity: f64,
+    pub time: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenOrder {
+    pub order_id: u64,
+    pub symbol: String,
+    pub side: Side,
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub stop_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub orig_qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub executed_qty: f64,
+    pub status: String,
+    pub position_side: PositionSide,
+    pub reduce_only: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderInfo {
+    pub order_id: u64,
+    pub client_order_id: String,
+    pub symbol: String,
+    pub side: Side,
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub position_side: PositionSide,
+    pub status: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub stop_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub orig_qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub executed_qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub avg_price: f64,
+    pub reduce_only: bool,
+    pub close_position: bool,
+    /// Time of creation of the order.
+    pub time: u64,
+    pub update_time: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionRisk {
+    pub symbol: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub position_amt: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub entry_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub mark_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub un_realized_profit: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub liquidation_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub leverage: f64,
+    pub margin_type: String,
+    pub position_side: PositionSide,
+}
+
+impl PositionRisk {
+    /// Get the side of the orders that close the position.
+    pub fn closing_side(&self) -> Side {
+        self.position_side.closing_side(self.position_amt)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Balance {
+    pub asset: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub available_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub cross_un_pnl: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub cross_wallet_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub max_withdraw_amount: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountAsset {
+    pub asset: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub wallet_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub unrealized_profit: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub margin_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub initial_margin: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub maint_margin: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub available_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub max_withdraw_amount: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountPosition {
+    pub symbol: String,
+    pub position_side: PositionSide,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub position_amt: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub entry_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub unrealized_profit: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub initial_margin: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub maint_margin: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub leverage: f64,
+    pub isolated: bool,
+}
+
+/// Account information. The totals are in USDT (or in USD in multi-assets mode).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInformation {
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub total_wallet_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub total_unrealized_profit: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub total_margin_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub total_initial_margin: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub total_maint_margin: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub available_balance: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub max_withdraw_amount: f64,
+    pub multi_assets_margin: bool,
+    pub assets: Vec<AccountAsset>,
+    pub positions: Vec<AccountPosition>,
+}
+
+/// Type of an income (see "Get Income History" in the Binance futures api documentation).
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IncomeType {
+    Transfer,
+    RealizedPnl,
+    FundingFee,
+    Commission,
+    InsuranceClear,
+    /// Every other type (e.g., WELCOME_BONUS, REFERRAL_KICKBACK).
+    #[serde(other)]
+    Other,
+}
+
+impl IncomeType {
+    /// Get the Binance name of the type (None for `Other`, that is never sent).
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            IncomeType::Transfer => Some("TRANSFER"),
+            IncomeType::RealizedPnl => Some("REALIZED_PNL"),
+            IncomeType::FundingFee => Some("FUNDING_FEE"),
+            IncomeType::Commission => Some("COMMISSION"),
+            IncomeType::InsuranceClear => Some("INSURANCE_CLEAR"),
+            IncomeType::Other => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Income {
+    /// Empty for the incomes that do not belong to a symbol (e.g., transfers).
+    pub symbol: String,
+    pub income_type: IncomeType,
+    /// Signed amount: negative when it was paid.
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub income: f64,
+    pub asset: String,
+    pub info: String,
+    pub time: u64,
+    pub tran_id: u64,
+    pub trade_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTrade {
+    pub id: u64,
+    pub order_id: u64,
+    pub symbol: String,
+    pub side: Side,
+    pub position_side: PositionSide,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub quote_qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub realized_pnl: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub commission: f64,
+    pub commission_asset: String,
+    pub maker: bool,
+    pub buyer: bool,
+    pub time: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BookTicker {
+    pub symbol: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub bid_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub bid_qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub ask_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub ask_qty: f64,
+    pub time: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LeverageResponse {
+    pub symbol: String,
+    pub leverage: u32,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub max_notional_value: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiumIndex {
+    pub symbol: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub mark_price: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub index_price: f64,
+    /// Rate of the last funding (the one that will be applied at `next_funding_time`).
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub last_funding_rate: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub interest_rate: f64,
+    pub next_funding_time: u64,
+    pub time: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FundingRate {
+    pub symbol: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    pub funding_rate: f64,
+    pub funding_time: u64,
+    /// Mark price at the funding time (None for the old fundings, that the exchange returns with an empty price).
+    #[serde(default, deserialize_with = "de_opt_float_from_str")]
+    pub mark_price: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PositionMarginResponse {
+    pub amount: f64,
+    pub code: i32,
+    pub msg: String,
+    /// 1 when the margin was added, 2 when it was removed.
+    #[serde(rename = "type")]
+    pub action: u8,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LeverageBracket {
+    pub bracket: u32,
+    pub initial_leverage: u32,
+    pub notional_cap: f64,
+    pub notional_floor: f64,
+    pub maint_margin_ratio: f64,
+    pub cum: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LeverageBrackets {
+    pub symbol: String,
+    pub brackets: Vec<LeverageBracket>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ExchangeInfo {
+    pub symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub status: String,
+    #[serde(default)]
+    pub filters: Vec<SymbolFilter>,
+}
+
+impl SymbolInfo {
+    /// Get the tick size of the prices (PRICE_FILTER), or None when the symbol has no price filter.
+    pub fn tick_size(&self) -> Option<f64> {
+        self.filters.iter().find_map(|filter| match filter {
+            SymbolFilter::PriceFilter { tick_size, .. } => Some(*tick_size),
+            SymbolFilter::Other => None,
+        })
+    }
+}
+
+/// Filter of a symbol. Only the filters used by the crate are read.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "filterType")]
+pub enum SymbolFilter {
+    #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+    PriceFilter {
+        #[serde(deserialize_with = "de_float_from_str")]
+        min_price: f64,
+        #[serde(deserialize_with = "de_float_from_str")]
+        max_price: f64,
+        #[serde(deserialize_with = "de_float_from_str")]
+        tick_size: f64,
+    },
+    #[serde(other)]
+    Other,
+}
+
 pub fn de_float_from_str<'a, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'a>,
@@ -77,3 +673,55 @@ where
     let str_val = String::deserialize(deserializer)?;
     str_val.parse::<f64>().map_err(de::Error::custom)
 }
+
+pub fn de_opt_float_from_str<'a, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'a>,
+{
+    let str_val = String::deserialize(deserializer)?;
+    if str_val.is_empty() {
+        Ok(None)
+    } else {
+        str_val.parse::<f64>().map(Some).map_err(de::Error::custom)
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::test;
+
+    /// Test the Binance names of the enums and the closing side of the positions.
+    #[test]
+    async fn side_and_position_side_test() {
+        assert_eq!(Side::from_binance("BUY"), Some(Side::Buy));
+        assert_eq!(Side::from_binance("buy"), None);
+        assert_eq!(OrderType::StopMarket.to_string(), "STOP_MARKET");
+        assert_eq!(
+            serde_json::from_str::<PositionSide>("\"SHORT\"").unwrap(),
+            PositionSide::Short
+        );
+
+        assert_eq!(PositionSide::Long.closing_side(0.0), Side::Sell);
+        assert_eq!(PositionSide::Short.closing_side(0.0), Side::Buy);
+        assert_eq!(PositionSide::Both.closing_side(0.002), Side::Sell);
+        assert_eq!(PositionSide::Both.closing_side(-0.002), Side::Buy);
+        assert_eq!(PositionSide::opened_by(Side::Sell), PositionSide::Short);
+    }
+
+    /// Test that the old funding rates, whose mark price is empty, are read.
+    #[test]
+    async fn funding_rate_test() {
+        let rates: Vec<FundingRate> = serde_json::from_str(
+            r#"[{"symbol":"BTCUSDT","fundingRate":"0.00010000","fundingTime":1570608000000,"markPrice":""},
+                {"symbol":"BTCUSDT","fundingRate":"-0.00003000","fundingTime":1700000000000,"markPrice":"37000.5"}]"#,
+        )
+        .unwrap();
+        assert_eq!(rates[0].mark_price, None);
+        assert_eq!(rates[1].mark_price, Some(37000.5));
+        assert_eq!(rates[1].funding_rate, -0.00003);
+        assert_eq!(WorkingType::default(), WorkingType::ContractPrice);
+        assert_eq!(WorkingType::MarkPrice.to_string(), "MARK_PRICE");
+    }
+}
@@ -0,0 +1,677 @@
+// orders.rs - Order Manager
+
+// This file contains the `OrderManager`, that tracks the orders of the strategies on the client side, keyed by the
+// client order id (newClientOrderId) that is sent with each order.
+
+// Each order follows the state machine of the exchange:
+//
+//     NEW -> PARTIALLY_FILLED -> FILLED
+//      |            |
+//      +------------+--------> CANCELED / EXPIRED
+//      +---------------------> REJECTED
+//
+// FILLED, CANCELED, EXPIRED and REJECTED are final: an update that would leave them, or go back to a previous state,
+// is refused. The fills of the orders (quantity, price and fee) are recorded with the executed quantity and the
+// average price, and are passed to the `PositionManager` (positions.rs) when one is attached. They carry the position
+// side the exchange gave to the order (LONG or SHORT in hedge mode, for an order sent with BOTH).
+
+// The manager learns about the orders from:
+// - `submit` and `cancel`: the acknowledgement of the exchange. An order refused by the exchange is REJECTED, but an
+//   order whose response was lost or unreadable (e.g., E03, E11 or E14) stays NEW, since it may be live: `refresh`
+//   looks it up by its client order id.
+// - `on_update`: an update of the order with its last fill (e.g., from the ORDER_TRADE_UPDATE event of the user data
+//   stream).
+// - `refresh`: the state of the order read from the exchange (GET /fapi/v1/order). The fees are not known this way.
+
+use crate::binance_orders::query_order;
+use crate::error::is_exchange_unavailable;
+use crate::exchange::Exchange;
+use crate::models::{Fill, OrderAck, OrderRequest, PositionSide};
+use crate::positions::PositionManager;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Executed quantity below which a quantity is considered zero.
+const QUANTITY_TOLERANCE: f64 = 1e-12;
+
+/// Status of an order (see "Order Status" in the Binance futures api documentation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    Expired,
+    Rejected,
+}
+
+impl OrderStatus {
+    /// Get the status from its Binance name (e.g., "PARTIALLY_FILLED").
+    pub fn from_binance(status: &str) -> Option<Self> {
+        match status {
+            "NEW" => Some(OrderStatus::New),
+            "PARTIALLY_FILLED" => Some(OrderStatus::PartiallyFilled),
+            "FILLED" => Some(OrderStatus::Filled),
+            "CANCELED" => Some(OrderStatus::Canceled),
+            "EXPIRED" | "EXPIRED_IN_MATCH" => Some(OrderStatus::Expired),
+            "REJECTED" => Some(OrderStatus::Rejected),
+            _ => None,
+        }
+    }
+
+    /// Get the Binance name of the status.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "NEW",
+            OrderStatus::PartiallyFilled => "PARTIALLY_FILLED",
+            OrderStatus::Filled => "FILLED",
+            OrderStatus::Canceled => "CANCELED",
+            OrderStatus::Expired => "EXPIRED",
+            OrderStatus::Rejected => "REJECTED",
+        }
+    }
+
+    /// Checks if the order can not change anymore.
+    pub fn is_final(&self) -> bool {
+        !matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+
+    /// Checks if an order in this status can go to `next`.
+    pub fn can_become(&self, next: OrderStatus) -> bool {
+        match self {
+            OrderStatus::New => next != OrderStatus::New,
+            OrderStatus::PartiallyFilled => {
+                !matches!(next, OrderStatus::New | OrderStatus::Rejected)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Update of an order, with its last fill.
+#[derive(Debug, Clone)]
+pub struct OrderUpdate {
+    pub client_order_id: String,
+    pub order_id: u64,
+    /// Binance name of the new status.
+    pub status: String,
+    /// Quantity of the last fill (0 when the update has no fill).
+    pub last_filled_qty: f64,
+    pub last_filled_price: f64,
+    /// Fee of the last fill.
+    pub commission: f64,
+    /// Position side of the order in the exchange, when the update has it.
+    pub position_side: Option<PositionSide>,
+    pub time: u64,
+}
+
+/// Order tracked by the `OrderManager`.
+#[derive(Debug, Clone)]
+pub struct ManagedOrder {
+    pub client_order_id: String,
+    /// Id given by the exchange (None until the exchange accepts the order).
+    pub order_id: Option<u64>,
+    /// Tag of the strategy that placed the order.
+    pub strategy: String,
+    pub request: OrderRequest,
+    /// Position side of the order in the exchange: LONG or SHORT in hedge mode, even when the request has BOTH. The
+    /// fills are recorded with it.
+    pub position_side: PositionSide,
+    pub status: OrderStatus,
+    pub executed_qty: f64,
+    /// Average price of the fills.
+    pub avg_price: f64,
+    /// Sum of the fees of the fills.
+    pub fees: f64,
+    pub fills: Vec<Fill>,
+    /// Error returned by the exchange when the order was rejected.
+    pub error: Option<String>,
+    pub update_time: u64,
+}
+
+/// Client-side state of the orders, keyed by client order id.
+#[derive(Default)]
+pub struct OrderManager {
+    orders: Mutex<HashMap<String, ManagedOrder>>,
+    next_id: AtomicU64,
+    positions: Option<Arc<PositionManager>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+impl OrderManager {
+    pub fn new() -> Self {
+        OrderManager::default()
+    }
+
+    /// Creates a manager that passes the fills of the orders to a `PositionManager`.
+    pub fn with_position_manager(positions: Arc<PositionManager>) -> Self {
+        OrderManager {
+            positions: Some(positions),
+            ..OrderManager::default()
+        }
+    }
+
+    /// Generates a client order id (up to 36 characters of [0-9a-z_], as the exchange requires).
+    fn generate_client_order_id(&self) -> String {
+        format!(
+            "qn_{}_{}",
+            now(),
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    /// Places an order and starts tracking it.
+    ///
+    /// # Arguments
+    /// * `exchange`: The exchange where the order is placed.
+    /// * `strategy`: The tag of the strategy that places the order.
+    /// * `order`: The order. A client order id is generated when it has none.
+    ///
+    /// # Returns
+    /// The tracked order, or the error of the exchange. The order is kept as REJECTED when the exchange refused it, and
+    /// as NEW when the exchange could not be reached or its response could not be read (see `is_exchange_unavailable`),
+    /// so `refresh` can look it up.
+    ///
+    pub async fn submit(
+        &self,
+        exchange: &dyn Exchange,
+        strategy: &str,
+        mut order: OrderRequest,
+    ) -> Result<ManagedOrder, String> {
+        let client_order_id = order
+            .client_order_id
+            .get_or_insert_with(|| self.generate_client_order_id())
+            .clone();
+        self.orders.lock().unwrap().insert(
+            client_order_id.clone(),
+            ManagedOrder {
+                client_order_id: client_order_id.clone(),
+                order_id: None,
+                strategy: strategy.to_string(),
+                request: order.clone(),
+                position_side: order.position_side,
+                status: OrderStatus::New,
+                executed_qty: 0.0,
+                avg_price: 0.0,
+                fees: 0.0,
+                fills: Vec::new(),
+                error: None,
+                update_time: now(),
+            },
+        );
+
+        match exchange.place_order(&order).await {
+            Ok(ack) => self.apply_ack(&client_order_id, &ack),
+            Err(error) => {
+                let mut orders = self.orders.lock().unwrap();
+                let managed = orders.get_mut(&client_order_id).unwrap();
+                if !is_exchange_unavailable(&error) {
+                    managed.status = OrderStatus::Rejected;
+                }
+                managed.error = Some(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    /// Cancels a tracked order.
+    ///
+    /// # Returns
+    /// The tracked order, or the error of the exchange. Orders in a final status are not sent to the exchange.
+    ///
+    pub async fn cancel(
+        &self,
+        exchange: &dyn Exchange,
+        client_order_id: &str,
+    ) -> Result<ManagedOrder, String> {
+        let order = self
+            .order(client_order_id)
+            .ok_or_else(|| unknown(client_order_id))?;
+        if order.status.is_final() {
+            return Err(format!(
+                "Order {} is already {}.",
+                client_order_id, order.status
+            ));
+        }
+        let order_id = order.order_id.ok_or_else(|| {
+            format!(
+                "Order {} was not accepted by the exchange.",
+                client_order_id
+            )
+        })?;
+
+        let ack = exchange
+            .cancel_order(&order.request.symbol, order_id)
+            .await?;
+        self.apply_ack(client_order_id, &ack)
+    }
+
+    /// Applies an acknowledgement of the exchange. When the order was executed (e.g., a MARKET order acknowledged
+    /// FILLED), the executed quantity is recorded as a fill before the order becomes final.
+    fn apply_ack(&self, client_order_id: &str, ack: &OrderAck) -> Result<ManagedOrder, String> {
+        let order = self
+            .order(client_order_id)
+            .ok_or_else(|| unknown(client_order_id))?;
+        if ack.executed_qty > order.executed_qty + QUANTITY_TOLERANCE {
+            return self.on_update(&execution_update(
+                &order,
+                ack.order_id,
+                &ack.status,
+                ack.executed_qty,
+                ack.avg_price,
+                ack.position_side,
+                ack.update_time,
+            ));
+        }
+
+        let mut orders = self.orders.lock().unwrap();
+        let order = orders
+            .get_mut(client_order_id)
+            .ok_or_else(|| unknown(client_order_id))?;
+        order.order_id = Some(ack.order_id);
+        order.update_time = ack.update_time;
+        if let Some(position_side) = ack.position_side {
+            order.position_side = position_side;
+        }
+        if let Some(status) = OrderStatus::from_binance(&ack.status) {
+            if status != order.status && order.status.can_become(status) {
+                order.status = status;
+            }
+        }
+        Ok(order.clone())
+    }
+
+    /// Applies an update of an order.
+    ///
+    /// # Returns
+    /// The tracked order, or an error when the order is unknown or the update breaks the state machine.
+    ///
+    pub fn on_update(&self, update: &OrderUpdate) -> Result<ManagedOrder, String> {
+        let status = OrderStatus::from_binance(&update.status)
+            .ok_or_else(|| format!("Unknown order status: {}", update.status))?;
+
+        let (order, fill) = {
+            let mut orders = self.orders.lock().unwrap();
+            let order = orders
+                .get_mut(&update.client_order_id)
+                .ok_or_else(|| unknown(&update.client_order_id))?;
+
+            let repeated = status == order.status && status == OrderStatus::PartiallyFilled;
+            if !repeated && !order.status.can_become(status) {
+                return Err(format!(
+                    "Order {} can not go from {} to {}.",
+                    order.client_order_id, order.status, status
+                ));
+            }
+
+            order.order_id = Some(update.order_id);
+            order.status = status;
+            order.update_time = update.time;
+            if let Some(position_side) = update.position_side {
+                order.position_side = position_side;
+            }
+
+            let fill = (update.last_filled_qty > QUANTITY_TOLERANCE).then(|| {
+                let executed_qty = order.executed_qty + update.last_filled_qty;
+                order.avg_price = (order.avg_price * order.executed_qty
+                    + update.last_filled_price * update.last_filled_qty)
+                    / executed_qty;
+                order.executed_qty = executed_qty;
+                order.fees += update.commission;
+
+                let fill = Fill {
+                    order_id: update.order_id,
+                    symbol: order.request.symbol.clone(),
+                    side: order.request.side,
+                    position_side: order.position_side,
+                    price: update.last_filled_price,
+                    quantity: update.last_filled_qty,
+                    time: update.time,
+                };
+                order.fills.push(fill.clone());
+                fill
+            });
+            (order.clone(), fill)
+        };
+
+        if let (Some(positions), Some(fill)) = (&self.positions, fill) {
+            positions.apply_fill(&fill);
+        }
+        Ok(order)
+    }
+
+    /// Reads the state of a tracked order from the exchange and applies it.
+    ///
+    /// The quantity executed since the last update is recorded as one fill, at the price that explains the new
+    /// average price.
+    ///
+    /// # Returns
+    /// The tracked order, or the error of the request.
+    ///
+    pub async fn refresh(&self, client_order_id: &str) -> Result<ManagedOrder, String> {
+        let order = self
+            .order(client_order_id)
+            .ok_or_else(|| unknown(client_order_id))?;
+        if order.status.is_final() && order.order_id.is_some() {
+            return Ok(order);
+        }
+        let info = query_order(&order.request.symbol, client_order_id).await?;
+        let status = OrderStatus::from_binance(&info.status)
+            .ok_or_else(|| format!("Unknown order status: {}", info.status))?;
+        if status == order.status && info.executed_qty <= order.executed_qty {
+            // Nothing was executed, but the id is learned when the acknowledgement of the order was lost.
+            let mut orders = self.orders.lock().unwrap();
+            let order = orders
+                .get_mut(client_order_id)
+                .ok_or_else(|| unknown(client_order_id))?;
+            order.order_id = Some(info.order_id);
+            order.position_side = info.position_side;
+            return Ok(order.clone());
+        }
+
+        self.on_update(&execution_update(
+            &order,
+            info.order_id,
+            &info.status,
+            info.executed_qty,
+            info.avg_price,
+            Some(info.position_side),
+            info.update_time,
+        ))
+    }
+
+    /// Get a tracked order by its client order id.
+    pub fn order(&self, client_order_id: &str) -> Option<ManagedOrder> {
+        self.orders.lock().unwrap().get(client_order_id).cloned()
+    }
+
+    /// Get the orders placed by a strategy, from the oldest to the newest.
+    pub fn orders_by_strategy(&self, strategy: &str) -> Vec<ManagedOrder> {
+        let mut orders: Vec<ManagedOrder> = self
+            .orders
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|o| o.strategy == strategy)
+            .cloned()
+            .collect();
+        orders.sort_by_key(|o| (o.update_time, o.client_order_id.clone()));
+        orders
+    }
+
+    /// Get the orders that are not in a final status.
+    pub fn open_orders(&self) -> Vec<ManagedOrder> {
+        self.orders
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|o| !o.status.is_final())
+            .cloned()
+            .collect()
+    }
+}
+
+/// Builds the update of an order from its state in the exchange: the quantity executed since the last update is one
+/// fill, at the price that explains the new average price. The fees are not known this way.
+fn execution_update(
+    order: &ManagedOrder,
+    order_id: u64,
+    status: &str,
+    executed_qty: f64,
+    avg_price: f64,
+    position_side: Option<PositionSide>,
+    time: u64,
+) -> OrderUpdate {
+    let last_filled_qty = (executed_qty - order.executed_qty).max(0.0);
+    let last_filled_price = if last_filled_qty > QUANTITY_TOLERANCE {
+        (avg_price * executed_qty - order.avg_price * order.executed_qty) / last_filled_qty
+    } else {
+        0.0
+    };
+    OrderUpdate {
+        client_order_id: order.client_order_id.clone(),
+        order_id,
+        status: status.to_string(),
+        last_filled_qty,
+        last_filled_price,
+        commission: 0.0,
+        position_side,
+        time,
+    }
+}
+
+fn unknown(client_order_id: &str) -> String {
+    format!("Order {} is not tracked.", client_order_id)
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::BinanceExchange;
+    use crate::mock_server;
+    use crate::models::{OrderType, Side};
+    use tokio::test;
+
+    fn limit_order(side: Side, price: f64) -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side,
+            order_type: OrderType::Limit,
+            quantity: 0.002,
+            price: Some(price),
+            stop_price: None,
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        }
+    }
+
+    /// Test an order through NEW and FILLED, with the fill read from the exchange and passed to the positions.
+    #[test]
+    async fn order_manager_refresh_test() {
+        let server = mock_server::isolated();
+        server.set_price(31000.0);
+        let positions = Arc::new(PositionManager::new());
+        let manager = OrderManager::with_position_manager(positions.clone());
+        let exchange = BinanceExchange::new();
+
+        let order = server
+            .run(manager.submit(&exchange, "trend", limit_order(Side::Buy, 30000.0)))
+            .await
+            .unwrap();
+        assert_eq!(order.status, OrderStatus::New);
+        assert!(order.order_id.is_some());
+        assert!(server
+            .requests()
+            .iter()
+            .any(|r| r.contains(&format!("newClientOrderId={}", order.client_order_id))));
+
+        server.set_price(29900.0);
+        let order = server
+            .run(manager.refresh(&order.client_order_id))
+            .await
+            .unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.executed_qty, 0.002);
+        assert!((order.avg_price - 29900.0).abs() < 1e-6);
+        assert_eq!(order.fills.len(), 1);
+        assert_eq!(
+            positions.position("BTCUSDT", PositionSide::Both).amount,
+            0.002
+        );
+
+        assert_eq!(manager.orders_by_strategy("trend").len(), 1);
+        assert!(manager.orders_by_strategy("other").is_empty());
+        assert!(manager.open_orders().is_empty());
+    }
+
+    /// Test that in hedge mode the fills of an order sent with BOTH are recorded with the position side it was sent
+    /// with, so the local positions reconcile with the exchange ones.
+    #[test]
+    async fn order_manager_hedge_mode_reconcile_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(31000.0);
+        let positions = Arc::new(PositionManager::new());
+        let manager = OrderManager::with_position_manager(positions.clone());
+        let exchange = BinanceExchange::new();
+
+        let order = server
+            .run(manager.submit(&exchange, "trend", limit_order(Side::Buy, 30000.0)))
+            .await
+            .unwrap();
+        assert_eq!(order.request.position_side, PositionSide::Both);
+        assert_eq!(order.position_side, PositionSide::Long);
+
+        server.set_price(29900.0);
+        let order = server
+            .run(manager.refresh(&order.client_order_id))
+            .await
+            .unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.fills[0].position_side, PositionSide::Long);
+        assert_eq!(
+            positions.position("BTCUSDT", PositionSide::Long).amount,
+            0.002
+        );
+
+        let drifts = server
+            .run(positions.reconcile(&exchange, "BTCUSDT"))
+            .await
+            .unwrap();
+        assert!(drifts.is_empty());
+    }
+
+    /// Test that a MARKET order acknowledged FILLED records its fill and passes it to the positions.
+    #[test]
+    async fn order_manager_market_fill_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        let positions = Arc::new(PositionManager::new());
+        let manager = OrderManager::with_position_manager(positions.clone());
+        let exchange = BinanceExchange::new();
+
+        let mut order = limit_order(Side::Buy, 0.0);
+        order.order_type = OrderType::Market;
+        order.price = None;
+        let order = server
+            .run(manager.submit(&exchange, "trend", order))
+            .await
+            .unwrap();
+
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.executed_qty, 0.002);
+        assert!((order.avg_price - 30000.0).abs() < 1e-6);
+        assert_eq!(order.fills.len(), 1);
+        assert_eq!(
+            positions.position("BTCUSDT", PositionSide::Both).amount,
+            0.002
+        );
+        assert!(server.requests().iter().any(
+            |r| r.starts_with("POST /fapi/v1/order?") && r.contains("newOrderRespType=RESULT")
+        ));
+    }
+
+    /// Test the partial fills, the fees and the refused transitions of the state machine.
+    #[test]
+    async fn order_manager_updates_test() {
+        let server = mock_server::isolated();
+        server.set_price(31000.0);
+        let manager = OrderManager::new();
+        let exchange = BinanceExchange::new();
+
+        let order = server
+            .run(manager.submit(&exchange, "grid", limit_order(Side::Buy, 30000.0)))
+            .await
+            .unwrap();
+        let update = |status: &str, qty: f64, price: f64| OrderUpdate {
+            client_order_id: order.client_order_id.clone(),
+            order_id: order.order_id.unwrap(),
+            status: status.to_string(),
+            last_filled_qty: qty,
+            last_filled_price: price,
+            commission: qty * price * 0.0002,
+            position_side: None,
+            time: now(),
+        };
+
+        manager
+            .on_update(&update("PARTIALLY_FILLED", 0.001, 30000.0))
+            .unwrap();
+        let filled = manager
+            .on_update(&update("FILLED", 0.001, 29000.0))
+            .unwrap();
+        assert_eq!(filled.status, OrderStatus::Filled);
+        assert!((filled.avg_price - 29500.0).abs() < 1e-6);
+        assert!((filled.fees - 0.0118).abs() < 1e-9);
+        assert_eq!(filled.fills.len(), 2);
+
+        assert!(manager.on_update(&update("NEW", 0.0, 0.0)).is_err());
+        assert!(manager.on_update(&update("CANCELED", 0.0, 0.0)).is_err());
+        assert!(server
+            .run(manager.cancel(&exchange, &order.client_order_id))
+            .await
+            .is_err());
+
+        // There is no position to reduce, so a reduce only order is rejected.
+        let mut order = limit_order(Side::Buy, 30000.0);
+        order.reduce_only = true;
+        let rejected = server.run(manager.submit(&exchange, "grid", order)).await;
+        assert!(rejected.is_err());
+        assert!(manager
+            .orders_by_strategy("grid")
+            .iter()
+            .any(|o| o.status == OrderStatus::Rejected && o.error.is_some()));
+    }
+
+    /// Test that an order whose response can not be read stays NEW, and that it is found by `refresh` and filled.
+    #[test]
+    async fn order_manager_unreadable_response_test() {
+        let server = mock_server::isolated();
+        server.set_price(31000.0);
+        let manager = OrderManager::new();
+        let exchange = BinanceExchange::new();
+        server.inject_fault("/fapi/v1/order", mock_server::Fault::MalformedBody);
+
+        let res = server
+            .run(manager.submit(&exchange, "trend", limit_order(Side::Buy, 30000.0)))
+            .await;
+        let error = res.unwrap_err();
+        assert!(is_exchange_unavailable(&error));
+        let order = manager.orders_by_strategy("trend").remove(0);
+        assert_eq!(order.status, OrderStatus::New);
+        assert_eq!(order.error, Some(error));
+
+        let order = server
+            .run(manager.refresh(&order.client_order_id))
+            .await
+            .unwrap();
+        assert!(order.order_id.is_some());
+        assert_eq!(order.status, OrderStatus::New);
+
+        server.set_price(29900.0);
+        let order = server
+            .run(manager.refresh(&order.client_order_id))
+            .await
+            .unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.executed_qty, 0.002);
+    }
+}
@@ -0,0 +1,245 @@
+// paper.rs - Paper Trading Exchange
+
+// This file contains the `PaperExchange`, an implementation of the `Exchange` trait that simulates the orders against
+// live market data of the Binance futures exchange, without sending any order to it.
+
+// The live prices come from one of the sources of `PriceSource` (last price, mark price or best bid/ask) and feed the
+// matching engine of simulator.rs, which accepts or rejects the orders, triggers the stops, fills with the configured
+// slippage and fees and tracks the positions and the pnl.
+
+// Unlike the Binance testnet, the fills follow the real market prices, so the results are close to what the strategy
+// would get in live trading.
+
+// Only the orders sent through the `Exchange` trait of a `PaperExchange` are simulated. The free functions of
+// binance_orders.rs (e.g., `new_order`, `place_order` or `close_position`) always go to the live exchange, so a
+// strategy traded on paper must use the `Exchange` trait only.
+
+use crate::binance_orders::{book_ticker, last_price, mark_price, spawn_scoped};
+use crate::exchange::Exchange;
+use crate::get_candles::get_klines;
+use crate::models::{Balance, KlineData, OpenOrder, OrderAck, OrderRequest, PositionRisk};
+use crate::simulator::{SimFill, Simulator, SimulatorConfig};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Live price used to drive the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceSource {
+    /// Last traded price (/fapi/v1/ticker/price).
+    LastPrice,
+    /// Mark price (/fapi/v1/premiumIndex).
+    MarkPrice,
+    /// Best bid and ask (/fapi/v1/ticker/bookTicker). Market orders take the other side of the book.
+    BookTicker,
+}
+
+/// `Exchange` implementation that simulates the orders against live prices.
+pub struct PaperExchange {
+    source: PriceSource,
+    simulator: Mutex<Simulator>,
+}
+
+impl PaperExchange {
+    /// Creates a paper trading exchange.
+    ///
+    /// # Arguments
+    /// * `config`: The initial balance, fees and slippage of the simulation.
+    /// * `source`: The live price used to accept, trigger and fill the orders.
+    ///
+    pub fn new(config: SimulatorConfig, source: PriceSource) -> Self {
+        PaperExchange {
+            source,
+            simulator: Mutex::new(Simulator::new(config)),
+        }
+    }
+
+    /// Fetches the live price of a symbol and feeds it to the simulation.
+    ///
+    /// The orders reached by the new price are filled. It is called before every order and query, and can be called
+    /// periodically (see `spawn_price_feed`) so that the stops trigger even when the strategy is idle.
+    ///
+    pub async fn refresh(&self, symbol: &str) -> Result<(), String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        match self.source {
+            PriceSource::LastPrice => {
+                let price = last_price(symbol).await?;
+                let mut simulator = self.simulator.lock().unwrap();
+                simulator.set_time(now);
+                simulator.on_price(symbol, price);
+            }
+            PriceSource::MarkPrice => {
+                let price = mark_price(symbol).await?;
+                let mut simulator = self.simulator.lock().unwrap();
+                simulator.set_time(now);
+                simulator.on_price(symbol, price);
+            }
+            PriceSource::BookTicker => {
+                let ticker = book_ticker(symbol).await?;
+                let mut simulator = self.simulator.lock().unwrap();
+                simulator.set_time(now);
+                simulator.on_quote(symbol, ticker.bid_price, ticker.ask_price);
+            }
+        }
+        Ok(())
+    }
+
+    /// Spawns a task that refreshes the price of a symbol every `interval`, with the exchange url and the account of
+    /// the caller (see `spawn_scoped`).
+    pub fn spawn_price_feed(
+        self: Arc<Self>,
+        symbol: String,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        spawn_scoped(async move {
+            loop {
+                let _ = self.refresh(&symbol).await;
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+
+    /// Every fill done since the beginning of the paper trading.
+    pub fn fills(&self) -> Vec<SimFill> {
+        self.simulator.lock().unwrap().fills().to_vec()
+    }
+
+    /// Realized pnl (without fees) since the beginning of the paper trading.
+    pub fn realized_pnl(&self) -> f64 {
+        self.simulator.lock().unwrap().realized_pnl()
+    }
+
+    /// Fees paid since the beginning of the paper trading.
+    pub fn fees_paid(&self) -> f64 {
+        self.simulator.lock().unwrap().fees_paid()
+    }
+}
+
+#[async_trait]
+impl Exchange for PaperExchange {
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderAck, String> {
+        self.refresh(&order.symbol).await?;
+        self.simulator.lock().unwrap().submit(order)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<OrderAck, String> {
+        self.refresh(symbol).await?;
+        self.simulator.lock().unwrap().cancel(symbol, order_id)
+    }
+
+    async fn amend_order(
+        &self,
+        symbol: &str,
+        order_id: u64,
+        quantity: f64,
+        price: f64,
+    ) -> Result<OrderAck, String> {
+        self.refresh(symbol).await?;
+        self.simulator
+            .lock()
+            .unwrap()
+            .amend(symbol, order_id, quantity, price)
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<OpenOrder>, String> {
+        self.refresh(symbol).await?;
+        Ok(self.simulator.lock().unwrap().open_orders(symbol))
+    }
+
+    async fn positions(&self, symbol: &str) -> Result<Vec<PositionRisk>, String> {
+        self.refresh(symbol).await?;
+        Ok(self.simulator.lock().unwrap().positions(symbol))
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>, String> {
+        // The unrealized pnl of the balance is calculated with the prices of every traded symbol.
+        let symbols = self.simulator.lock().unwrap().symbols();
+        for symbol in symbols {
+            self.refresh(&symbol).await?;
+        }
+        Ok(self.simulator.lock().unwrap().balances())
+    }
+
+    async fn candles(
+        &self,
+        symbol: &str,
+        interval: &str,
+        limit: usize,
+    ) -> Result<Vec<KlineData>, String> {
+        get_klines(symbol, interval, limit).await
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ORDER_WOULD_TRIGGER_IMMEDIATELY;
+    use crate::mock_server;
+    use crate::models::{OrderType, PositionSide, Side};
+    use tokio::test;
+
+    /// Test a paper trade against the live price.
+    ///
+    /// This test places a market order and checks that the position is opened, then checks that a stop order
+    /// below the market price is rejected like in the exchange.
+    ///
+    #[test]
+    async fn paper_exchange_market_order_test() {
+        mock_server::shared();
+        let exchange = PaperExchange::new(SimulatorConfig::default(), PriceSource::LastPrice);
+
+        let mut order = OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            quantity: 0.001,
+            price: None,
+            stop_price: None,
+            position_side: PositionSide::Long,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+        let res = exchange.place_order(&order).await.unwrap();
+        assert_eq!(res.status, "FILLED");
+
+        let positions = exchange.positions("BTCUSDT").await.unwrap();
+        assert_eq!(positions[0].position_amt, 0.001);
+
+        order.order_type = OrderType::StopMarket;
+        order.stop_price = Some(1.0);
+        let res = exchange.place_order(&order).await;
+        assert_eq!(res.unwrap_err(), ORDER_WOULD_TRIGGER_IMMEDIATELY);
+    }
+
+    /// Test that the balances are calculated with the live price, not with the price of the last order.
+    #[test]
+    async fn paper_exchange_balances_refresh_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        let exchange = PaperExchange::new(SimulatorConfig::default(), PriceSource::LastPrice);
+
+        let order = OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Market,
+            quantity: 0.001,
+            price: None,
+            stop_price: None,
+            position_side: PositionSide::Long,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+        server.run(exchange.place_order(&order)).await.unwrap();
+
+        server.set_price(31000.0);
+        let balances = server.run(exchange.balances()).await.unwrap();
+        assert!((balances[0].cross_un_pnl - 1.0).abs() < 1e-9);
+    }
+}
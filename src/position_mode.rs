@@ -0,0 +1,397 @@
+// position_mode.rs - Position Mode
+
+// This file contains the position mode of the account (one-way or hedge mode) and the position side and reduce only
+// parameters that the orders must send in each mode.
+
+// The mode is read with GET /fapi/v1/positionSide/dual and cached per exchange (per account with accounts.rs, see
+// `exchange_key`), so the orders do not query it each time. `set_position_mode` only sends
+// POST /fapi/v1/positionSide/dual when the account is not already in the requested mode. When an order is rejected
+// because its position side does not match the mode (E17, e.g., the mode was changed in the Binance UI), the cache is
+// cleared with `invalidate_position_mode` and the order is sent again, once: a second E17 is returned.
+
+// Parameters of the orders:
+// - One-way mode: positionSide=BOTH, and reduceOnly=true for the orders that can only reduce the position.
+// - Hedge mode: positionSide=LONG or SHORT, and never reduceOnly (the exchange rejects it). When the caller does not
+//   choose the side, it is the side opened by the order (BUY -> LONG, SELL -> SHORT), or the side closed by it for
+//   reduce only orders (BUY -> SHORT, SELL -> LONG). A reduce only order whose side would increase the chosen
+//   position side (e.g., SELL of the SHORT position) is refused with E22, as it can not be sent as reduce only.
+
+use crate::binance_orders::{exchange_key, resend_backoff, MAX_SEND_ATTEMPTS};
+use crate::error::{
+    error_handler, DNS_ERROR, ERROR_MALFORMED_RESPONSE, RECVWINDOW_ERROR, REDUCE_ONLY_SIDE_MISMATCH,
+};
+use crate::models::{PositionSide, Side};
+use crate::request::BinanceRequest;
+use async_recursion::async_recursion;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Position mode of the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionMode {
+    /// One position per symbol (positionSide=BOTH).
+    OneWay,
+    /// A long and a short position per symbol (dual side position).
+    Hedge,
+}
+
+/// Cached position mode per exchange key.
+static POSITION_MODES: RwLock<Option<HashMap<String, PositionMode>>> = RwLock::new(None);
+
+fn cached_mode(key: &str) -> Option<PositionMode> {
+    POSITION_MODES
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|modes| modes.get(key).copied())
+}
+
+fn cache_mode(key: String, mode: PositionMode) {
+    POSITION_MODES
+        .write()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, mode);
+}
+
+/// Clears the cached position mode, so the next call to `get_position_mode` reads it from the exchange.
+pub async fn invalidate_position_mode() {
+    let key = exchange_key().await;
+    if let Some(modes) = POSITION_MODES.write().unwrap().as_mut() {
+        modes.remove(&key);
+    }
+}
+
+/// Reads the position mode from the exchange and caches it. A request rejected with E07 or E08 is sent again, at
+/// most MAX_SEND_ATTEMPTS times in total.
+pub async fn refresh_position_mode() -> Result<PositionMode, String> {
+    refresh_position_mode_attempt(1).await
+}
+
+#[async_recursion]
+async fn refresh_position_mode_attempt(attempt: u32) -> Result<PositionMode, String> {
+    let result = BinanceRequest::get("/fapi/v1/positionSide/dual")
+        .signed()
+        .send()
+        .await;
+
+    if result.status() == StatusCode::OK {
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        let mode = match data["dualSidePosition"].as_bool() {
+            Some(true) => PositionMode::Hedge,
+            Some(false) => PositionMode::OneWay,
+            None => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        cache_mode(exchange_key().await, mode);
+        Ok(mode)
+    } else {
+        let error = error_handler(result, None).await;
+        if (error == DNS_ERROR || error == RECVWINDOW_ERROR) && attempt < MAX_SEND_ATTEMPTS {
+            tokio::time::sleep(resend_backoff(attempt + 1)).await;
+            refresh_position_mode_attempt(attempt + 1).await
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Get the position mode of the account, from the cache or from the exchange.
+pub async fn get_position_mode() -> Result<PositionMode, String> {
+    match cached_mode(&exchange_key().await) {
+        Some(mode) => Ok(mode),
+        None => refresh_position_mode().await,
+    }
+}
+
+/// Changes the position mode of the account, when it is not already in that mode.
+///
+/// A request rejected with E07 or E08 is sent again, at most MAX_SEND_ATTEMPTS times in total.
+///
+/// # Returns
+/// True when the mode was changed, false when the account was already in the mode, or the mapped error.
+///
+pub async fn set_position_mode(mode: PositionMode) -> Result<bool, String> {
+    set_position_mode_attempt(mode, 1).await
+}
+
+#[async_recursion]
+async fn set_position_mode_attempt(mode: PositionMode, attempt: u32) -> Result<bool, String> {
+    if get_position_mode().await? == mode {
+        return Ok(false);
+    }
+
+    let result = BinanceRequest::post("/fapi/v1/positionSide/dual")
+        .param("dualSidePosition", mode == PositionMode::Hedge)
+        .signed()
+        .send()
+        .await;
+
+    if result.status() == StatusCode::OK {
+        cache_mode(exchange_key().await, mode);
+        Ok(true)
+    } else {
+        let error = error_handler(result, None).await;
+        if (error == DNS_ERROR || error == RECVWINDOW_ERROR) && attempt < MAX_SEND_ATTEMPTS {
+            tokio::time::sleep(resend_backoff(attempt + 1)).await;
+            set_position_mode_attempt(mode, attempt + 1).await
+        } else {
+            // The cached mode may be stale (e.g., E06 after a change in the Binance UI).
+            invalidate_position_mode().await;
+            Err(error)
+        }
+    }
+}
+
+/// Get the positionSide and reduceOnly parameters of an order in a position mode (see the header of this file).
+///
+/// # Arguments
+/// * `mode`: The position mode of the account.
+/// * `side`: The side of the order.
+/// * `position_side`: The position side chosen by the caller, used only in hedge mode (BOTH lets it be derived).
+/// * `reduce_only`: Whether the order can only reduce the position.
+///
+/// # Returns
+/// The positionSide and, when it must be sent, the reduceOnly parameter, or E22 when a reduce only order would
+/// increase the position side chosen in hedge mode.
+///
+pub fn position_side_params(
+    mode: PositionMode,
+    side: Side,
+    position_side: PositionSide,
+    reduce_only: bool,
+) -> Result<(PositionSide, Option<bool>), String> {
+    match (mode, position_side) {
+        (PositionMode::OneWay, _) => Ok((PositionSide::Both, reduce_only.then_some(true))),
+        (PositionMode::Hedge, PositionSide::Both) if reduce_only => {
+            Ok((PositionSide::opened_by(side.opposite()), None))
+        }
+        (PositionMode::Hedge, PositionSide::Both) => Ok((PositionSide::opened_by(side), None)),
+        (PositionMode::Hedge, position_side)
+            if reduce_only && position_side == PositionSide::opened_by(side) =>
+        {
+            Err(REDUCE_ONLY_SIDE_MISMATCH.to_string())
+        }
+        (PositionMode::Hedge, position_side) => Ok((position_side, None)),
+    }
+}
+
+/// Get the positionSide and reduceOnly parameters of an order in the current position mode of the account.
+pub async fn order_position_side(
+    side: Side,
+    position_side: PositionSide,
+    reduce_only: bool,
+) -> Result<(PositionSide, Option<bool>), String> {
+    let mode = get_position_mode().await?;
+    position_side_params(mode, side, position_side, reduce_only)
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_orders::{new_order_limit, place_order};
+    use crate::error::POSITION_SIDE_MISMATCH;
+    use crate::mock_server;
+    use crate::models::{OrderRequest, OrderType};
+    use tokio::test;
+
+    /// Test the parameters of the orders in each mode.
+    #[test]
+    async fn position_side_params_test() {
+        use PositionMode::*;
+
+        assert_eq!(
+            position_side_params(OneWay, Side::Sell, PositionSide::Long, true),
+            Ok((PositionSide::Both, Some(true)))
+        );
+        assert_eq!(
+            position_side_params(OneWay, Side::Buy, PositionSide::Both, false),
+            Ok((PositionSide::Both, None))
+        );
+        assert_eq!(
+            position_side_params(Hedge, Side::Buy, PositionSide::Both, false),
+            Ok((PositionSide::Long, None))
+        );
+        assert_eq!(
+            position_side_params(Hedge, Side::Buy, PositionSide::Both, true),
+            Ok((PositionSide::Short, None))
+        );
+        assert_eq!(
+            position_side_params(Hedge, Side::Buy, PositionSide::Short, true),
+            Ok((PositionSide::Short, None))
+        );
+        assert_eq!(
+            position_side_params(Hedge, Side::Sell, PositionSide::Long, false),
+            Ok((PositionSide::Long, None))
+        );
+        // A reduce only order can not increase the position side it names.
+        assert_eq!(
+            position_side_params(Hedge, Side::Sell, PositionSide::Short, true),
+            Err(REDUCE_ONLY_SIDE_MISMATCH.to_string())
+        );
+        assert_eq!(
+            position_side_params(Hedge, Side::Buy, PositionSide::Long, true),
+            Err(REDUCE_ONLY_SIDE_MISMATCH.to_string())
+        );
+    }
+
+    /// Test that the mode is read once and that it is only changed when needed.
+    #[test]
+    async fn position_mode_cache_test() {
+        let server = mock_server::isolated();
+        let count = |method: &str| {
+            server
+                .requests()
+                .iter()
+                .filter(|r| r.starts_with(&format!("{} /fapi/v1/positionSide/dual", method)))
+                .count()
+        };
+
+        assert_eq!(
+            server.run(get_position_mode()).await.unwrap(),
+            PositionMode::OneWay
+        );
+        assert_eq!(
+            server.run(get_position_mode()).await.unwrap(),
+            PositionMode::OneWay
+        );
+        assert_eq!(count("GET"), 1);
+
+        assert!(server
+            .run(set_position_mode(PositionMode::Hedge))
+            .await
+            .unwrap());
+        assert!(!server
+            .run(set_position_mode(PositionMode::Hedge))
+            .await
+            .unwrap());
+        assert_eq!(count("POST"), 1);
+        assert_eq!(
+            server.run(get_position_mode()).await.unwrap(),
+            PositionMode::Hedge
+        );
+
+        // A change made outside of the crate is seen after the cache is cleared.
+        server.set_dual_side_position(false);
+        server.run(invalidate_position_mode()).await;
+        assert_eq!(
+            server.run(get_position_mode()).await.unwrap(),
+            PositionMode::OneWay
+        );
+        assert_eq!(count("GET"), 2);
+    }
+
+    /// Test that an order sent with a stale mode clears the cache and is sent again with the right position side.
+    #[test]
+    async fn stale_position_mode_test() {
+        let server = mock_server::isolated();
+        server.set_price(31000.0);
+        assert_eq!(
+            server.run(get_position_mode()).await.unwrap(),
+            PositionMode::OneWay
+        );
+
+        // The mode is changed in the Binance UI.
+        server.set_dual_side_position(true);
+        let order = OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            quantity: 0.002,
+            price: Some(30000.0),
+            stop_price: None,
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+        server.run(place_order(&order)).await.unwrap();
+
+        assert_eq!(
+            server.run(get_position_mode()).await.unwrap(),
+            PositionMode::Hedge
+        );
+        let orders: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .collect();
+        assert_eq!(orders.len(), 2);
+        assert!(orders[0].contains("positionSide=BOTH"));
+        assert!(orders[1].contains("positionSide=LONG"));
+    }
+
+    /// Test that an order still rejected with E17 once the mode was read again is not sent a third time.
+    #[test]
+    async fn position_side_mismatch_attempts_test() {
+        let server = mock_server::isolated();
+        server.set_price(31000.0);
+        for _ in 0..4 {
+            server.inject_error(
+                "/fapi/v1/order",
+                400,
+                -4061,
+                "Order's position side does not match user's setting.",
+            );
+        }
+        let order = OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            quantity: 0.002,
+            price: Some(30000.0),
+            stop_price: None,
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        };
+
+        let placed = server.run(place_order(&order)).await;
+        let legacy = server
+            .run(new_order_limit(
+                30000.0,
+                &mut 0,
+                Side::Buy,
+                PositionSide::Both,
+            ))
+            .await;
+
+        assert_eq!(placed.unwrap_err(), POSITION_SIDE_MISMATCH);
+        assert_eq!(legacy, POSITION_SIDE_MISMATCH);
+        let orders = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .count();
+        assert_eq!(orders, 4);
+    }
+
+    /// Test that a position mode that keeps failing with a dns error is read at most MAX_SEND_ATTEMPTS times.
+    #[test]
+    async fn refresh_position_mode_attempts_test() {
+        let server = mock_server::isolated();
+        for _ in 0..MAX_SEND_ATTEMPTS + 1 {
+            server.inject_error(
+                "/fapi/v1/positionSide/dual",
+                400,
+                -1000,
+                "No such host is known.",
+            );
+        }
+
+        let res = server.run(refresh_position_mode()).await;
+
+        assert_eq!(res.unwrap_err(), DNS_ERROR);
+        let reads = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("GET /fapi/v1/positionSide/dual"))
+            .count();
+        assert_eq!(reads, MAX_SEND_ATTEMPTS as usize);
+    }
+}
@@ -0,0 +1,397 @@
+// positions.rs - Position Manager
+
+// This file contains the `PositionManager`, that keeps the positions of the account locally, per symbol and position
+// side, instead of reading them from the exchange after every order.
+
+// The amount and the entry price of a position are computed from the fills of the orders (`apply_fill`), with the
+// same rules as the exchange:
+// - A fill in the direction of the position increases it, and the entry price becomes the average price.
+// - A fill against the position reduces it, keeping the entry price.
+// - In one-way mode (BOTH), a fill bigger than the position flips it, with the fill price as the entry price.
+// - A fill without quantity is ignored.
+// The amounts are signed like in /fapi/v2/positionRisk: positive for long and negative for short positions.
+
+// The local state is reconciled periodically with /fapi/v2/positionRisk (`reconcile`, `spawn_reconciliation`). The
+// fields that only the exchange knows (mark price, unrealized profit, liquidation price, leverage and margin type)
+// are copied from it. When the amount or the entry price disagree, a `PositionDrift` alert is logged and sent to the
+// subscribers of `subscribe_drift_alerts`, and the exchange state replaces the local one.
+
+use crate::binance_orders::spawn_scoped;
+use crate::exchange::Exchange;
+use crate::models::{Fill, PositionRisk, PositionSide};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Difference between the local and the exchange amounts that is not considered a drift.
+const AMOUNT_TOLERANCE: f64 = 1e-8;
+/// Relative difference between the local and the exchange entry prices that is not considered a drift.
+const ENTRY_PRICE_TOLERANCE: f64 = 1e-4;
+/// Number of alerts kept for the subscribers that are behind.
+const ALERT_CHANNEL_CAPACITY: usize = 64;
+
+/// A position of one symbol and position side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub symbol: String,
+    /// BOTH in one-way mode, LONG or SHORT in hedge mode.
+    pub position_side: PositionSide,
+    /// Signed amount: positive for long and negative for short positions.
+    pub amount: f64,
+    pub entry_price: f64,
+    pub mark_price: f64,
+    pub unrealized_pnl: f64,
+    pub liquidation_price: f64,
+    pub leverage: f64,
+    pub margin_type: String,
+}
+
+impl Position {
+    fn new(symbol: &str, position_side: PositionSide) -> Self {
+        Position {
+            symbol: symbol.to_string(),
+            position_side,
+            amount: 0.0,
+            entry_price: 0.0,
+            mark_price: 0.0,
+            unrealized_pnl: 0.0,
+            liquidation_price: 0.0,
+            leverage: 0.0,
+            margin_type: String::new(),
+        }
+    }
+
+    fn update_unrealized_pnl(&mut self) {
+        self.unrealized_pnl = if self.amount == 0.0 || self.mark_price == 0.0 {
+            0.0
+        } else {
+            self.amount * (self.mark_price - self.entry_price)
+        };
+    }
+}
+
+/// Disagreement between the local and the exchange state of a position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionDrift {
+    pub symbol: String,
+    pub position_side: PositionSide,
+    pub local_amount: f64,
+    pub exchange_amount: f64,
+    pub local_entry_price: f64,
+    pub exchange_entry_price: f64,
+}
+
+/// Local state of the positions, built from the fills and reconciled with the exchange.
+pub struct PositionManager {
+    positions: Mutex<HashMap<(String, PositionSide), Position>>,
+    alerts: broadcast::Sender<PositionDrift>,
+}
+
+impl Default for PositionManager {
+    fn default() -> Self {
+        PositionManager::new()
+    }
+}
+
+impl PositionManager {
+    pub fn new() -> Self {
+        PositionManager {
+            positions: Mutex::new(HashMap::new()),
+            alerts: broadcast::channel(ALERT_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Subscribes to the drift alerts raised by the reconciliations.
+    pub fn subscribe_drift_alerts(&self) -> broadcast::Receiver<PositionDrift> {
+        self.alerts.subscribe()
+    }
+
+    /// Get the position of a symbol and position side (an empty position when there is none).
+    pub fn position(&self, symbol: &str, position_side: PositionSide) -> Position {
+        self.positions
+            .lock()
+            .unwrap()
+            .get(&(symbol.to_string(), position_side))
+            .cloned()
+            .unwrap_or_else(|| Position::new(symbol, position_side))
+    }
+
+    /// Get the open positions (amount different from 0).
+    pub fn positions(&self) -> Vec<Position> {
+        self.positions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|p| p.amount.abs() > AMOUNT_TOLERANCE)
+            .cloned()
+            .collect()
+    }
+
+    /// Updates a position with the fill of an order. A fill without quantity (at or below AMOUNT_TOLERANCE) leaves
+    /// the position unchanged.
+    ///
+    /// # Returns
+    /// The position after the fill.
+    ///
+    pub fn apply_fill(&self, fill: &Fill) -> Position {
+        let mut positions = self.positions.lock().unwrap();
+        let position = positions
+            .entry((fill.symbol.clone(), fill.position_side))
+            .or_insert_with(|| Position::new(&fill.symbol, fill.position_side));
+        if fill.quantity <= AMOUNT_TOLERANCE {
+            return position.clone();
+        }
+
+        let delta = fill.side.sign() * fill.quantity;
+        let amount = position.amount + delta;
+
+        if position.amount == 0.0 || position.amount.signum() == delta.signum() {
+            // Increase: average entry price.
+            position.entry_price = (position.amount.abs() * position.entry_price
+                + fill.quantity * fill.price)
+                / amount.abs();
+        } else if amount.abs() <= AMOUNT_TOLERANCE {
+            position.entry_price = 0.0;
+        } else if amount.signum() != position.amount.signum() {
+            // The fill closed the position and opened one in the other direction.
+            position.entry_price = fill.price;
+        }
+        position.amount = if amount.abs() <= AMOUNT_TOLERANCE {
+            0.0
+        } else {
+            amount
+        };
+        position.update_unrealized_pnl();
+        position.clone()
+    }
+
+    /// Updates the mark price (and the unrealized profit) of the positions of a symbol.
+    pub fn update_mark_price(&self, symbol: &str, mark_price: f64) {
+        for position in self.positions.lock().unwrap().values_mut() {
+            if position.symbol == symbol {
+                position.mark_price = mark_price;
+                position.update_unrealized_pnl();
+            }
+        }
+    }
+
+    /// Compares the local positions of a symbol with the exchange ones and adopts the exchange state.
+    ///
+    /// # Arguments
+    /// * `symbol`: The symbol of the positions.
+    /// * `exchange_positions`: The positions of the exchange (from /fapi/v2/positionRisk).
+    ///
+    /// # Returns
+    /// The positions whose amount or entry price disagreed. Each one is also logged and sent to the subscribers of
+    /// the drift alerts.
+    ///
+    pub fn reconcile_positions(
+        &self,
+        symbol: &str,
+        exchange_positions: &[PositionRisk],
+    ) -> Vec<PositionDrift> {
+        let mut drifts = Vec::new();
+        let mut positions = self.positions.lock().unwrap();
+
+        // Local positions that the exchange does not report are closed.
+        for ((position_symbol, position_side), position) in positions.iter_mut() {
+            if position_symbol == symbol
+                && position.amount != 0.0
+                && !exchange_positions
+                    .iter()
+                    .any(|p| p.symbol == symbol && p.position_side == *position_side)
+            {
+                drifts.push(PositionDrift {
+                    symbol: symbol.to_string(),
+                    position_side: *position_side,
+                    local_amount: position.amount,
+                    exchange_amount: 0.0,
+                    local_entry_price: position.entry_price,
+                    exchange_entry_price: 0.0,
+                });
+                position.amount = 0.0;
+                position.entry_price = 0.0;
+                position.update_unrealized_pnl();
+            }
+        }
+
+        for exchange in exchange_positions.iter().filter(|p| p.symbol == symbol) {
+            let position = positions
+                .entry((symbol.to_string(), exchange.position_side))
+                .or_insert_with(|| Position::new(symbol, exchange.position_side));
+
+            let amount_drift = (position.amount - exchange.position_amt).abs() > AMOUNT_TOLERANCE;
+            let entry_price_drift = exchange.position_amt != 0.0
+                && (position.entry_price - exchange.entry_price).abs()
+                    > exchange.entry_price * ENTRY_PRICE_TOLERANCE;
+            if amount_drift || entry_price_drift {
+                drifts.push(PositionDrift {
+                    symbol: symbol.to_string(),
+                    position_side: exchange.position_side,
+                    local_amount: position.amount,
+                    exchange_amount: exchange.position_amt,
+                    local_entry_price: position.entry_price,
+                    exchange_entry_price: exchange.entry_price,
+                });
+            }
+
+            position.amount = exchange.position_amt;
+            position.entry_price = exchange.entry_price;
+            position.mark_price = exchange.mark_price;
+            position.unrealized_pnl = exchange.un_realized_profit;
+            position.liquidation_price = exchange.liquidation_price;
+            position.leverage = exchange.leverage;
+            position.margin_type = exchange.margin_type.clone();
+        }
+
+        for drift in &drifts {
+            warn!(
+                symbol = %drift.symbol,
+                position_side = %drift.position_side,
+                local_amount = drift.local_amount,
+                exchange_amount = drift.exchange_amount,
+                local_entry_price = drift.local_entry_price,
+                exchange_entry_price = drift.exchange_entry_price,
+                "The local position drifted from the exchange one"
+            );
+            // Sending only fails when nobody is subscribed.
+            let _ = self.alerts.send(drift.clone());
+        }
+        drifts
+    }
+
+    /// Reads the positions of a symbol from the exchange and reconciles them with the local ones.
+    ///
+    /// # Returns
+    /// The drifts found (see `reconcile_positions`), or the error of the request.
+    ///
+    pub async fn reconcile(
+        &self,
+        exchange: &dyn Exchange,
+        symbol: &str,
+    ) -> Result<Vec<PositionDrift>, String> {
+        let exchange_positions = exchange.positions(symbol).await?;
+        Ok(self.reconcile_positions(symbol, &exchange_positions))
+    }
+
+    /// Spawns a task that reconciles the positions of a symbol every `interval`, in the account of the caller (see
+    /// accounts.rs). Failed reconciliations are skipped.
+    pub fn spawn_reconciliation(
+        self: Arc<Self>,
+        exchange: Arc<dyn Exchange>,
+        symbol: String,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        spawn_scoped(async move {
+            loop {
+                let _ = self.reconcile(exchange.as_ref(), &symbol).await;
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchange::BinanceExchange;
+    use crate::mock_server;
+    use crate::models::Side;
+    use tokio::test;
+
+    fn fill(side: Side, position_side: PositionSide, price: f64, quantity: f64) -> Fill {
+        Fill {
+            order_id: 1,
+            symbol: "BTCUSDT".to_string(),
+            side,
+            position_side,
+            price,
+            quantity,
+            time: 0,
+        }
+    }
+
+    /// Test the amount and entry price of the positions after increasing, reducing and flipping them, and after empty
+    /// fills.
+    #[test]
+    async fn apply_fill_test() {
+        let manager = PositionManager::new();
+
+        let position = manager.apply_fill(&fill(Side::Buy, PositionSide::Both, 30000.0, 0.0));
+        assert_eq!(position.amount, 0.0);
+        assert_eq!(position.entry_price, 0.0);
+
+        manager.apply_fill(&fill(Side::Buy, PositionSide::Both, 30000.0, 0.002));
+        let position = manager.apply_fill(&fill(Side::Buy, PositionSide::Both, 33000.0, 0.001));
+        assert!((position.amount - 0.003).abs() < 1e-12);
+        assert!((position.entry_price - 31000.0).abs() < 1e-6);
+
+        let position = manager.apply_fill(&fill(Side::Sell, PositionSide::Both, 35000.0, 0.0));
+        assert!((position.amount - 0.003).abs() < 1e-12);
+        assert!((position.entry_price - 31000.0).abs() < 1e-6);
+
+        let position = manager.apply_fill(&fill(Side::Sell, PositionSide::Both, 35000.0, 0.001));
+        assert!((position.amount - 0.002).abs() < 1e-12);
+        assert!((position.entry_price - 31000.0).abs() < 1e-6);
+
+        let position = manager.apply_fill(&fill(Side::Sell, PositionSide::Both, 36000.0, 0.003));
+        assert!((position.amount + 0.001).abs() < 1e-12);
+        assert_eq!(position.entry_price, 36000.0);
+
+        let position = manager.apply_fill(&fill(Side::Buy, PositionSide::Both, 34000.0, 0.001));
+        assert_eq!(position.amount, 0.0);
+        assert_eq!(position.entry_price, 0.0);
+        assert!(manager.positions().is_empty());
+
+        let position = manager.apply_fill(&fill(Side::Sell, PositionSide::Short, 30000.0, 0.001));
+        assert_eq!(position.amount, -0.001);
+        manager.update_mark_price("BTCUSDT", 29000.0);
+        assert!(
+            (manager
+                .position("BTCUSDT", PositionSide::Short)
+                .unrealized_pnl
+                - 1.0)
+                .abs()
+                < 1e-9
+        );
+    }
+
+    /// Test that the reconciliation with /fapi/v2/positionRisk raises an alert when the positions disagree and adopts
+    /// the exchange state.
+    #[test]
+    async fn reconcile_drift_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_position("BTCUSDT", "LONG", 0.002, 30000.0);
+        let manager = PositionManager::new();
+        let mut alerts = manager.subscribe_drift_alerts();
+
+        manager.apply_fill(&fill(Side::Buy, PositionSide::Long, 30000.0, 0.002));
+        let drifts = server
+            .run(manager.reconcile(&BinanceExchange::new(), "BTCUSDT"))
+            .await
+            .unwrap();
+        assert!(drifts.is_empty());
+        let position = manager.position("BTCUSDT", PositionSide::Long);
+        assert_eq!(position.leverage, 20.0);
+        assert_eq!(position.margin_type, "cross");
+
+        // A fill that the exchange does not know about.
+        manager.apply_fill(&fill(Side::Buy, PositionSide::Long, 31000.0, 0.001));
+        let drifts = server
+            .run(manager.reconcile(&BinanceExchange::new(), "BTCUSDT"))
+            .await
+            .unwrap();
+        assert_eq!(drifts.len(), 1);
+        assert_eq!(drifts[0].exchange_amount, 0.002);
+        assert!((drifts[0].local_amount - 0.003).abs() < 1e-12);
+        assert_eq!(alerts.recv().await.unwrap(), drifts[0]);
+        assert_eq!(
+            manager.position("BTCUSDT", PositionSide::Long).amount,
+            0.002
+        );
+    }
+}
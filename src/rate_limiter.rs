@@ -0,0 +1,479 @@
+// rate_limiter.rs - Request Weight and Order Rate Limiter
+
+// This file contains the rate limiter shared by every request sent to the Binance futures api.
+
+// The exchange limits the weight of the requests of an IP per minute (REQUEST_WEIGHT) and the number of orders of an
+// account per 10 seconds and per minute (ORDERS). Breaking the limits is answered with HTTP 429 and, when the client
+// keeps sending requests, with an IP ban (HTTP 418). The limiter knows the weight of each endpoint, counts the weight
+// and the orders sent in the current windows and reads the real usage from the `X-MBX-USED-WEIGHT-1M` and
+// `X-MBX-ORDER-COUNT-*` headers of the responses.
+
+// Before a request is sent, `RateLimiter::acquire` checks that it fits in the limits. When it does not, the request
+// waits (queues) for the next window if the wait is shorter than `RateLimits::max_wait`, otherwise it is rejected with
+// the same error the exchange would return (E11). After a 429 the requests wait for the Retry-After of the response,
+// and after a 418 every request is rejected (E12) until the ban expires.
+
+// There is one limiter per exchange server (see `rate_limiter`), shared by every function of the crate. The request
+// weight is limited per IP, so it is counted per server. The orders are limited per account, so they are counted per
+// account of accounts.rs (the default credentials have their own count).
+
+use crate::accounts;
+use crate::time_sync;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ONE_MINUTE_MS: u64 = 60_000;
+const TEN_SECONDS_MS: u64 = 10_000;
+/// Wait applied after a 429 without Retry-After header (in seconds).
+const DEFAULT_RETRY_AFTER: u64 = 60;
+/// Ban applied after a 418 without Retry-After header (in seconds).
+const DEFAULT_BAN: u64 = 120;
+
+/// Limits of the exchange (the default values are the ones of the Binance futures api).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    /// Request weight per minute.
+    pub request_weight_1m: u32,
+    /// Orders per 10 seconds.
+    pub orders_10s: u32,
+    /// Orders per minute.
+    pub orders_1m: u32,
+    /// Longest time a request waits for the limits. Requests that would wait longer are rejected.
+    pub max_wait: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            request_weight_1m: 2400,
+            orders_10s: 300,
+            orders_1m: 1200,
+            max_wait: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Usage of the limits in the current windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RateLimitUsage {
+    pub used_weight_1m: u32,
+    pub orders_10s: u32,
+    pub orders_1m: u32,
+    /// Time (in milliseconds since the epoch) until which the requests are blocked by a 429 or a 418.
+    pub blocked_until: Option<u64>,
+    /// True while the IP is banned (418).
+    pub banned: bool,
+}
+
+/// Reason why a request was not sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitRejection {
+    /// HTTP status the exchange would return (429 or 418).
+    pub status: StatusCode,
+    /// Seconds until the request can be sent.
+    pub retry_after: u64,
+}
+
+/// Orders of an account in the current windows.
+#[derive(Debug, Clone, Copy, Default)]
+struct OrderCount {
+    orders_10s: u32,
+    orders_1m: u32,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    minute: u64,
+    ten_seconds: u64,
+    used_weight_1m: u32,
+    /// Orders of each account, keyed by `account_key`.
+    orders: HashMap<String, OrderCount>,
+    blocked_until: Option<u64>,
+    banned: bool,
+}
+
+impl LimiterState {
+    /// Get the usage of the server weight and of the orders of an account.
+    fn usage(&self, account: &str) -> RateLimitUsage {
+        let orders = self.orders.get(account).copied().unwrap_or_default();
+        RateLimitUsage {
+            used_weight_1m: self.used_weight_1m,
+            orders_10s: orders.orders_10s,
+            orders_1m: orders.orders_1m,
+            blocked_until: self.blocked_until,
+            banned: self.banned,
+        }
+    }
+}
+
+/// Rate limiter of one exchange server.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: Mutex<RateLimits>,
+    state: Mutex<LimiterState>,
+    /// Origin of the server (see `time_sync::origin`), empty for a limiter created with `new`.
+    origin: String,
+    /// Time of the windows in the clock of the server, in milliseconds since the epoch.
+    clock: fn(&str) -> u64,
+}
+
+/// Get the time of an exchange server (local time corrected by the offset of time_sync.rs), in milliseconds.
+fn now_ms(origin: &str) -> u64 {
+    let local = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    (local + time_sync::clock_offset_of(origin)) as u64
+}
+
+/// Get the key of the orders of the current account (see accounts.rs), empty for the default credentials.
+fn account_key() -> String {
+    accounts::current_account()
+        .map(|account| account.name().to_string())
+        .unwrap_or_default()
+}
+
+/// Get the weight of a request (see the "Request Weight" of each endpoint in the Binance futures api documentation).
+///
+/// # Arguments
+/// * `method`: The HTTP method (e.g., "GET").
+/// * `path`: The endpoint (e.g., "/fapi/v1/klines").
+/// * `query`: The query string of the request, used by the endpoints whose weight depends on the parameters.
+///
+pub fn endpoint_weight(method: &str, path: &str, query: &str) -> u32 {
+    let has_symbol = query.split('&').any(|p| p.starts_with("symbol="));
+    let limit = query
+        .split('&')
+        .find_map(|p| p.strip_prefix("limit="))
+        .and_then(|l| l.parse::<u32>().ok());
+
+    match (method, path) {
+        (_, "/fapi/v1/klines") => match limit.unwrap_or(500) {
+            0..=99 => 1,
+            100..=499 => 2,
+            500..=1000 => 5,
+            _ => 10,
+        },
+        (_, "/fapi/v1/ticker/price") if has_symbol => 1,
+        (_, "/fapi/v1/ticker/price") => 2,
+        (_, "/fapi/v1/ticker/bookTicker") if has_symbol => 2,
+        (_, "/fapi/v1/ticker/bookTicker") => 5,
+        (_, "/fapi/v1/premiumIndex") if has_symbol => 1,
+        (_, "/fapi/v1/premiumIndex") => 10,
+        ("GET", "/fapi/v1/openOrders") if has_symbol => 1,
+        ("GET", "/fapi/v1/openOrders") => 40,
+        ("GET", "/fapi/v1/positionSide/dual") | ("GET", "/fapi/v1/multiAssetsMargin") => 30,
+        ("GET", "/fapi/v1/income") => 30,
+        ("POST", "/fapi/v1/countdownCancelAll") => 10,
+        ("GET", "/fapi/v2/positionRisk")
+        | ("GET", "/fapi/v2/balance")
+        | ("GET", "/fapi/v2/account")
+        | ("GET", "/fapi/v1/allOrders")
+        | ("GET", "/fapi/v1/userTrades") => 5,
+        _ => 1,
+    }
+}
+
+/// Checks if a request counts in the order limits (new and modified orders).
+pub fn is_order_request(method: &str, path: &str) -> bool {
+    matches!(
+        (method, path),
+        ("POST", "/fapi/v1/order") | ("PUT", "/fapi/v1/order") | ("POST", "/fapi/v1/batchOrders")
+    )
+}
+
+impl RateLimiter {
+    /// Creates a limiter with the given limits.
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter::with_clock(limits, now_ms)
+    }
+
+    /// Creates a limiter whose windows follow `clock` instead of the time of the exchange server.
+    fn with_clock(limits: RateLimits, clock: fn(&str) -> u64) -> Self {
+        RateLimiter {
+            limits: Mutex::new(limits),
+            state: Mutex::new(LimiterState::default()),
+            origin: String::new(),
+            clock,
+        }
+    }
+
+    /// Changes the limits (e.g., for an account with higher order limits).
+    pub fn set_limits(&self, limits: RateLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    /// Get the usage of the limits in the current windows, with the orders of the current account.
+    pub fn usage(&self) -> RateLimitUsage {
+        self.usage_for(&account_key())
+    }
+
+    fn usage_for(&self, account: &str) -> RateLimitUsage {
+        let mut state = self.state.lock().unwrap();
+        roll_windows(&mut state, (self.clock)(&self.origin));
+        state.usage(account)
+    }
+
+    /// Waits until a request fits in the limits and counts it, the orders in the count of the current account.
+    ///
+    /// # Arguments
+    /// * `method`: The HTTP method of the request.
+    /// * `path`: The endpoint of the request.
+    /// * `query`: The query string of the request.
+    ///
+    /// # Returns
+    /// `Err` with the status the exchange would return when the request can not be sent within `max_wait`.
+    ///
+    pub async fn acquire(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+    ) -> Result<(), RateLimitRejection> {
+        self.acquire_for(account_key(), method, path, query).await
+    }
+
+    /// Waits until a request of an account (see `account_key`) fits in the limits and counts it.
+    async fn acquire_for(
+        &self,
+        account: String,
+        method: &str,
+        path: &str,
+        query: &str,
+    ) -> Result<(), RateLimitRejection> {
+        let weight = endpoint_weight(method, path, query);
+        let is_order = is_order_request(method, path);
+
+        loop {
+            let limits = *self.limits.lock().unwrap();
+            let wait_ms = {
+                let now = (self.clock)(&self.origin);
+                let mut state = self.state.lock().unwrap();
+                roll_windows(&mut state, now);
+                let usage = state.usage(&account);
+
+                let wait_ms = if let Some(blocked_until) = usage.blocked_until {
+                    if usage.banned {
+                        return Err(RateLimitRejection {
+                            status: StatusCode::IM_A_TEAPOT,
+                            retry_after: (blocked_until - now).div_ceil(1000),
+                        });
+                    }
+                    blocked_until - now
+                } else if usage.used_weight_1m + weight > limits.request_weight_1m
+                    || (is_order && usage.orders_1m + 1 > limits.orders_1m)
+                {
+                    ONE_MINUTE_MS - now % ONE_MINUTE_MS
+                } else if is_order && usage.orders_10s + 1 > limits.orders_10s {
+                    TEN_SECONDS_MS - now % TEN_SECONDS_MS
+                } else {
+                    state.used_weight_1m += weight;
+                    if is_order {
+                        let orders = state.orders.entry(account).or_default();
+                        orders.orders_10s += 1;
+                        orders.orders_1m += 1;
+                    }
+                    return Ok(());
+                };
+
+                if wait_ms > limits.max_wait.as_millis() as u64 {
+                    return Err(RateLimitRejection {
+                        status: StatusCode::TOO_MANY_REQUESTS,
+                        retry_after: wait_ms.div_ceil(1000),
+                    });
+                }
+                wait_ms
+            };
+
+            tokio::time::sleep(Duration::from_millis(wait_ms)).await;
+        }
+    }
+
+    /// Updates the usage with the status and the headers of a response of the exchange, sent by the current account.
+    pub fn record(&self, status: StatusCode, headers: &HeaderMap) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+        let now = (self.clock)(&self.origin);
+        let mut state = self.state.lock().unwrap();
+        roll_windows(&mut state, now);
+
+        // The headers count every request of the IP (or account), also the ones of other programs.
+        if let Some(weight) = header("x-mbx-used-weight-1m") {
+            state.used_weight_1m = state.used_weight_1m.max(weight as u32);
+        }
+        let orders = state.orders.entry(account_key()).or_default();
+        if let Some(count) = header("x-mbx-order-count-10s") {
+            orders.orders_10s = orders.orders_10s.max(count as u32);
+        }
+        if let Some(count) = header("x-mbx-order-count-1m") {
+            orders.orders_1m = orders.orders_1m.max(count as u32);
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = header("retry-after").unwrap_or(DEFAULT_RETRY_AFTER);
+            state.blocked_until = Some(now + retry_after * 1000);
+        } else if status == StatusCode::IM_A_TEAPOT {
+            let retry_after = header("retry-after").unwrap_or(DEFAULT_BAN);
+            state.blocked_until = Some(now + retry_after * 1000);
+            state.banned = true;
+        }
+    }
+}
+
+/// Starts new windows when the current ones are over and lifts the expired blocks.
+fn roll_windows(state: &mut LimiterState, now: u64) {
+    let minute = now / ONE_MINUTE_MS;
+    if state.minute != minute {
+        state.minute = minute;
+        state.used_weight_1m = 0;
+        state.orders.clear();
+    }
+    let ten_seconds = now / TEN_SECONDS_MS;
+    if state.ten_seconds != ten_seconds {
+        state.ten_seconds = ten_seconds;
+        for orders in state.orders.values_mut() {
+            orders.orders_10s = 0;
+        }
+    }
+    if state.blocked_until.is_some_and(|until| until <= now) {
+        state.blocked_until = None;
+        state.banned = false;
+    }
+}
+
+/// Get the limiter of an exchange server, creating it in the first call.
+///
+/// # Arguments
+/// * `base_url`: The base url of the server (e.g., "https://fapi.binance.com").
+///
+pub fn rate_limiter(base_url: &str) -> Arc<RateLimiter> {
+    static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RateLimiter>>>> = OnceLock::new();
+    LIMITERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(base_url.to_string())
+        .or_insert_with(|| {
+            Arc::new(RateLimiter {
+                origin: time_sync::origin(base_url),
+                ..RateLimiter::new(RateLimits::default())
+            })
+        })
+        .clone()
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use tokio::test;
+
+    /// Test the weight table.
+    #[test]
+    async fn endpoint_weight_test() {
+        assert_eq!(endpoint_weight("GET", "/fapi/v1/klines", "limit=1500"), 10);
+        assert_eq!(endpoint_weight("GET", "/fapi/v1/klines", "limit=10"), 1);
+        assert_eq!(
+            endpoint_weight("GET", "/fapi/v1/openOrders", "timestamp=1"),
+            40
+        );
+        assert_eq!(
+            endpoint_weight("GET", "/fapi/v1/openOrders", "symbol=BTCUSDT"),
+            1
+        );
+        assert_eq!(endpoint_weight("GET", "/fapi/v2/positionRisk", ""), 5);
+        assert!(is_order_request("POST", "/fapi/v1/order"));
+        assert!(!is_order_request("DELETE", "/fapi/v1/order"));
+    }
+
+    /// Clock fixed at the start of a minute, so the windows do not roll during a test.
+    fn window_start(_origin: &str) -> u64 {
+        1_700_000_040_000
+    }
+
+    /// Test that the requests that do not fit in the limits are rejected when they would wait too long.
+    #[test]
+    async fn acquire_rejects_over_limit_test() {
+        let limiter = RateLimiter::with_clock(
+            RateLimits {
+                request_weight_1m: 10,
+                orders_10s: 1,
+                orders_1m: 10,
+                max_wait: Duration::ZERO,
+            },
+            window_start,
+        );
+
+        assert!(limiter.acquire("POST", "/fapi/v1/order", "").await.is_ok());
+        let rejection = limiter
+            .acquire("POST", "/fapi/v1/order", "")
+            .await
+            .unwrap_err();
+        assert_eq!(rejection.status, StatusCode::TOO_MANY_REQUESTS);
+
+        // The used weight of the headers is counted.
+        let mut headers = HeaderMap::new();
+        headers.insert("X-MBX-USED-WEIGHT-1M", HeaderValue::from_static("9"));
+        limiter.record(StatusCode::OK, &headers);
+        assert_eq!(limiter.usage().used_weight_1m, 9);
+        assert!(limiter.acquire("GET", "/fapi/v1/ping", "").await.is_ok());
+        assert!(limiter.acquire("GET", "/fapi/v1/ping", "").await.is_err());
+    }
+
+    /// Test that the orders are counted per account while the weight is counted for the whole server.
+    #[test]
+    async fn acquire_orders_per_account_test() {
+        let limiter = RateLimiter::with_clock(
+            RateLimits {
+                request_weight_1m: 3,
+                orders_10s: 1,
+                orders_1m: 10,
+                max_wait: Duration::ZERO,
+            },
+            window_start,
+        );
+        let order =
+            |account: &str| limiter.acquire_for(account.to_string(), "POST", "/fapi/v1/order", "");
+
+        assert!(order("").await.is_ok());
+        assert!(order("").await.is_err());
+        assert!(order("sub").await.is_ok());
+        assert_eq!(limiter.usage_for("sub").orders_10s, 1);
+        assert_eq!(limiter.usage_for("other").orders_10s, 0);
+        assert_eq!(limiter.usage_for("other").used_weight_1m, 2);
+
+        // The weight of the server is shared by the accounts.
+        assert!(order("other").await.is_ok());
+        assert!(order("another").await.is_err());
+    }
+
+    /// Test that a 429 blocks the requests for the Retry-After and that a 418 rejects them.
+    #[test]
+    async fn acquire_after_429_and_418_test() {
+        let limiter = RateLimiter::new(RateLimits::default());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Retry-After", HeaderValue::from_static("1"));
+        limiter.record(StatusCode::TOO_MANY_REQUESTS, &headers);
+        let start = std::time::Instant::now();
+        assert!(limiter.acquire("GET", "/fapi/v1/ping", "").await.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(900));
+
+        headers.insert("Retry-After", HeaderValue::from_static("120"));
+        limiter.record(StatusCode::IM_A_TEAPOT, &headers);
+        let rejection = limiter
+            .acquire("GET", "/fapi/v1/ping", "")
+            .await
+            .unwrap_err();
+        assert_eq!(rejection.status, StatusCode::IM_A_TEAPOT);
+        assert!(limiter.usage().banned);
+    }
+}
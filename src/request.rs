@@ -0,0 +1,507 @@
+// request.rs - Binance Request Builder
+
+// This file contains `BinanceRequest`, the single layer used by the crate to build and send the requests to the
+// Binance futures api.
+
+// The parameters are added with their types (`param`, `param_opt`) and URL-encoded when the query string is built.
+// The security type of the endpoint decides what is added to the request:
+// - Security::None: public market data, sent without the API key (e.g., klines, ticker/price, exchangeInfo).
+// - Security::ApiKey: endpoints that only need the API key header (e.g., user data streams).
+// - Security::Signed: account and trade endpoints (TRADE and USER_DATA). The timestamp (corrected by time_sync.rs)
+//   and the recvWindow are appended and the query string is signed, in the order the exchange verifies it. They are
+//   added once the rate limiter let the request through, right before each attempt is sent, so the time waited in
+//   the limiter does not count in the recvWindow.
+
+// The requests with the API key are not sent when the credentials can not be loaded or the request can not be
+// signed: an error response with E24 is returned instead, so `fetch` returns E24 (the cause is logged).
+
+// The new and modified orders (POST and PUT /fapi/v1/order) pass through the risk checks of risk.rs before they
+// are signed. A rejected order is not sent: `send_order` and `fetch_order` return the typed `RiskRejection`, and
+// `send` (used by the legacy order functions, that return strings) returns an error response with the rejection.
+
+// The requests are sent with `send_request`, so they go through the rate limiter and are sent again (a few times,
+// waiting longer after each attempt) when they do not reach the exchange. A new order (POST /fapi/v1/order) is never
+// sent again blindly, as it could be executed twice: it always carries a newClientOrderId, and when its response is
+// lost (e.g., a timeout), the order is looked up with origClientOrderId until it is found or until its recvWindow has
+// passed (the exchange rejects it after that). Only an order that does not exist then is sent again, with a new
+// timestamp. The new orders are signed with a recvWindow of at most SYNCED_RECV_WINDOW, so the lookup is short.
+// `fetch` also reads the JSON response into a type and maps the errors with `error_handler`, sending the request
+// again after a DNS or recvWindow error.
+
+use crate::binance_orders::{
+    credentials_error_response, exchange_url, get_client, get_public_client, get_signature,
+    get_timestamp, resend_backoff, send_request, send_signed_request, try_send_request,
+    unreachable_response, SignUrl, MAX_SEND_ATTEMPTS,
+};
+use crate::error::{
+    error_handler, OrderError, DNS_ERROR, ERROR_MALFORMED_RESPONSE, RECVWINDOW_ERROR,
+};
+use crate::risk::{self, RiskRejection};
+use crate::time_sync;
+use async_recursion::async_recursion;
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use urlencoding::encode;
+
+/// Binance error code of an order that does not exist (GET /fapi/v1/order).
+const ORDER_DOES_NOT_EXIST: i64 = -2013;
+
+/// Result of the lookup of an order whose response was lost.
+enum LostOrder {
+    /// The order was placed: the response of the lookup.
+    Found(Response),
+    /// The order does not exist and its recvWindow has passed: it can be sent again.
+    NotPlaced,
+    /// The lookups failed: it is not known if the order was placed.
+    Unknown,
+}
+
+/// Security type of an endpoint (see "Endpoint Security Type" in the Binance futures api documentation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    /// Public endpoint.
+    None,
+    /// The API key is sent in the X-MBX-APIKEY header.
+    ApiKey,
+    /// The API key is sent and the request is signed.
+    Signed,
+}
+
+/// Request to an endpoint of the Binance futures api.
+#[derive(Debug, Clone)]
+pub struct BinanceRequest {
+    method: &'static str,
+    path: String,
+    params: Vec<(String, String)>,
+    security: Security,
+}
+
+impl BinanceRequest {
+    /// Creates a public request.
+    ///
+    /// # Arguments
+    /// * `method`: The HTTP method ("GET", "POST", "PUT" or "DELETE").
+    /// * `path`: The endpoint (e.g., "/fapi/v1/order").
+    ///
+    pub fn new(method: &'static str, path: &str) -> Self {
+        BinanceRequest {
+            method,
+            path: path.to_string(),
+            params: Vec::new(),
+            security: Security::None,
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        BinanceRequest::new("GET", path)
+    }
+
+    pub fn post(path: &str) -> Self {
+        BinanceRequest::new("POST", path)
+    }
+
+    pub fn put(path: &str) -> Self {
+        BinanceRequest::new("PUT", path)
+    }
+
+    pub fn delete(path: &str) -> Self {
+        BinanceRequest::new("DELETE", path)
+    }
+
+    /// Marks the request as SIGNED (TRADE and USER_DATA endpoints).
+    pub fn signed(mut self) -> Self {
+        self.security = Security::Signed;
+        self
+    }
+
+    /// Marks the request as needing only the API key.
+    pub fn api_key(mut self) -> Self {
+        self.security = Security::ApiKey;
+        self
+    }
+
+    /// Adds a parameter.
+    pub fn param(mut self, key: &str, value: impl Display) -> Self {
+        self.params.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a parameter only when it has a value.
+    pub fn param_opt(self, key: &str, value: Option<impl Display>) -> Self {
+        match value {
+            Some(value) => self.param(key, value),
+            None => self,
+        }
+    }
+
+    pub fn method(&self) -> &'static str {
+        self.method
+    }
+
+    pub fn security(&self) -> Security {
+        self.security
+    }
+
+    /// Builds the URL-encoded query string, with the timestamp, recvWindow and signature of the SIGNED requests.
+    ///
+    /// # Returns
+    /// The query string, or E24 when a SIGNED request can not be signed.
+    ///
+    pub async fn query(&self) -> Result<String, String> {
+        self.query_at(
+            get_timestamp(SystemTime::now()).await,
+            time_sync::recv_window().await,
+        )
+        .await
+    }
+
+    /// Builds the query string, signed with a given timestamp and recvWindow.
+    async fn query_at(&self, timestamp: u128, recv_window: u64) -> Result<String, String> {
+        let query = self.unsigned_query();
+        if self.security == Security::Signed {
+            sign_query(query, timestamp, recv_window).await
+        } else {
+            Ok(query)
+        }
+    }
+
+    /// Builds the URL-encoded query string of the parameters, without the timestamp, recvWindow and signature.
+    fn unsigned_query(&self) -> String {
+        self.params
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+
+    /// Builds the complete url of the request, or E24 when a SIGNED request can not be signed.
+    pub async fn url(&self) -> Result<String, String> {
+        Ok(self.url_with_query(self.query().await?).await)
+    }
+
+    async fn url_with_query(&self, query: String) -> String {
+        if query.is_empty() {
+            format!("{}{}", exchange_url().await, self.path)
+        } else {
+            format!("{}{}?{}", exchange_url().await, self.path, query)
+        }
+    }
+
+    /// Sends the request, with the API key only when the endpoint needs it. The orders rejected by the risk checks
+    /// are not sent, and a 400 response with the rejection is returned instead (use `send_order` to get it typed).
+    #[async_recursion]
+    pub async fn send(&self) -> Response {
+        match self.send_order().await {
+            Ok(response) => response,
+            Err(rejection) => {
+                let body = serde_json::json!({ "code": -1013, "msg": rejection.to_string() });
+                Response::from(
+                    http::Response::builder()
+                        .status(StatusCode::BAD_REQUEST.as_u16())
+                        .body(body.to_string())
+                        .unwrap(),
+                )
+            }
+        }
+    }
+
+    /// Sends the request like `send`, after the risk checks when it is a new or modified order.
+    ///
+    /// # Returns
+    /// The response, or the rejection of the risk checks (the order was not sent).
+    ///
+    pub async fn send_order(&self) -> Result<Response, RiskRejection> {
+        if self.path == "/fapi/v1/order" && matches!(self.method, "POST" | "PUT") {
+            risk::check_order_request(self.method, &self.params).await?;
+        }
+
+        let client = match self.security {
+            Security::None => get_public_client().await,
+            Security::ApiKey | Security::Signed => match get_client().await {
+                Ok(client) => client,
+                Err(_) => return Ok(credentials_error_response()),
+            },
+        };
+        if self.path == "/fapi/v1/order" && self.method == "POST" {
+            return Ok(self.with_client_order_id().send_new_order(client).await);
+        }
+        if self.security == Security::Signed {
+            let sign = url_signer(time_sync::recv_window().await, Arc::default());
+            let url = self.url_with_query(self.unsigned_query()).await;
+            return Ok(send_signed_request(client, url, self.method, Some(&*sign)).await);
+        }
+        match self.url().await {
+            Ok(url) => Ok(send_request(client, url, self.method).await),
+            Err(_) => Ok(credentials_error_response()),
+        }
+    }
+
+    /// Get a parameter of the request.
+    fn param_value(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Adds a generated newClientOrderId when the order has none, so it can be looked up when its response is lost.
+    fn with_client_order_id(&self) -> BinanceRequest {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        if self.param_value("newClientOrderId").is_some() {
+            return self.clone();
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        self.clone().param(
+            "newClientOrderId",
+            format!("qr_{}_{}", now, NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+        )
+    }
+
+    /// Sends a new order, looking it up before sending it again when its response is lost (see the file header).
+    async fn send_new_order(&self, client: reqwest::Client) -> Response {
+        let recv_window = time_sync::recv_window()
+            .await
+            .min(time_sync::SYNCED_RECV_WINDOW);
+        let signed_at = Arc::new(AtomicU64::new(0));
+        let sign = url_signer(recv_window, signed_at.clone());
+        let url = self.url_with_query(self.unsigned_query()).await;
+        for attempt in 1..=MAX_SEND_ATTEMPTS {
+            if attempt > 1 {
+                tokio::time::sleep(resend_backoff(attempt)).await;
+            }
+            let error = match try_send_request(
+                client.clone(),
+                url.clone(),
+                self.method,
+                Some(&*sign),
+            )
+            .await
+            {
+                Ok(response) => return response,
+                Err(error) => error,
+            };
+
+            let timestamp = signed_at.load(Ordering::Relaxed) as u128;
+            match self
+                .lookup_lost_order(timestamp + recv_window as u128)
+                .await
+            {
+                LostOrder::Found(response) => {
+                    info!(%error, "The response of the order was lost, but the order was placed");
+                    return response;
+                }
+                LostOrder::NotPlaced => {
+                    warn!(%error, attempt, "The order was not placed, sending it again");
+                }
+                // The order may exist: it is not sent again.
+                LostOrder::Unknown => return unreachable_response(),
+            }
+        }
+        unreachable_response()
+    }
+
+    /// Looks up an order whose response was lost, until it is found or until `expiry` (its timestamp plus its
+    /// recvWindow, after which the exchange does not accept it) has passed.
+    async fn lookup_lost_order(&self, expiry: u128) -> LostOrder {
+        let lookup = BinanceRequest::get("/fapi/v1/order")
+            .param("symbol", self.param_value("symbol").unwrap_or_default())
+            .param(
+                "origClientOrderId",
+                self.param_value("newClientOrderId").unwrap_or_default(),
+            )
+            .signed();
+
+        let mut attempt = 2;
+        loop {
+            tokio::time::sleep(resend_backoff(attempt)).await;
+            attempt += 1;
+            let response = lookup.send().await;
+            if response.status() == StatusCode::OK {
+                return LostOrder::Found(response);
+            }
+            let body = response.bytes().await.unwrap_or_default();
+            let does_not_exist = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|data| data["code"].as_i64())
+                == Some(ORDER_DOES_NOT_EXIST);
+
+            // Checked after the lookup, so the last lookup is made once the order can not be accepted any more.
+            if get_timestamp(SystemTime::now()).await > expiry {
+                return if does_not_exist {
+                    LostOrder::NotPlaced
+                } else {
+                    LostOrder::Unknown
+                };
+            }
+        }
+    }
+
+    /// Sends the request and reads its JSON response.
+    ///
+    /// # Returns
+    /// The response, or the mapped error. DNS and recvWindow errors (E07 and E08) are not returned: the request is
+    /// sent again.
+    ///
+    #[async_recursion]
+    pub async fn fetch<T: DeserializeOwned>(&self) -> Result<T, String> {
+        let error = match read_response(self.send().await).await {
+            Err(error) => error,
+            result => return result,
+        };
+        if error == DNS_ERROR || error == RECVWINDOW_ERROR {
+            self.fetch().await
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Sends an order and reads its JSON response, like `fetch`.
+    ///
+    /// # Returns
+    /// The response, the rejection of the risk checks or the mapped error.
+    ///
+    #[async_recursion]
+    pub async fn fetch_order<T: DeserializeOwned>(&self) -> Result<T, OrderError> {
+        let error = match read_response(self.send_order().await?).await {
+            Err(error) => error,
+            Ok(data) => return Ok(data),
+        };
+        if error == DNS_ERROR || error == RECVWINDOW_ERROR {
+            self.fetch_order().await
+        } else {
+            Err(error.into())
+        }
+    }
+}
+
+/// Appends the timestamp and the recvWindow to a query string, and signs it.
+async fn sign_query(
+    mut query: String,
+    timestamp: u128,
+    recv_window: u64,
+) -> Result<String, String> {
+    if !query.is_empty() {
+        query.push('&');
+    }
+    query.push_str(&format!(
+        "timestamp={}&recvWindow={}",
+        timestamp, recv_window
+    ));
+    let signature = get_signature(query.clone()).await?;
+    query.push_str(&format!("&signature={}", signature));
+    Ok(query)
+}
+
+/// Builds the `SignUrl` of a SIGNED request, that reads the timestamp when the url is signed and stores it in
+/// `signed_at`.
+fn url_signer(recv_window: u64, signed_at: Arc<AtomicU64>) -> Box<SignUrl> {
+    Box::new(move |url: String| {
+        let signed_at = signed_at.clone();
+        Box::pin(async move {
+            let timestamp = get_timestamp(SystemTime::now()).await;
+            signed_at.store(timestamp as u64, Ordering::Relaxed);
+            let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+            let query = sign_query(query.to_string(), timestamp, recv_window).await?;
+            Ok(format!("{}?{}", path, query))
+        })
+    })
+}
+
+/// Reads the JSON response of a request, or maps its error.
+async fn read_response<T: DeserializeOwned>(result: Response) -> Result<T, String> {
+    if result.status() == StatusCode::OK {
+        match result.json().await {
+            Ok(data) => Ok(data),
+            Err(_) => Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        }
+    } else {
+        Err(error_handler(result, None).await)
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server;
+    use tokio::test;
+
+    /// Test that the parameters are encoded and that only the SIGNED requests carry the timestamp and signature.
+    #[test]
+    async fn binance_request_query_test() {
+        mock_server::shared();
+
+        let request = BinanceRequest::get("/fapi/v1/ticker/price").param("symbol", "BTCUSDT");
+        assert_eq!(request.query().await.unwrap(), "symbol=BTCUSDT");
+
+        let request = BinanceRequest::post("/fapi/v1/order")
+            .param("symbol", "BTCUSDT")
+            .param("newClientOrderId", "a b&c")
+            .param_opt("price", None::<f64>)
+            .param_opt("quantity", Some(0.001))
+            .signed();
+        let query = request.query().await.unwrap();
+        assert!(query
+            .starts_with("symbol=BTCUSDT&newClientOrderId=a%20b%26c&quantity=0.001&timestamp="));
+
+        let (unsigned, signature) = query.split_once("&signature=").unwrap();
+        assert!(unsigned.contains("&recvWindow="));
+        assert_eq!(
+            signature,
+            get_signature(unsigned.to_string()).await.unwrap()
+        );
+    }
+
+    /// Test that a SIGNED request is signed after the wait in the rate limiter, so the wait does not count in its
+    /// recvWindow.
+    #[test]
+    async fn sign_after_rate_limiter_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        let origin = reqwest::Url::parse(&server.url)
+            .unwrap()
+            .origin()
+            .ascii_serialization();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            "retry-after",
+            reqwest::header::HeaderValue::from_static("1"),
+        );
+
+        let (blocked_at, response) = server
+            .run(async {
+                let blocked_at = get_timestamp(SystemTime::now()).await;
+                crate::rate_limiter::rate_limiter(&origin)
+                    .record(StatusCode::TOO_MANY_REQUESTS, &headers);
+                let response = BinanceRequest::get("/fapi/v1/openOrders")
+                    .param("symbol", "BTCUSDT")
+                    .signed()
+                    .send()
+                    .await;
+                (blocked_at, response)
+            })
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let request = server
+            .requests()
+            .into_iter()
+            .find(|r| r.starts_with("GET /fapi/v1/openOrders"))
+            .unwrap();
+        let timestamp: u128 = request
+            .split(['?', '&'])
+            .find_map(|param| param.strip_prefix("timestamp="))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(timestamp >= blocked_at + 1000);
+    }
+}
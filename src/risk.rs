@@ -0,0 +1,754 @@
+// risk.rs - Pre-Trade Risk Checks
+
+// This file contains the risk layer that every order passes through before it is signed: `BinanceRequest::send`
+// checks the new and modified orders (POST and PUT /fapi/v1/order) with `check_order`, and a rejected order never
+// reaches the exchange. Like the rejections of the local rate limiter, the rejection is returned as an error
+// response, so every order function of the crate reports it with its usual error handling.
+
+// Rules (each one is disabled until its limit is set with `set_risk_limits`):
+// - KILL_SWITCH: no order can open or increase a position (`set_kill_switch`).
+// - MAX_ORDER_NOTIONAL: notional (quantity x price) of one order.
+// - MAX_SYMBOL_NOTIONAL: notional of the positions and open orders of a symbol, with the new order.
+// - MAX_OPEN_ORDERS: open orders of a symbol, with the new order.
+// - MAX_POSITION: amount of a position (in the base asset) after the order is filled.
+// - PRICE_BAND: distance between the price (or stop price) of the order and the mark price, as a fraction of it.
+// - DAILY_LOSS_LIMIT: loss of the account since 00:00 UTC: realized PnL, commissions and funding fees.
+// The orders that can only reduce a position (reduceOnly or closePosition, or in hedge mode, where reduceOnly is not
+// sent, a SELL of the LONG position or a BUY of the SHORT position) are only checked against the price band, so
+// the positions can always be closed, even when the kill switch is active.
+
+// The limits are kept per exchange (and per account, see accounts.rs). Only the data needed by the enabled rules is
+// read from the exchange (mark price, open orders, positions or income history), so no request is added when no
+// rule is enabled.
+
+// A rejection is a `RiskRejection` with the rule that fired. The order functions that return a `Result` return it as
+// `OrderError::Rejected`, and the legacy ones as the error "E18: Order rejected by the risk check <RULE>: <detail>",
+// that `RiskRejection::from_error` reads back.
+
+use crate::account::{income_history, IncomeQuery};
+use crate::binance_orders::{exchange_key, open_orders, position_risk, premium_index};
+use crate::error::RISK_CHECK_REJECTED;
+use crate::models::{IncomeType, OrderType, PositionSide, Side};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Limits of the risk checks. The rules whose limit is None are not checked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RiskLimits {
+    /// Maximum notional of one order, in the quote asset.
+    pub max_order_notional: Option<f64>,
+    /// Maximum notional of the positions and open orders of a symbol, in the quote asset.
+    pub max_symbol_notional: Option<f64>,
+    /// Maximum number of open orders of a symbol.
+    pub max_open_orders: Option<usize>,
+    /// Maximum amount of a position, in the base asset (e.g., BTC).
+    pub max_position: Option<f64>,
+    /// Maximum distance between the price of an order and the mark price, as a fraction (e.g., 0.05 for 5%).
+    pub price_band: Option<f64>,
+    /// Maximum loss since 00:00 UTC (realized PnL, commissions and funding fees), in the quote asset (a positive
+    /// number).
+    pub daily_loss_limit: Option<f64>,
+}
+
+/// Rule of the risk checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RiskRule {
+    KillSwitch,
+    MaxOrderNotional,
+    MaxSymbolNotional,
+    MaxOpenOrders,
+    MaxPosition,
+    PriceBand,
+    DailyLossLimit,
+}
+
+impl RiskRule {
+    const ALL: [RiskRule; 7] = [
+        RiskRule::KillSwitch,
+        RiskRule::MaxOrderNotional,
+        RiskRule::MaxSymbolNotional,
+        RiskRule::MaxOpenOrders,
+        RiskRule::MaxPosition,
+        RiskRule::PriceBand,
+        RiskRule::DailyLossLimit,
+    ];
+
+    /// Get the name of the rule used in the errors.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RiskRule::KillSwitch => "KILL_SWITCH",
+            RiskRule::MaxOrderNotional => "MAX_ORDER_NOTIONAL",
+            RiskRule::MaxSymbolNotional => "MAX_SYMBOL_NOTIONAL",
+            RiskRule::MaxOpenOrders => "MAX_OPEN_ORDERS",
+            RiskRule::MaxPosition => "MAX_POSITION",
+            RiskRule::PriceBand => "PRICE_BAND",
+            RiskRule::DailyLossLimit => "DAILY_LOSS_LIMIT",
+        }
+    }
+}
+
+impl fmt::Display for RiskRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Order rejected by the risk checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskRejection {
+    pub rule: RiskRule,
+    /// Why the rule fired (e.g., "notional 5000.00 is above 1000.00").
+    pub detail: String,
+}
+
+impl RiskRejection {
+    fn new(rule: RiskRule, detail: String) -> Self {
+        RiskRejection { rule, detail }
+    }
+
+    /// Reads a rejection back from the error returned by an order function.
+    ///
+    /// # Returns
+    /// The rejection, or None when the error is not a rejection of the risk checks.
+    ///
+    pub fn from_error(error: &str) -> Option<Self> {
+        let (rule, detail) = error
+            .strip_prefix(RISK_CHECK_REJECTED)?
+            .trim_start()
+            .split_once(": ")?;
+        let rule = RiskRule::ALL.into_iter().find(|r| r.as_str() == rule)?;
+        Some(RiskRejection::new(
+            rule,
+            detail.trim_end_matches('.').to_string(),
+        ))
+    }
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}.", RISK_CHECK_REJECTED, self.rule, self.detail)
+    }
+}
+
+/// Order as seen by the risk checks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskOrder {
+    pub symbol: String,
+    pub side: Side,
+    /// None for the modified orders, whose type is not sent.
+    pub order_type: Option<OrderType>,
+    pub quantity: f64,
+    pub price: Option<f64>,
+    pub stop_price: Option<f64>,
+    pub position_side: PositionSide,
+    /// The order can only reduce a position (reduceOnly or closePosition).
+    pub reduce_only: bool,
+    /// Id of the order when it is a modification of a resting order.
+    pub amended_order_id: Option<u64>,
+}
+
+impl RiskOrder {
+    /// Reads the order from the parameters of an order request.
+    ///
+    /// # Arguments
+    /// * `method`: "POST" for a new order or "PUT" for a modification.
+    /// * `params`: The parameters of the request.
+    ///
+    /// # Returns
+    /// The order, or None when the parameters do not describe an order (the exchange will reject it).
+    ///
+    pub fn from_params(method: &str, params: &[(String, String)]) -> Option<Self> {
+        let get = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        let number = |key: &str| get(key).and_then(|v| v.parse::<f64>().ok());
+        Some(RiskOrder {
+            symbol: get("symbol")?.to_string(),
+            side: Side::from_binance(get("side")?)?,
+            order_type: get("type").and_then(OrderType::from_binance),
+            quantity: number("quantity").unwrap_or(0.0),
+            price: number("price"),
+            stop_price: number("stopPrice"),
+            position_side: get("positionSide")
+                .and_then(PositionSide::from_binance)
+                .unwrap_or(PositionSide::Both),
+            reduce_only: get("reduceOnly") == Some("true") || get("closePosition") == Some("true"),
+            amended_order_id: match method {
+                "PUT" => get("orderId").and_then(|id| id.parse().ok()),
+                _ => None,
+            },
+        })
+    }
+
+    /// Checks if the order can only reduce a position: reduceOnly or closePosition, or a SELL of the LONG position
+    /// or a BUY of the SHORT position (hedge mode, where the orders can not be sent with reduceOnly).
+    pub fn reduces_position(&self) -> bool {
+        self.reduce_only
+            || matches!(
+                (self.side, self.position_side),
+                (Side::Sell, PositionSide::Long) | (Side::Buy, PositionSide::Short)
+            )
+    }
+
+    /// Get the price used for the notional of the order: its price, its stop price or the mark price.
+    fn reference_price(&self, mark_price: f64) -> f64 {
+        self.price
+            .filter(|p| *p > 0.0)
+            .or(self.stop_price.filter(|p| *p > 0.0))
+            .unwrap_or(mark_price)
+    }
+}
+
+/// Risk settings of an exchange url.
+#[derive(Debug, Clone, Default)]
+struct RiskSettings {
+    limits: RiskLimits,
+    kill_switch: bool,
+}
+
+/// Risk settings per exchange key (see `exchange_key`).
+static RISK_SETTINGS: RwLock<Option<HashMap<String, RiskSettings>>> = RwLock::new(None);
+
+fn settings(key: &str) -> RiskSettings {
+    RISK_SETTINGS
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|settings| settings.get(key).cloned())
+        .unwrap_or_default()
+}
+
+fn update_settings(key: String, update: impl FnOnce(&mut RiskSettings)) {
+    let mut settings = RISK_SETTINGS.write().unwrap();
+    update(
+        settings
+            .get_or_insert_with(HashMap::new)
+            .entry(key)
+            .or_default(),
+    );
+}
+
+/// Sets the limits of the risk checks of the current exchange.
+pub async fn set_risk_limits(limits: RiskLimits) {
+    update_settings(exchange_key().await, |s| s.limits = limits);
+}
+
+/// Get the limits of the risk checks of the current exchange.
+pub async fn risk_limits() -> RiskLimits {
+    settings(&exchange_key().await).limits
+}
+
+/// Activates or deactivates the kill switch of the current exchange. While it is active, only the orders that
+/// reduce a position are sent.
+pub async fn set_kill_switch(active: bool) {
+    if active {
+        warn!("Kill switch activated: only the orders that reduce a position are sent");
+    }
+    update_settings(exchange_key().await, |s| s.kill_switch = active);
+}
+
+/// Checks if the kill switch of the current exchange is active.
+pub async fn is_kill_switch_active() -> bool {
+    settings(&exchange_key().await).kill_switch
+}
+
+/// Checks an order against the risk limits of the current exchange.
+///
+/// # Arguments
+/// * `order`: The order that will be sent.
+///
+/// # Returns
+/// Ok when the order can be sent, the rejection of the first rule that fired, or the rejection of the rule whose
+/// data could not be read from the exchange (the order is not sent without its checks).
+///
+pub async fn check_order(order: &RiskOrder) -> Result<(), RiskRejection> {
+    let RiskSettings {
+        limits,
+        kill_switch,
+    } = settings(&exchange_key().await);
+    let unavailable = |rule: RiskRule, error: String| {
+        RiskRejection::new(
+            rule,
+            format!("the exchange data could not be read ({})", error),
+        )
+    };
+
+    let reduces_position = order.reduces_position();
+    if kill_switch && !reduces_position {
+        return Err(RiskRejection::new(
+            RiskRule::KillSwitch,
+            "the kill switch is active".to_string(),
+        ));
+    }
+
+    // The first enabled rule that needs the mark price is the one rejected when it can not be read.
+    let mark_price_rule = if limits.price_band.is_some() {
+        Some(RiskRule::PriceBand)
+    } else if reduces_position {
+        None
+    } else if limits.max_order_notional.is_some() {
+        Some(RiskRule::MaxOrderNotional)
+    } else if limits.max_symbol_notional.is_some() {
+        Some(RiskRule::MaxSymbolNotional)
+    } else {
+        None
+    };
+    let mark_price = match mark_price_rule {
+        Some(rule) => {
+            premium_index(&order.symbol)
+                .await
+                .map_err(|e| unavailable(rule, e))?
+                .mark_price
+        }
+        None => 0.0,
+    };
+
+    if let Some(band) = limits.price_band {
+        for price in [order.price, order.stop_price].into_iter().flatten() {
+            if price > 0.0 && (price - mark_price).abs() > band * mark_price {
+                return Err(RiskRejection::new(
+                    RiskRule::PriceBand,
+                    format!(
+                        "price {:.2} is more than {}% away from the mark price {:.2}",
+                        price,
+                        band * 100.0,
+                        mark_price
+                    ),
+                ));
+            }
+        }
+    }
+
+    if reduces_position {
+        return Ok(());
+    }
+
+    let notional = order.quantity * order.reference_price(mark_price);
+    if let Some(max) = limits.max_order_notional {
+        if notional > max {
+            return Err(RiskRejection::new(
+                RiskRule::MaxOrderNotional,
+                format!("notional {:.2} is above {:.2}", notional, max),
+            ));
+        }
+    }
+
+    if let Some(max) = limits
+        .max_position
+        .filter(|_| order.amended_order_id.is_none())
+    {
+        let positions = position_risk(Some(&order.symbol))
+            .await
+            .map_err(|e| unavailable(RiskRule::MaxPosition, e))?;
+        let amount = positions
+            .iter()
+            .find(|p| p.position_side == order.position_side)
+            .map_or(0.0, |p| p.position_amt);
+        let new_amount = amount + order.side.sign() * order.quantity;
+        if new_amount.abs() > max && new_amount.abs() > amount.abs() {
+            return Err(RiskRejection::new(
+                RiskRule::MaxPosition,
+                format!("position {} would be above {}", new_amount, max),
+            ));
+        }
+    }
+
+    // The first enabled rule that needs the open orders is the one rejected when they can not be read.
+    let resting = order.order_type != Some(OrderType::Market);
+    let open_orders_rule =
+        if limits.max_open_orders.is_some() && resting && order.amended_order_id.is_none() {
+            Some(RiskRule::MaxOpenOrders)
+        } else if limits.max_symbol_notional.is_some() {
+            Some(RiskRule::MaxSymbolNotional)
+        } else {
+            None
+        };
+    let orders = if let Some(rule) = open_orders_rule {
+        open_orders(Some(&order.symbol))
+            .await
+            .map_err(|e| unavailable(rule, e))?
+            .into_iter()
+            .filter(|o| Some(o.order_id) != order.amended_order_id)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    if let Some(max) = limits.max_open_orders {
+        if resting && order.amended_order_id.is_none() && orders.len() + 1 > max {
+            return Err(RiskRejection::new(
+                RiskRule::MaxOpenOrders,
+                format!("{} open orders is the maximum", max),
+            ));
+        }
+    }
+
+    if let Some(max) = limits.max_symbol_notional {
+        let positions = position_risk(Some(&order.symbol))
+            .await
+            .map_err(|e| unavailable(RiskRule::MaxSymbolNotional, e))?;
+        let positions_notional: f64 = positions
+            .iter()
+            .map(|p| p.position_amt.abs() * mark_price)
+            .sum();
+        let orders_notional: f64 = orders
+            .iter()
+            .filter(|o| !o.reduce_only)
+            .map(|o| {
+                let price = if o.price > 0.0 {
+                    o.price
+                } else if o.stop_price > 0.0 {
+                    o.stop_price
+                } else {
+                    mark_price
+                };
+                (o.orig_qty - o.executed_qty) * price
+            })
+            .sum();
+        let symbol_notional = positions_notional + orders_notional + notional;
+        if symbol_notional > max {
+            return Err(RiskRejection::new(
+                RiskRule::MaxSymbolNotional,
+                format!(
+                    "notional of {} would be {:.2}, above {:.2}",
+                    order.symbol, symbol_notional, max
+                ),
+            ));
+        }
+    }
+
+    if let Some(limit) = limits.daily_loss_limit {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let incomes = income_history(&IncomeQuery {
+            start_time: Some(now - now % 86_400_000),
+            limit: Some(1000),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| unavailable(RiskRule::DailyLossLimit, e))?;
+        let pnl: f64 = incomes
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i.income_type,
+                    IncomeType::RealizedPnl | IncomeType::Commission | IncomeType::FundingFee
+                )
+            })
+            .map(|i| i.income)
+            .sum();
+        if pnl <= -limit {
+            return Err(RiskRejection::new(
+                RiskRule::DailyLossLimit,
+                format!(
+                    "PnL of the day {:.2} (with commissions and funding) reached the limit of -{:.2}",
+                    pnl, limit
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks an order request before it is signed.
+///
+/// # Arguments
+/// * `method`, `params`: The method and the parameters of a request to /fapi/v1/order.
+///
+/// # Returns
+/// Ok when the order can be sent (or when the parameters are not an order, that the exchange will reject), or the
+/// rejection of the first rule that fired.
+///
+pub async fn check_order_request(
+    method: &str,
+    params: &[(String, String)],
+) -> Result<(), RiskRejection> {
+    let Some(order) = RiskOrder::from_params(method, params) else {
+        return Ok(());
+    };
+    let result = check_order(&order).await;
+    if let Err(rejection) = &result {
+        warn!(
+            symbol = %order.symbol,
+            rule = %rejection.rule,
+            detail = %rejection.detail,
+            "Order rejected by the risk checks"
+        );
+    }
+    result
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_orders::{new_order_limit, place_order};
+    use crate::error::OrderError;
+    use crate::mock_server;
+    use crate::models::{OrderAck, OrderRequest};
+    use tokio::test;
+
+    fn order(side: Side, order_type: OrderType, quantity: f64, price: Option<f64>) -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side,
+            order_type,
+            quantity,
+            price,
+            stop_price: None,
+            position_side: PositionSide::Both,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        }
+    }
+
+    /// Test that a rejection is written and read back with its rule.
+    #[test]
+    async fn risk_rejection_test() {
+        let rejection = RiskRejection::new(
+            RiskRule::MaxOrderNotional,
+            "notional 5000.00 is above 1000.00".to_string(),
+        );
+        let error = rejection.to_string();
+        assert_eq!(
+            error,
+            "E18: Order rejected by the risk check MAX_ORDER_NOTIONAL: notional 5000.00 is above 1000.00."
+        );
+        assert_eq!(RiskRejection::from_error(&error), Some(rejection));
+        assert_eq!(
+            RiskRejection::from_error("E01: Order would immediately trigger."),
+            None
+        );
+    }
+
+    /// Test that each rule rejects the orders before they reach the exchange.
+    #[test]
+    async fn check_order_rules_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        server.set_mark_price(30000.0);
+        server.set_position("BTCUSDT", "BOTH", 0.01, 30000.0);
+
+        let rule_of =
+            |result: Result<OrderAck, OrderError>| result.unwrap_err().rejection().map(|r| r.rule);
+        let (results, closed) = server
+            .run(async {
+                let mut results = Vec::new();
+                set_risk_limits(RiskLimits {
+                    max_order_notional: Some(1000.0),
+                    price_band: Some(0.05),
+                    ..Default::default()
+                })
+                .await;
+                results.push(place_order(&order(Side::Buy, OrderType::Market, 0.1, None)).await);
+                results.push(
+                    place_order(&order(Side::Buy, OrderType::Limit, 0.001, Some(20000.0))).await,
+                );
+
+                set_risk_limits(RiskLimits {
+                    max_open_orders: Some(1),
+                    ..Default::default()
+                })
+                .await;
+                place_order(&order(Side::Buy, OrderType::Limit, 0.001, Some(29000.0)))
+                    .await
+                    .unwrap();
+                results.push(
+                    place_order(&order(Side::Buy, OrderType::Limit, 0.001, Some(29000.0))).await,
+                );
+
+                set_risk_limits(RiskLimits {
+                    max_position: Some(0.012),
+                    ..Default::default()
+                })
+                .await;
+                results.push(place_order(&order(Side::Buy, OrderType::Market, 0.005, None)).await);
+
+                set_risk_limits(RiskLimits {
+                    max_symbol_notional: Some(350.0),
+                    ..Default::default()
+                })
+                .await;
+                results.push(place_order(&order(Side::Buy, OrderType::Market, 0.001, None)).await);
+
+                set_risk_limits(RiskLimits::default()).await;
+                set_kill_switch(true).await;
+                results.push(place_order(&order(Side::Buy, OrderType::Market, 0.001, None)).await);
+                let mut close = order(Side::Sell, OrderType::Market, 0.001, None);
+                close.reduce_only = true;
+                let closed = place_order(&close).await;
+                set_kill_switch(false).await;
+                (results, closed)
+            })
+            .await;
+
+        let rules: Vec<Option<RiskRule>> = results.into_iter().map(rule_of).collect();
+        assert_eq!(
+            rules,
+            vec![
+                Some(RiskRule::MaxOrderNotional),
+                Some(RiskRule::PriceBand),
+                Some(RiskRule::MaxOpenOrders),
+                Some(RiskRule::MaxPosition),
+                Some(RiskRule::MaxSymbolNotional),
+                Some(RiskRule::KillSwitch),
+            ]
+        );
+        assert!(closed.is_ok());
+        // Only the accepted orders reached the exchange.
+        let sent = server
+            .requests()
+            .iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .count();
+        assert_eq!(sent, 2);
+    }
+
+    /// Test that the kill switch lets the positions of the hedge mode be closed (without reduceOnly), and that the
+    /// rejections are returned typed.
+    #[test]
+    async fn kill_switch_hedge_mode_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+        server.set_position("BTCUSDT", "LONG", 0.01, 30000.0);
+        server.set_position("BTCUSDT", "SHORT", -0.01, 30000.0);
+
+        let hedge_order = |side: Side, position_side: PositionSide| {
+            let mut order = order(side, OrderType::Market, 0.01, None);
+            order.position_side = position_side;
+            order
+        };
+        let results = server
+            .run(async {
+                set_kill_switch(true).await;
+                let results = vec![
+                    place_order(&hedge_order(Side::Sell, PositionSide::Long)).await,
+                    place_order(&hedge_order(Side::Buy, PositionSide::Short)).await,
+                    place_order(&hedge_order(Side::Buy, PositionSide::Long)).await,
+                    place_order(&hedge_order(Side::Sell, PositionSide::Short)).await,
+                ];
+                set_kill_switch(false).await;
+                results
+            })
+            .await;
+
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        for result in &results[2..] {
+            let error = result.as_ref().unwrap_err();
+            assert_eq!(error.rejection().unwrap().rule, RiskRule::KillSwitch);
+            assert!(error.to_string().starts_with(RISK_CHECK_REJECTED));
+        }
+        let sent: Vec<String> = server
+            .requests()
+            .into_iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .collect();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|r| !r.contains("reduceOnly")));
+    }
+
+    /// Test that an order whose mark price can not be read is rejected by the rule that needed it.
+    #[test]
+    async fn mark_price_unavailable_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        let handle = server.clone();
+
+        let result = server
+            .run(async move {
+                set_risk_limits(RiskLimits {
+                    max_order_notional: Some(1000.0),
+                    ..Default::default()
+                })
+                .await;
+                handle.inject_error("/fapi/v1/premiumIndex", 400, -1121, "Invalid symbol.");
+                place_order(&order(Side::Buy, OrderType::Market, 0.001, None)).await
+            })
+            .await;
+
+        let rejection = result.unwrap_err().rejection().cloned().unwrap();
+        assert_eq!(rejection.rule, RiskRule::MaxOrderNotional);
+        assert!(rejection.detail.contains("could not be read"));
+    }
+
+    /// Test that an order whose open orders can not be read is rejected by the rule that needed them.
+    #[test]
+    async fn open_orders_unavailable_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        let handle = server.clone();
+
+        let (market, limit) = server
+            .run(async move {
+                set_risk_limits(RiskLimits {
+                    max_symbol_notional: Some(100_000.0),
+                    max_open_orders: Some(10),
+                    ..Default::default()
+                })
+                .await;
+                handle.inject_error("/fapi/v1/openOrders", 400, -1121, "Invalid symbol.");
+                let market = place_order(&order(Side::Buy, OrderType::Market, 0.001, None)).await;
+                handle.inject_error("/fapi/v1/openOrders", 400, -1121, "Invalid symbol.");
+                let limit =
+                    place_order(&order(Side::Buy, OrderType::Limit, 0.001, Some(29000.0))).await;
+                (market, limit)
+            })
+            .await;
+
+        // A MARKET order does not rest, so only the symbol notional needed the open orders.
+        let rejection = market.unwrap_err().rejection().cloned().unwrap();
+        assert_eq!(rejection.rule, RiskRule::MaxSymbolNotional);
+        assert!(rejection.detail.contains("could not be read"));
+        let rejection = limit.unwrap_err().rejection().cloned().unwrap();
+        assert_eq!(rejection.rule, RiskRule::MaxOpenOrders);
+    }
+
+    /// Test that the daily loss limit stops the orders after a loss (with the commissions and the funding fees, but
+    /// not the transfers), and that the legacy order functions return the rejection instead of stopping the program.
+    #[test]
+    async fn daily_loss_limit_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        server.add_income("BTCUSDT", "REALIZED_PNL", -150.0, now);
+        server.add_income("BTCUSDT", "COMMISSION", -5.0, now);
+        server.add_income("BTCUSDT", "FUNDING_FEE", -3.0, now);
+        server.add_income("", "TRANSFER", 1000.0, now);
+        let handle = server.clone();
+
+        let (before, after) = server
+            .run(async move {
+                set_risk_limits(RiskLimits {
+                    daily_loss_limit: Some(200.0),
+                    ..Default::default()
+                })
+                .await;
+                let before = new_order_limit(29000.0, &mut 0, Side::Buy, PositionSide::Long).await;
+                handle.add_income("ETHUSDT", "REALIZED_PNL", -60.0, now);
+                let after = new_order_limit(29000.0, &mut 0, Side::Buy, PositionSide::Long).await;
+                (before, after)
+            })
+            .await;
+
+        assert_eq!(before, "200 OK");
+        let rejection = RiskRejection::from_error(&after).unwrap();
+        assert_eq!(rejection.rule, RiskRule::DailyLossLimit);
+        assert!(rejection.detail.contains("-218.00"));
+    }
+}
@@ -0,0 +1,854 @@
+// simulator.rs - Order Matching Simulator
+
+// This file contains a small matching engine that reproduces the behaviour of the Binance futures exchange for the
+// order types used by the strategies (MARKET, LIMIT and STOP_MARKET), keeping track of open orders, positions and
+// wallet balance.
+
+// Structures:
+// - SimulatorConfig: Initial balance, fee rates and slippage of the simulation.
+// - SimFill: A fill done by the simulator (price, quantity, fee and realized pnl).
+// - Simulator: The matching engine itself. It is fed with prices (`on_price`), best bid/ask (`on_quote`) or
+//   candles (`on_candle`) and fills the resting orders that are reached.
+
+// MARKET orders and marketable LIMIT orders (a buy at or above the ask, a sell at or below the bid) are filled as
+// takers when they are received, at the best price of the other side (never worse than the limit price of a LIMIT
+// order, even with slippage). The other LIMIT and STOP_MARKET orders rest
+// until a price reaches them. The other order types (STOP, TAKE_PROFIT, TAKE_PROFIT_MARKET and
+// TRAILING_STOP_MARKET) are not simulated and are rejected with ERROR_NOT_MAPPED (E02).
+
+// The simulator does not know where the prices come from, so it is shared by the backtest (historical candles) and
+// the paper trading (live prices) implementations of the `Exchange` trait. Rejections use the same error constants
+// of error.rs that the real exchange produces, so the strategies handle them in the same way.
+
+use crate::error::*;
+use crate::models::{
+    Balance, KlineData, OpenOrder, OrderAck, OrderRequest, OrderType, PositionRisk, PositionSide,
+    Side,
+};
+use std::collections::HashMap;
+
+/// Configuration of a simulation.
+#[derive(Debug, Clone)]
+pub struct SimulatorConfig {
+    /// Initial USDT balance of the wallet.
+    pub initial_balance: f64,
+    /// Fee charged over the notional of MARKET and STOP_MARKET fills (ex: 0.0004 = 0.04%).
+    pub taker_fee_rate: f64,
+    /// Fee charged over the notional of LIMIT fills.
+    pub maker_fee_rate: f64,
+    /// Slippage of MARKET and STOP_MARKET fills, in basis points, always against the order.
+    pub slippage_bps: f64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            initial_balance: 10_000.0,
+            taker_fee_rate: 0.0004,
+            maker_fee_rate: 0.0002,
+            slippage_bps: 0.0,
+        }
+    }
+}
+
+/// A fill done by the simulator.
+#[derive(Debug, Clone)]
+pub struct SimFill {
+    pub order_id: u64,
+    pub symbol: String,
+    pub side: Side,
+    pub position_side: PositionSide,
+    pub price: f64,
+    /// Price before the slippage (the limit price, the stop price or the market price).
+    pub reference_price: f64,
+    pub quantity: f64,
+    pub fee: f64,
+    pub realized_pnl: f64,
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SimPosition {
+    /// Signed amount: positive for long, negative for short.
+    amount: f64,
+    entry_price: f64,
+}
+
+#[derive(Debug, Clone)]
+struct SimOrder {
+    order_id: u64,
+    request: OrderRequest,
+}
+
+/// Matching engine used by the backtest and paper trading exchanges.
+#[derive(Debug)]
+pub struct Simulator {
+    config: SimulatorConfig,
+    wallet_balance: f64,
+    orders: Vec<SimOrder>,
+    positions: HashMap<(String, PositionSide), SimPosition>,
+    last_prices: HashMap<String, f64>,
+    quotes: HashMap<String, (f64, f64)>,
+    fills: Vec<SimFill>,
+    next_order_id: u64,
+    time: u64,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        Simulator {
+            wallet_balance: config.initial_balance,
+            config,
+            orders: Vec::new(),
+            positions: HashMap::new(),
+            last_prices: HashMap::new(),
+            quotes: HashMap::new(),
+            fills: Vec::new(),
+            next_order_id: 1,
+            time: 0,
+        }
+    }
+
+    /// Sets the clock of the simulation (in milliseconds), used in the acks and fills.
+    pub fn set_time(&mut self, time: u64) {
+        self.time = time;
+    }
+
+    /// Last price received for a symbol.
+    pub fn last_price(&self, symbol: &str) -> Option<f64> {
+        self.last_prices.get(symbol).copied()
+    }
+
+    /// Symbols that received a price.
+    pub fn symbols(&self) -> Vec<String> {
+        self.last_prices.keys().cloned().collect()
+    }
+
+    /// Every fill done since the beginning of the simulation.
+    pub fn fills(&self) -> &[SimFill] {
+        &self.fills
+    }
+
+    /// Sum of the realized pnl of every fill (without fees).
+    pub fn realized_pnl(&self) -> f64 {
+        self.fills.iter().map(|f| f.realized_pnl).sum()
+    }
+
+    /// Sum of the fees of every fill.
+    pub fn fees_paid(&self) -> f64 {
+        self.fills.iter().map(|f| f.fee).sum()
+    }
+
+    /// Receives an order, validating it like the exchange does.
+    ///
+    /// MARKET orders and marketable LIMIT orders are filled right away as takers, at the best price of the other side
+    /// (the last price without a quote). The other LIMIT and STOP_MARKET orders rest until a price reaches them. A
+    /// STOP_MARKET that would trigger immediately is rejected with `ORDER_WOULD_TRIGGER_IMMEDIATELY`, a reduce only
+    /// order without position with `ERROR_NOTHING_TO_CLOSE` and the order types that are not simulated with
+    /// `ERROR_NOT_MAPPED`.
+    ///
+    pub fn submit(&mut self, order: &OrderRequest) -> Result<OrderAck, String> {
+        let last_price = match self.last_price(&order.symbol) {
+            Some(price) => price,
+            None => return Err(NO_MARKET_PRICE.to_string()),
+        };
+
+        if order.quantity <= 0.0 {
+            return Err(ERROR_NOT_VALID_QUANTITY.to_string());
+        }
+
+        if is_reducing(order) && self.position(&order.symbol, order.position_side).amount == 0.0 {
+            return Err(ERROR_NOTHING_TO_CLOSE.to_string());
+        }
+
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+
+        // With a known book a taker order takes the best price of the other side.
+        let taker_price = match self.quotes.get(&order.symbol) {
+            Some((_, ask)) if order.side == Side::Buy => *ask,
+            Some((bid, _)) => *bid,
+            None => last_price,
+        };
+
+        match order.order_type {
+            OrderType::Market => return Ok(self.fill_on_submit(order_id, order, taker_price)),
+            OrderType::Limit => {
+                let price = match order.price {
+                    Some(price) => price,
+                    None => return Err(ERROR_NOT_MAPPED.to_string()),
+                };
+                let marketable = if order.side == Side::Buy {
+                    price >= taker_price
+                } else {
+                    price <= taker_price
+                };
+                if marketable {
+                    return Ok(self.fill_on_submit(order_id, order, taker_price));
+                }
+            }
+            OrderType::StopMarket => {
+                let stop_price = match order.stop_price {
+                    Some(stop_price) => stop_price,
+                    None => return Err(ERROR_NOT_MAPPED.to_string()),
+                };
+                let would_trigger = if order.side == Side::Buy {
+                    stop_price <= last_price
+                } else {
+                    stop_price >= last_price
+                };
+                if would_trigger {
+                    return Err(ORDER_WOULD_TRIGGER_IMMEDIATELY.to_string());
+                }
+            }
+            _ => return Err(ERROR_NOT_MAPPED.to_string()),
+        }
+
+        self.orders.push(SimOrder {
+            order_id,
+            request: order.clone(),
+        });
+        Ok(self.ack(order_id, &order.symbol, "NEW"))
+    }
+
+    /// Fills an order as a taker when it is received, and acknowledges it FILLED.
+    fn fill_on_submit(&mut self, order_id: u64, order: &OrderRequest, price: f64) -> OrderAck {
+        self.fill(order_id, order, price, false);
+        let mut ack = self.ack(order_id, &order.symbol, "FILLED");
+        if let Some(fill) = self.fills.last().filter(|f| f.order_id == order_id) {
+            ack.executed_qty = fill.quantity;
+            ack.avg_price = fill.price;
+        }
+        ack
+    }
+
+    /// Cancels a resting order.
+    pub fn cancel(&mut self, symbol: &str, order_id: u64) -> Result<OrderAck, String> {
+        let index = self
+            .orders
+            .iter()
+            .position(|o| o.order_id == order_id && o.request.symbol == symbol)
+            .ok_or_else(|| UNKNOWN_ORDER.to_string())?;
+        self.orders.remove(index);
+
+        Ok(self.ack(order_id, symbol, "CANCELED"))
+    }
+
+    /// Modifies the price and quantity of a resting LIMIT order.
+    pub fn amend(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+        quantity: f64,
+        price: f64,
+    ) -> Result<OrderAck, String> {
+        let order = self
+            .orders
+            .iter_mut()
+            .find(|o| o.order_id == order_id && o.request.symbol == symbol)
+            .ok_or_else(|| UNKNOWN_ORDER.to_string())?;
+        if order.request.order_type != OrderType::Limit {
+            return Err(ERROR_NOT_MAPPED.to_string());
+        }
+        order.request.quantity = quantity;
+        order.request.price = Some(price);
+
+        Ok(self.ack(order_id, symbol, "NEW"))
+    }
+
+    /// Updates the price of a symbol and fills the orders reached by it.
+    pub fn on_price(&mut self, symbol: &str, price: f64) {
+        self.quotes.remove(symbol);
+        self.match_orders(symbol, price, price, (price, price));
+        self.last_prices.insert(symbol.to_string(), price);
+    }
+
+    /// Updates the best bid and ask of a symbol and fills the orders reached by them.
+    pub fn on_quote(&mut self, symbol: &str, bid: f64, ask: f64) {
+        // Buy orders are reached by the ask and sell orders by the bid.
+        self.match_orders(symbol, ask, bid, (ask, bid));
+        self.quotes.insert(symbol.to_string(), (bid, ask));
+        self.last_prices
+            .insert(symbol.to_string(), (bid + ask) / 2.0);
+    }
+
+    /// Fills the orders reached during a candle and updates the price of the symbol to its close.
+    pub fn on_candle(&mut self, symbol: &str, candle: &KlineData) {
+        self.quotes.remove(symbol);
+        self.set_time(candle.close_time as u64);
+        self.match_orders(symbol, candle.low, candle.high, (candle.open, candle.open));
+        self.last_prices.insert(symbol.to_string(), candle.close);
+    }
+
+    /// Resting orders of a symbol.
+    pub fn open_orders(&self, symbol: &str) -> Vec<OpenOrder> {
+        self.orders
+            .iter()
+            .filter(|o| o.request.symbol == symbol)
+            .map(|o| OpenOrder {
+                order_id: o.order_id,
+                symbol: o.request.symbol.clone(),
+                side: o.request.side,
+                order_type: o.request.order_type,
+                price: o.request.price.unwrap_or(0.0),
+                stop_price: o.request.stop_price.unwrap_or(0.0),
+                orig_qty: o.request.quantity,
+                executed_qty: 0.0,
+                status: "NEW".to_string(),
+                position_side: o.request.position_side,
+                reduce_only: o.request.reduce_only,
+            })
+            .collect()
+    }
+
+    /// Positions of a symbol, with the unrealized pnl calculated with the last price.
+    pub fn positions(&self, symbol: &str) -> Vec<PositionRisk> {
+        let mark_price = self.last_price(symbol).unwrap_or(0.0);
+        self.positions
+            .iter()
+            .filter(|((s, _), _)| s == symbol)
+            .map(|((s, position_side), p)| PositionRisk {
+                symbol: s.clone(),
+                position_amt: p.amount,
+                entry_price: p.entry_price,
+                mark_price,
+                un_realized_profit: p.amount * (mark_price - p.entry_price),
+                liquidation_price: 0.0,
+                leverage: 1.0,
+                margin_type: "cross".to_string(),
+                position_side: *position_side,
+            })
+            .collect()
+    }
+
+    /// USDT balance of the simulated wallet.
+    pub fn balances(&self) -> Vec<Balance> {
+        let cross_un_pnl: f64 = self
+            .positions
+            .iter()
+            .map(|((symbol, _), p)| {
+                let mark_price = self.last_price(symbol).unwrap_or(p.entry_price);
+                p.amount * (mark_price - p.entry_price)
+            })
+            .sum();
+
+        vec![Balance {
+            asset: "USDT".to_string(),
+            balance: self.wallet_balance,
+            available_balance: self.wallet_balance + cross_un_pnl,
+            cross_un_pnl,
+            cross_wallet_balance: self.wallet_balance,
+            max_withdraw_amount: self.wallet_balance + cross_un_pnl.min(0.0),
+        }]
+    }
+
+    fn ack(&self, order_id: u64, symbol: &str, status: &str) -> OrderAck {
+        OrderAck {
+            order_id,
+            symbol: symbol.to_string(),
+            status: status.to_string(),
+            client_order_id: String::new(),
+            update_time: self.time,
+            executed_qty: 0.0,
+            avg_price: 0.0,
+            position_side: None,
+        }
+    }
+
+    fn position(&self, symbol: &str, position_side: PositionSide) -> SimPosition {
+        self.positions
+            .get(&(symbol.to_string(), position_side))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Fills every resting order of the symbol reached by a price between `low` and `high`.
+    ///
+    /// `open` is the first price of the move for the (buy, sell) orders: the price, the ask and bid of a quote or the
+    /// open of a candle. A triggered STOP_MARKET is a market order, so it is filled at its stop price when the price
+    /// moved through it, or at `open` when the price gapped past it.
+    ///
+    fn match_orders(&mut self, symbol: &str, low: f64, high: f64, open: (f64, f64)) {
+        let (reached, resting): (Vec<SimOrder>, Vec<SimOrder>) =
+            self.orders.drain(..).partition(|o| {
+                if o.request.symbol != symbol {
+                    return false;
+                }
+                let is_buy = o.request.side == Side::Buy;
+                match o.request.order_type {
+                    OrderType::Limit => {
+                        let price = o.request.price.unwrap_or(0.0);
+                        (is_buy && low <= price) || (!is_buy && high >= price)
+                    }
+                    OrderType::StopMarket => {
+                        let stop_price = o.request.stop_price.unwrap_or(0.0);
+                        (is_buy && high >= stop_price) || (!is_buy && low <= stop_price)
+                    }
+                    _ => false,
+                }
+            });
+        self.orders = resting;
+
+        for order in reached {
+            if order.request.order_type == OrderType::Limit {
+                let price = order.request.price.unwrap_or(0.0);
+                self.fill(order.order_id, &order.request, price, true);
+            } else {
+                let stop_price = order.request.stop_price.unwrap_or(0.0);
+                let trigger_price = match order.request.side {
+                    Side::Buy => stop_price.max(open.0),
+                    _ => stop_price.min(open.1),
+                };
+                self.fill(order.order_id, &order.request, trigger_price, false);
+            }
+        }
+    }
+
+    /// Applies a fill to the position and to the wallet balance.
+    ///
+    /// Taker fills (`is_maker` false) are moved `slippage_bps` against the order before being applied, up to the
+    /// limit price of a LIMIT order, since the exchange never fills it at a worse price. A reducing
+    /// order that finds no position (e.g., a reduce only stop triggered after the position was closed) is expired
+    /// without a fill, like the exchange does.
+    ///
+    fn fill(&mut self, order_id: u64, order: &OrderRequest, reference_price: f64, is_maker: bool) {
+        let direction = order.side.sign();
+        let (price, fee_rate) = if is_maker {
+            (reference_price, self.config.maker_fee_rate)
+        } else {
+            let price = reference_price * (1.0 + direction * self.config.slippage_bps / 10_000.0);
+            let price = match (order.order_type, order.price) {
+                (OrderType::Limit, Some(limit)) if order.side == Side::Buy => price.min(limit),
+                (OrderType::Limit, Some(limit)) => price.max(limit),
+                _ => price,
+            };
+            (price, self.config.taker_fee_rate)
+        };
+
+        let key = (order.symbol.clone(), order.position_side);
+        let mut position = self.positions.get(&key).cloned().unwrap_or_default();
+
+        let mut quantity = order.quantity;
+        if is_reducing(order) {
+            // Reduce only orders never increase nor flip the position.
+            quantity = quantity.min(position.amount.abs());
+            if quantity == 0.0 {
+                return;
+            }
+        }
+        let signed_quantity = direction * quantity;
+
+        let mut realized_pnl = 0.0;
+        if position.amount == 0.0 || position.amount.signum() == signed_quantity.signum() {
+            let new_amount = position.amount + signed_quantity;
+            position.entry_price = (position.entry_price * position.amount.abs()
+                + price * quantity)
+                / new_amount.abs();
+            position.amount = new_amount;
+        } else {
+            let closed = quantity.min(position.amount.abs());
+            realized_pnl = closed * (price - position.entry_price) * position.amount.signum();
+            let new_amount = position.amount + signed_quantity;
+            if new_amount == 0.0 {
+                position.entry_price = 0.0;
+            } else if new_amount.signum() != position.amount.signum() {
+                // The order flipped the position, the remaining amount was opened at this price.
+                position.entry_price = price;
+            }
+            position.amount = new_amount;
+        }
+
+        let fee = quantity * price * fee_rate;
+        self.wallet_balance += realized_pnl - fee;
+        self.positions.insert(key, position);
+
+        self.fills.push(SimFill {
+            order_id,
+            symbol: order.symbol.clone(),
+            side: order.side,
+            position_side: order.position_side,
+            price,
+            reference_price,
+            quantity,
+            fee,
+            realized_pnl,
+            time: self.time,
+        });
+    }
+}
+
+/// Checks if an order can only reduce a position: reduce only orders in one-way mode, or orders
+/// against the position side in hedge mode (SELL in LONG and BUY in SHORT).
+fn is_reducing(order: &OrderRequest) -> bool {
+    match order.position_side {
+        PositionSide::Both => order.reduce_only,
+        position_side => order.side == position_side.closing_side(0.0),
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(
+        side: Side,
+        order_type: OrderType,
+        price: Option<f64>,
+        position_side: PositionSide,
+    ) -> OrderRequest {
+        OrderRequest {
+            symbol: "BTCUSDT".to_string(),
+            side,
+            order_type,
+            quantity: 0.01,
+            price: if order_type == OrderType::Limit {
+                price
+            } else {
+                None
+            },
+            stop_price: if order_type == OrderType::StopMarket {
+                price
+            } else {
+                None
+            },
+            position_side,
+            reduce_only: false,
+            client_order_id: None,
+            working_type: None,
+        }
+    }
+
+    /// Test that a stop order that would trigger immediately is rejected with the exchange error.
+    #[test]
+    fn stop_order_would_trigger_test() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_price("BTCUSDT", 30000.0);
+
+        let res = sim.submit(&order(
+            Side::Buy,
+            OrderType::StopMarket,
+            Some(29000.0),
+            PositionSide::Long,
+        ));
+        assert_eq!(res.unwrap_err(), ORDER_WOULD_TRIGGER_IMMEDIATELY);
+
+        let res = sim.submit(&order(
+            Side::Buy,
+            OrderType::StopMarket,
+            Some(31000.0),
+            PositionSide::Long,
+        ));
+        assert_eq!(res.unwrap().status, "NEW");
+        assert_eq!(sim.open_orders("BTCUSDT").len(), 1);
+    }
+
+    /// Test that a resting stop order is filled when the price reaches it and updates the position.
+    #[test]
+    fn stop_order_fill_test() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_price("BTCUSDT", 30000.0);
+        sim.submit(&order(
+            Side::Buy,
+            OrderType::StopMarket,
+            Some(31000.0),
+            PositionSide::Long,
+        ))
+        .unwrap();
+
+        sim.on_price("BTCUSDT", 30500.0);
+        assert!(sim.fills().is_empty());
+
+        sim.on_price("BTCUSDT", 31200.0);
+        assert_eq!(sim.fills().len(), 1);
+        assert!(sim.open_orders("BTCUSDT").is_empty());
+
+        // The price jumped over the stop: the order is filled at the price that triggered it.
+        let position = &sim.positions("BTCUSDT")[0];
+        assert_eq!(position.position_amt, 0.01);
+        assert_eq!(position.entry_price, 31200.0);
+    }
+
+    /// Test that a stop reached during a candle is filled at its stop price, and at the open when the candle gapped
+    /// past it.
+    #[test]
+    fn stop_order_gap_fill_test() {
+        let candle = |open: f64, low: f64, high: f64| KlineData {
+            open_time: 0,
+            open,
+            high,
+            low,
+            close: open,
+            volume: 1.0,
+            close_time: 59_999,
+            quote_asset_volume: open,
+            number_of_trades: 1,
+            take_buy_base_asset_volume: 0.5,
+            take_buy_quote_asset_volume: open / 2.0,
+            ignore: 0.0,
+        };
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_price("BTCUSDT", 30000.0);
+        let stop = order(
+            Side::Sell,
+            OrderType::StopMarket,
+            Some(29500.0),
+            PositionSide::Short,
+        );
+        sim.submit(&stop).unwrap();
+        sim.on_candle("BTCUSDT", &candle(30000.0, 29400.0, 30100.0));
+        sim.submit(&stop).unwrap();
+        sim.on_candle("BTCUSDT", &candle(29000.0, 28900.0, 29100.0));
+
+        assert_eq!(sim.fills()[0].reference_price, 29500.0);
+        assert_eq!(sim.fills()[1].reference_price, 29000.0);
+    }
+
+    /// Test that a reduce only stop triggered after its position was closed is expired without a fill.
+    #[test]
+    fn reduce_only_stop_without_position_test() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_price("BTCUSDT", 30000.0);
+        sim.submit(&order(
+            Side::Buy,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ))
+        .unwrap();
+        sim.submit(&order(
+            Side::Sell,
+            OrderType::StopMarket,
+            Some(29000.0),
+            PositionSide::Long,
+        ))
+        .unwrap();
+        sim.submit(&order(
+            Side::Sell,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ))
+        .unwrap();
+
+        sim.on_price("BTCUSDT", 28900.0);
+        assert_eq!(sim.fills().len(), 2);
+        assert!(sim.open_orders("BTCUSDT").is_empty());
+
+        sim.submit(&order(
+            Side::Buy,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ))
+        .unwrap();
+        let position = &sim.positions("BTCUSDT")[0];
+        assert_eq!(position.entry_price, 28900.0);
+        assert!(sim.balances()[0].balance.is_finite());
+    }
+
+    /// Test the realized pnl and fees of a round trip.
+    #[test]
+    fn round_trip_pnl_test() {
+        let mut sim = Simulator::new(SimulatorConfig {
+            initial_balance: 1000.0,
+            taker_fee_rate: 0.001,
+            maker_fee_rate: 0.0,
+            slippage_bps: 0.0,
+        });
+        sim.on_price("BTCUSDT", 30000.0);
+        sim.submit(&order(
+            Side::Buy,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ))
+        .unwrap();
+        sim.on_price("BTCUSDT", 31000.0);
+        sim.submit(&order(
+            Side::Sell,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ))
+        .unwrap();
+
+        // 10 of profit, 0.3 + 0.31 of fees.
+        let balance = &sim.balances()[0];
+        assert!((balance.balance - 1009.39).abs() < 1e-9);
+        assert_eq!(sim.positions("BTCUSDT")[0].position_amt, 0.0);
+    }
+
+    /// Test that a reduce only order without position is rejected with the exchange error.
+    #[test]
+    fn nothing_to_close_test() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_price("BTCUSDT", 30000.0);
+
+        let res = sim.submit(&order(
+            Side::Sell,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ));
+        assert_eq!(res.unwrap_err(), ERROR_NOTHING_TO_CLOSE);
+    }
+
+    /// Test canceling and amending a resting limit order.
+    #[test]
+    fn cancel_and_amend_test() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_price("BTCUSDT", 30000.0);
+        let ack = sim
+            .submit(&order(
+                Side::Buy,
+                OrderType::Limit,
+                Some(29000.0),
+                PositionSide::Both,
+            ))
+            .unwrap();
+
+        sim.amend("BTCUSDT", ack.order_id, 0.02, 29500.0).unwrap();
+        let open = sim.open_orders("BTCUSDT");
+        assert_eq!(open[0].price, 29500.0);
+        assert_eq!(open[0].orig_qty, 0.02);
+
+        sim.cancel("BTCUSDT", ack.order_id).unwrap();
+        assert!(sim.open_orders("BTCUSDT").is_empty());
+        assert_eq!(
+            sim.cancel("BTCUSDT", ack.order_id).unwrap_err(),
+            UNKNOWN_ORDER
+        );
+    }
+
+    /// Test that taker fills pay slippage and taker fee while maker fills use the limit price.
+    #[test]
+    fn slippage_and_fees_test() {
+        let mut sim = Simulator::new(SimulatorConfig {
+            initial_balance: 1000.0,
+            taker_fee_rate: 0.001,
+            maker_fee_rate: 0.0001,
+            slippage_bps: 10.0,
+        });
+        sim.on_quote("BTCUSDT", 29990.0, 30010.0);
+
+        sim.submit(&order(
+            Side::Buy,
+            OrderType::Market,
+            None,
+            PositionSide::Long,
+        ))
+        .unwrap();
+        let fill = sim.fills()[0].clone();
+        assert_eq!(fill.reference_price, 30010.0);
+        assert!((fill.price - 30040.01).abs() < 1e-9);
+        assert!((fill.fee - 0.3004001).abs() < 1e-9);
+
+        sim.submit(&order(
+            Side::Sell,
+            OrderType::Limit,
+            Some(30100.0),
+            PositionSide::Long,
+        ))
+        .unwrap();
+        sim.on_quote("BTCUSDT", 30100.0, 30110.0);
+        let fill = sim.fills()[1].clone();
+        assert_eq!(fill.price, 30100.0);
+        assert!((fill.fee - 0.0301).abs() < 1e-9);
+        assert!((sim.realized_pnl() - 0.5999).abs() < 1e-9);
+    }
+
+    /// Test that a LIMIT order that crosses the book is filled right away at the other side, as a taker, and that the
+    /// order types that are not simulated are rejected.
+    #[test]
+    fn marketable_limit_order_test() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        sim.on_quote("BTCUSDT", 29990.0, 30010.0);
+
+        let ack = sim
+            .submit(&order(
+                Side::Buy,
+                OrderType::Limit,
+                Some(30050.0),
+                PositionSide::Long,
+            ))
+            .unwrap();
+        assert_eq!(ack.status, "FILLED");
+        assert_eq!(ack.executed_qty, 0.01);
+        assert_eq!(ack.avg_price, 30010.0);
+        assert!((sim.fills()[0].fee - 0.01 * 30010.0 * 0.0004).abs() < 1e-9);
+
+        let ack = sim
+            .submit(&order(
+                Side::Sell,
+                OrderType::Limit,
+                Some(29000.0),
+                PositionSide::Long,
+            ))
+            .unwrap();
+        assert_eq!(ack.status, "FILLED");
+        assert_eq!(sim.fills()[1].price, 29990.0);
+        assert!(sim.open_orders("BTCUSDT").is_empty());
+
+        let ack = sim
+            .submit(&order(
+                Side::Buy,
+                OrderType::Limit,
+                Some(30000.0),
+                PositionSide::Long,
+            ))
+            .unwrap();
+        assert_eq!(ack.status, "NEW");
+        assert_eq!(sim.open_orders("BTCUSDT").len(), 1);
+
+        let res = sim.submit(&order(
+            Side::Buy,
+            OrderType::TakeProfitMarket,
+            Some(31000.0),
+            PositionSide::Long,
+        ));
+        assert_eq!(res.unwrap_err(), ERROR_NOT_MAPPED);
+    }
+
+    /// Test that the slippage of a marketable LIMIT order does not fill it at a worse price than its limit.
+    #[test]
+    fn marketable_limit_order_slippage_test() {
+        let mut sim = Simulator::new(SimulatorConfig {
+            slippage_bps: 10.0,
+            ..SimulatorConfig::default()
+        });
+        sim.on_quote("BTCUSDT", 29990.0, 29999.0);
+
+        let ack = sim
+            .submit(&order(
+                Side::Buy,
+                OrderType::Limit,
+                Some(30000.0),
+                PositionSide::Long,
+            ))
+            .unwrap();
+        assert_eq!(ack.status, "FILLED");
+        assert_eq!(ack.avg_price, 30000.0);
+
+        let ack = sim
+            .submit(&order(
+                Side::Sell,
+                OrderType::Limit,
+                Some(29989.0),
+                PositionSide::Long,
+            ))
+            .unwrap();
+        assert_eq!(ack.avg_price, 29989.0);
+
+        // Far from the limit, the slippage is applied in full.
+        let ack = sim
+            .submit(&order(
+                Side::Buy,
+                OrderType::Limit,
+                Some(31000.0),
+                PositionSide::Long,
+            ))
+            .unwrap();
+        assert!((ack.avg_price - 29999.0 * 1.001).abs() < 1e-6);
+    }
+}
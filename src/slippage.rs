@@ -0,0 +1,527 @@
+// slippage.rs - Slippage Guard for Market Orders
+
+// This file contains the price protection of the market orders. A market order is filled at whatever price the book
+// offers, so before it is sent, the price it would get (the best ask for a buy, the best bid for a sell, from
+// /fapi/v1/ticker/bookTicker) is compared with a reference price: the price the strategy wanted (e.g., the stop
+// price of `new_order` when it falls back to a market order after E01), or the last price.
+
+// What the guard does depends on its action:
+// - SlippageAction::LimitIoc: the market order is sent as a LIMIT IOC (immediate or cancel) order at the protected
+//   price (the reference price moved by the maximum slippage), so it is never filled at a worse price. The part that
+//   cannot be filled at that price expires.
+// - SlippageAction::Refuse: the market order is sent unchanged when the expected slippage is below the maximum, and
+//   refused (E19) otherwise.
+
+// The guard is optional and set per exchange (and per account) with `set_slippage_guard`. While it is set,
+// `new_order_market` and the E01 fallback of `new_order` send their orders through `guarded_market_order`. Every
+// guarded order returns a `SlippageReport` with the expected and the realized slippage, that is also logged.
+
+// The protected price is rounded to the tick size of the symbol (PRICE_FILTER of /fapi/v1/exchangeInfo), read once
+// per exchange and symbol.
+
+use crate::binance_orders::{book_ticker, exchange_key, last_price, symbol_info};
+use crate::error::{
+    OrderError, ERROR_MALFORMED_RESPONSE, ERROR_NOT_MAPPED, POSITION_SIDE_MISMATCH,
+    SLIPPAGE_TOO_HIGH,
+};
+use crate::models::{de_float_from_str, OrderRequest, OrderType, PositionSide, Side};
+use crate::position_mode;
+use crate::request::BinanceRequest;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tracing::{info, warn};
+
+/// Attempts of a guarded order: after E17, the position mode is read again and the order is sent once more.
+const MAX_GUARDED_ORDER_ATTEMPTS: u32 = 2;
+
+/// What the guard does with a market order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlippageAction {
+    /// Send it as a LIMIT IOC order at the protected price.
+    LimitIoc,
+    /// Send it unchanged when the expected slippage is below the maximum, and refuse it otherwise.
+    Refuse,
+}
+
+/// Slippage guard of the market orders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlippageGuard {
+    /// Maximum slippage from the reference price, as a fraction (e.g., 0.002 for 0.2%).
+    pub max_slippage: f64,
+    pub action: SlippageAction,
+}
+
+/// Expected and realized slippage of a guarded market order. The slippages are fractions of the reference price,
+/// positive when the price is worse than the reference (higher for a buy, lower for a sell).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlippageReport {
+    pub symbol: String,
+    pub side: Side,
+    pub reference_price: f64,
+    /// Best price of the book on the side of the order when it was checked.
+    pub expected_price: f64,
+    pub expected_slippage: f64,
+    /// Limit price of the LIMIT IOC order (None when the order was sent as a MARKET order).
+    pub protected_price: Option<f64>,
+    pub order_type: OrderType,
+    pub order_id: u64,
+    pub status: String,
+    pub executed_qty: f64,
+    /// Average fill price (None when nothing was filled).
+    pub avg_price: Option<f64>,
+    pub realized_slippage: Option<f64>,
+}
+
+/// Order returned with newOrderRespType=RESULT.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OrderResult {
+    order_id: u64,
+    status: String,
+    #[serde(deserialize_with = "de_float_from_str")]
+    executed_qty: f64,
+    #[serde(deserialize_with = "de_float_from_str")]
+    avg_price: f64,
+}
+
+/// Slippage guard per exchange key (see `exchange_key`).
+static SLIPPAGE_GUARDS: RwLock<Option<HashMap<String, SlippageGuard>>> = RwLock::new(None);
+
+/// Tick size of the prices per exchange key and symbol, read once from the PRICE_FILTER of exchangeInfo.
+static TICK_SIZES: RwLock<Option<HashMap<(String, String), f64>>> = RwLock::new(None);
+
+/// Sets (or removes with None) the slippage guard of the market orders of the current exchange.
+pub async fn set_slippage_guard(guard: Option<SlippageGuard>) {
+    let key = exchange_key().await;
+    let mut guards = SLIPPAGE_GUARDS.write().unwrap();
+    let guards = guards.get_or_insert_with(HashMap::new);
+    match guard {
+        Some(guard) => guards.insert(key, guard),
+        None => guards.remove(&key),
+    };
+}
+
+/// Get the slippage guard of the current exchange, or None when the market orders are not guarded.
+pub async fn slippage_guard() -> Option<SlippageGuard> {
+    let key = exchange_key().await;
+    SLIPPAGE_GUARDS
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|guards| guards.get(&key).copied())
+}
+
+/// Get the tick size of the prices of a symbol in the current exchange.
+pub(crate) async fn tick_size(symbol: &str) -> Result<f64, String> {
+    let key = (exchange_key().await, symbol.to_string());
+    if let Some(tick_size) = TICK_SIZES
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|sizes| sizes.get(&key).copied())
+    {
+        return Ok(tick_size);
+    }
+
+    // Every symbol of the exchange has a PRICE_FILTER.
+    let tick_size = symbol_info(symbol)
+        .await?
+        .tick_size()
+        .filter(|tick_size| *tick_size > 0.0)
+        .ok_or_else(|| ERROR_MALFORMED_RESPONSE.to_string())?;
+    TICK_SIZES
+        .write()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, tick_size);
+    Ok(tick_size)
+}
+
+/// Computes the slippage of a price from the reference price, positive when it is worse for the side.
+pub fn slippage(side: Side, reference_price: f64, price: f64) -> f64 {
+    side.sign() * (price - reference_price) / reference_price
+}
+
+/// Computes the protected price: the reference price moved by the maximum slippage, rounded to the tick size
+/// towards the reference price.
+///
+/// Returns E02 when a value is not a finite number (e.g., NaN) or the tick size is not positive.
+fn protected_price(
+    side: Side,
+    reference_price: f64,
+    max_slippage: f64,
+    tick_size: f64,
+) -> Result<f64, String> {
+    let decimal = |value: f64| Decimal::from_f64(value).ok_or_else(|| ERROR_NOT_MAPPED.to_string());
+    // Computed with decimals, so a price on the tick is not moved to the next one by a floating point error.
+    let reference_price = decimal(reference_price)?;
+    let max_slippage = decimal(max_slippage)?;
+    let tick_size = decimal(tick_size)?;
+    if tick_size <= Decimal::ZERO {
+        return Err(ERROR_NOT_MAPPED.to_string());
+    }
+    let (price, strategy) = match side {
+        Side::Buy => (
+            reference_price * (Decimal::ONE + max_slippage),
+            RoundingStrategy::ToZero,
+        ),
+        Side::Sell => (
+            reference_price * (Decimal::ONE - max_slippage),
+            RoundingStrategy::AwayFromZero,
+        ),
+    };
+    ((price / tick_size).round_dp_with_strategy(0, strategy) * tick_size)
+        .to_f64()
+        .ok_or_else(|| ERROR_NOT_MAPPED.to_string())
+}
+
+/// Sends a market order through a slippage guard.
+///
+/// # Arguments
+/// * `order`: The market order (its type, price and stop price are not used).
+/// * `reference_price`: The price the order should get, or None to use the last price.
+/// * `guard`: The maximum slippage and what to do with the order.
+///
+/// # Returns
+/// The `SlippageReport` of the order (an IOC order may be expired, with nothing filled), SLIPPAGE_TOO_HIGH (E19)
+/// when the order is refused, or the mapped error.
+///
+pub async fn guarded_market_order(
+    order: &OrderRequest,
+    reference_price: Option<f64>,
+    guard: &SlippageGuard,
+) -> Result<SlippageReport, OrderError> {
+    let reference_price = match reference_price {
+        Some(price) => price,
+        None => last_price(&order.symbol).await?,
+    };
+    let book = book_ticker(&order.symbol).await?;
+    let expected_price = match order.side {
+        Side::Buy => book.ask_price,
+        Side::Sell => book.bid_price,
+    };
+    let expected_slippage = slippage(order.side, reference_price, expected_price);
+
+    let protected_price = match guard.action {
+        SlippageAction::LimitIoc => Some(protected_price(
+            order.side,
+            reference_price,
+            guard.max_slippage,
+            tick_size(&order.symbol).await?,
+        )?),
+        SlippageAction::Refuse if expected_slippage > guard.max_slippage => {
+            warn!(
+                symbol = %order.symbol,
+                side = %order.side,
+                reference_price,
+                expected_price,
+                expected_slippage,
+                max_slippage = guard.max_slippage,
+                "Market order refused by the slippage guard"
+            );
+            return Err(SLIPPAGE_TOO_HIGH.into());
+        }
+        SlippageAction::Refuse => None,
+    };
+
+    let result = send_guarded_order(order, protected_price).await?;
+    let avg_price = Some(result.avg_price).filter(|_| result.executed_qty > 0.0);
+    let report = SlippageReport {
+        symbol: order.symbol.clone(),
+        side: order.side,
+        reference_price,
+        expected_price,
+        expected_slippage,
+        protected_price,
+        order_type: match protected_price {
+            Some(_) => OrderType::Limit,
+            None => OrderType::Market,
+        },
+        order_id: result.order_id,
+        status: result.status,
+        executed_qty: result.executed_qty,
+        avg_price,
+        realized_slippage: avg_price.map(|p| slippage(order.side, reference_price, p)),
+    };
+    info!(
+        symbol = %report.symbol,
+        side = %report.side,
+        order_type = %report.order_type,
+        status = %report.status,
+        reference_price,
+        expected_slippage,
+        realized_slippage = report.realized_slippage,
+        executed_qty = report.executed_qty,
+        "Guarded market order"
+    );
+    Ok(report)
+}
+
+/// Sends the order of `guarded_market_order`: a LIMIT IOC order at the protected price, or a MARKET order.
+async fn send_guarded_order(
+    order: &OrderRequest,
+    protected_price: Option<f64>,
+) -> Result<OrderResult, OrderError> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let (position_side, reduce_only) =
+            position_mode::order_position_side(order.side, order.position_side, order.reduce_only)
+                .await?;
+        let order_type = match protected_price {
+            Some(_) => OrderType::Limit,
+            None => OrderType::Market,
+        };
+        let result = BinanceRequest::post("/fapi/v1/order")
+            .param("symbol", &order.symbol)
+            .param("side", order.side)
+            .param("type", order_type)
+            .param("quantity", order.quantity)
+            .param("positionSide", position_side)
+            .param_opt("price", protected_price)
+            .param_opt("timeInForce", protected_price.map(|_| "IOC"))
+            .param_opt("newClientOrderId", order.client_order_id.as_ref())
+            .param_opt("reduceOnly", reduce_only)
+            .param("newOrderRespType", "RESULT")
+            .signed()
+            .fetch_order()
+            .await;
+        match result {
+            Err(error)
+                if error == POSITION_SIDE_MISMATCH && attempts < MAX_GUARDED_ORDER_ATTEMPTS =>
+            {
+                continue
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Builds the market order of the legacy order functions (`new_order_market` and the E01 fallback of `new_order`).
+pub(crate) fn legacy_market_order(
+    side: Side,
+    quantity: f64,
+    position_side: PositionSide,
+    reduce_only: bool,
+) -> OrderRequest {
+    OrderRequest {
+        symbol: "BTCUSDT".to_string(),
+        side,
+        order_type: OrderType::Market,
+        quantity,
+        price: None,
+        stop_price: None,
+        position_side,
+        reduce_only,
+        client_order_id: None,
+        working_type: None,
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binance_orders::{new_order, new_order_market};
+    use crate::mock_server;
+    use crate::models::WorkingType;
+    use tokio::test;
+
+    /// Test the sign of the slippage and the rounding of the protected price.
+    #[test]
+    async fn protected_price_test() {
+        assert!((slippage(Side::Buy, 30000.0, 30030.0) - 0.001).abs() < 1e-12);
+        assert!((slippage(Side::Sell, 30000.0, 30030.0) + 0.001).abs() < 1e-12);
+        assert_eq!(
+            protected_price(Side::Buy, 30000.05, 0.001, 0.1),
+            Ok(30030.0)
+        );
+        assert_eq!(
+            protected_price(Side::Sell, 30000.05, 0.001, 0.1),
+            Ok(29970.1)
+        );
+        assert_eq!(
+            protected_price(Side::Buy, 30000.05, 0.001, 0.01),
+            Ok(30030.05)
+        );
+        assert_eq!(
+            protected_price(Side::Buy, 30000.05, 0.001, 0.5),
+            Ok(30030.0)
+        );
+        assert_eq!(
+            protected_price(Side::Sell, 30000.05, 0.001, 0.5),
+            Ok(29970.5)
+        );
+
+        for (reference_price, max_slippage, tick_size) in [
+            (f64::NAN, 0.001, 0.1),
+            (f64::INFINITY, 0.001, 0.1),
+            (30000.0, f64::NAN, 0.1),
+            (30000.0, 0.001, f64::NEG_INFINITY),
+            (30000.0, 0.001, 0.0),
+        ] {
+            assert_eq!(
+                protected_price(Side::Buy, reference_price, max_slippage, tick_size),
+                Err(ERROR_NOT_MAPPED.to_string())
+            );
+        }
+    }
+
+    /// Test that the guard refuses the market orders whose slippage is too high, and sends the other ones.
+    #[test]
+    async fn refuse_action_test() {
+        let server = mock_server::isolated();
+        server.set_price(30100.0);
+        let guard = SlippageGuard {
+            max_slippage: 0.002,
+            action: SlippageAction::Refuse,
+        };
+        let order = legacy_market_order(Side::Buy, 0.001, PositionSide::Both, false);
+
+        let (refused, sent) = server
+            .run(async {
+                (
+                    guarded_market_order(&order, Some(30000.0), &guard).await,
+                    guarded_market_order(&order, Some(30090.0), &guard).await,
+                )
+            })
+            .await;
+
+        assert_eq!(refused.unwrap_err(), SLIPPAGE_TOO_HIGH);
+        let report = sent.unwrap();
+        assert_eq!(report.order_type, OrderType::Market);
+        assert_eq!(report.expected_price, 30100.1);
+        assert_eq!(report.executed_qty, 0.001);
+        assert!((report.realized_slippage.unwrap() - 10.0 / 30090.0).abs() < 1e-9);
+        assert_eq!(
+            server
+                .requests()
+                .iter()
+                .filter(|r| r.starts_with("POST /fapi/v1/order"))
+                .count(),
+            1
+        );
+    }
+
+    /// Test that the guard sends the market orders as IOC limits at the protected price, that expire when the
+    /// market moved too far, and that the legacy functions go through it.
+    #[test]
+    async fn limit_ioc_action_test() {
+        let server = mock_server::isolated();
+        server.set_dual_side_position(true);
+        server.set_price(30000.0);
+        let guard = SlippageGuard {
+            max_slippage: 0.001,
+            action: SlippageAction::LimitIoc,
+        };
+        let order = legacy_market_order(Side::Sell, 0.001, PositionSide::Short, false);
+
+        let (filled, expired, fallback) = server
+            .run(async {
+                set_slippage_guard(Some(guard)).await;
+                let filled = guarded_market_order(&order, Some(30010.0), &guard).await;
+                let expired = guarded_market_order(&order, Some(30100.0), &guard).await;
+                // A sell stop above the market would trigger immediately: it falls back to a guarded order.
+                let fallback = new_order(
+                    30010.0,
+                    &mut 0,
+                    Side::Sell,
+                    false,
+                    PositionSide::Short,
+                    WorkingType::ContractPrice,
+                )
+                .await;
+                let market = new_order_market(&mut 0, Side::Buy, PositionSide::Long).await;
+                set_slippage_guard(None).await;
+                (filled, expired, (fallback, market))
+            })
+            .await;
+
+        let filled = filled.unwrap();
+        assert_eq!(filled.order_type, OrderType::Limit);
+        assert_eq!(filled.protected_price, Some(29980.0));
+        assert_eq!(filled.status, "FILLED");
+        assert_eq!(filled.avg_price, Some(30000.0));
+        let expired = expired.unwrap();
+        assert_eq!(expired.protected_price, Some(30069.9));
+        assert_eq!(expired.status, "EXPIRED");
+        assert_eq!(expired.executed_qty, 0.0);
+        assert_eq!(expired.realized_slippage, None);
+        assert_eq!(fallback, ("200 OK".to_string(), "200 OK".to_string()));
+
+        let requests = server.requests();
+        let orders: Vec<&String> = requests
+            .iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .collect();
+        assert!(orders[2].contains("type=STOP_MARKET"));
+        assert!(orders[3..]
+            .iter()
+            .all(|r| r.contains("type=LIMIT") && r.contains("timeInForce=IOC")));
+    }
+
+    /// Test that the guarded orders keep the reduce only flag, use the tick size of their symbol and give up after a
+    /// second E17.
+    #[test]
+    async fn guarded_order_parameters_test() {
+        let server = mock_server::isolated();
+        server.set_price(30000.0);
+        server.set_position("BTCUSDT", "BOTH", 0.01, 30000.0);
+        let guard = SlippageGuard {
+            max_slippage: 0.001,
+            action: SlippageAction::LimitIoc,
+        };
+        let mut order = legacy_market_order(Side::Sell, 0.001, PositionSide::Both, false);
+        order.symbol = "ETHUSDT".to_string();
+        let handle = server.clone();
+
+        let (fallback, eth, mismatch) = server
+            .run(async move {
+                set_slippage_guard(Some(guard)).await;
+                // A reduce only sell stop above the market falls back to a guarded reduce only order.
+                let fallback = new_order(
+                    30010.0,
+                    &mut 0,
+                    Side::Sell,
+                    true,
+                    PositionSide::Both,
+                    WorkingType::ContractPrice,
+                )
+                .await;
+                let eth = guarded_market_order(&order, Some(30000.05), &guard).await;
+                for _ in 0..3 {
+                    handle.inject_error(
+                        "/fapi/v1/order",
+                        400,
+                        -4061,
+                        "Order's position side does not match user's setting.",
+                    );
+                }
+                let mismatch = guarded_market_order(&order, Some(30000.05), &guard).await;
+                set_slippage_guard(None).await;
+                (fallback, eth, mismatch)
+            })
+            .await;
+
+        assert_eq!(fallback, "200 OK");
+        assert_eq!(eth.unwrap().protected_price, Some(29970.05));
+        assert_eq!(mismatch.unwrap_err(), POSITION_SIDE_MISMATCH);
+
+        let requests = server.requests();
+        let orders: Vec<&String> = requests
+            .iter()
+            .filter(|r| r.starts_with("POST /fapi/v1/order"))
+            .collect();
+        assert!(orders[1].contains("type=LIMIT") && orders[1].contains("reduceOnly=true"));
+        // The stop, the fallback, the ETHUSDT order and the two attempts of the last order.
+        assert_eq!(orders.len(), 5);
+        let exchange_info = requests
+            .iter()
+            .filter(|r| r.starts_with("GET /fapi/v1/exchangeInfo"))
+            .count();
+        assert_eq!(exchange_info, 2);
+    }
+}
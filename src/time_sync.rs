@@ -0,0 +1,223 @@
+// time_sync.rs - Server Time Synchronisation
+
+// This file contains the synchronisation of the local clock with the clock of the Binance futures server.
+
+// The signed requests carry a `timestamp` that the exchange compares with its own clock: a request is rejected with
+// the E08 error when it arrives more than `recvWindow` milliseconds after the timestamp or when the timestamp is ahead
+// of the server. Instead of hiding the drift of the local clock with a huge recvWindow, the server time is polled
+// (GET /fapi/v1/time), the offset between both clocks is estimated with the round-trip latency of the request and
+// `get_timestamp` applies it to every signed request.
+
+// Once the clock is synchronised, `recv_window` returns a tight window (SYNCED_RECV_WINDOW), so a delayed request is
+// rejected by the exchange instead of being executed late. A recvWindow error makes `error_handler` synchronise the
+// clock again before the request is retried.
+
+// The drift is kept per server (the origin of `exchange_url`), since each server has its own clock: the offset of the
+// testnet is not applied to the requests of the production server.
+
+use crate::binance_orders::{exchange_url, spawn_scoped};
+use crate::error::{error_handler, ERROR_MALFORMED_RESPONSE};
+use crate::request::BinanceRequest;
+use async_recursion::async_recursion;
+use reqwest::{StatusCode, Url};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// recvWindow (in milliseconds) used while the clock is not synchronised with the server.
+pub const UNSYNCED_RECV_WINDOW: u64 = 50_000;
+/// recvWindow (in milliseconds) used once the clock is synchronised with the server.
+pub const SYNCED_RECV_WINDOW: u64 = 5_000;
+/// Number of requests made in each synchronisation. The one with the lowest round-trip is used.
+const SYNC_SAMPLES: usize = 3;
+
+/// Drift measured in the last synchronisation with each server, keyed by origin (see `origin`).
+static CLOCK_DRIFTS: RwLock<Option<HashMap<String, ClockDrift>>> = RwLock::new(None);
+/// recvWindow chosen by the user (0 to choose it from the state of the synchronisation).
+static RECV_WINDOW_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Measured difference between the server clock and the local clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockDrift {
+    /// Server time minus local time, in milliseconds (positive when the local clock is late).
+    pub offset_ms: i64,
+    /// Round-trip latency of the request used to measure the offset, in milliseconds.
+    pub round_trip_ms: u64,
+    /// Local time of the measurement, in milliseconds since the epoch.
+    pub synced_at: u64,
+}
+
+/// Get the origin of a base url (e.g., "https://fapi.binance.com"), the key of the drift of its server.
+pub fn origin(base_url: &str) -> String {
+    Url::parse(base_url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| base_url.to_string())
+}
+
+fn cached_drift(origin: &str) -> Option<ClockDrift> {
+    CLOCK_DRIFTS
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|drifts| drifts.get(origin).copied())
+}
+
+fn cache_drift(origin: String, drift: ClockDrift) {
+    CLOCK_DRIFTS
+        .write()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(origin, drift);
+}
+
+fn local_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Get the time of the exchange server.
+///
+/// # Returns
+/// The server time in milliseconds since the epoch, or the error of the request.
+///
+pub async fn server_time() -> Result<u64, String> {
+    let result = BinanceRequest::get("/fapi/v1/time").send().await;
+
+    if result.status() == StatusCode::OK {
+        let data: serde_json::Value = match result.json().await {
+            Ok(data) => data,
+            Err(_) => return Err(ERROR_MALFORMED_RESPONSE.to_string()),
+        };
+        data["serverTime"]
+            .as_u64()
+            .ok_or_else(|| ERROR_MALFORMED_RESPONSE.to_string())
+    } else {
+        Err(error_handler(result, None).await)
+    }
+}
+
+/// Synchronises the local clock with the exchange server (see `exchange_url`).
+///
+/// The server time is requested a few times and the sample with the lowest round-trip latency is kept, assuming that
+/// the server read its clock in the middle of the round-trip.
+///
+/// # Returns
+/// The measured drift, that is applied to the next signed requests.
+///
+#[async_recursion]
+pub async fn sync_server_time() -> Result<ClockDrift, String> {
+    let mut best: Option<ClockDrift> = None;
+
+    for _ in 0..SYNC_SAMPLES {
+        let sent_at = local_time_ms();
+        let started = Instant::now();
+        let server = server_time().await?;
+        let round_trip_ms = started.elapsed().as_millis() as u64;
+
+        let drift = ClockDrift {
+            offset_ms: server as i64 - (sent_at + round_trip_ms / 2) as i64,
+            round_trip_ms,
+            synced_at: sent_at,
+        };
+        if best.is_none_or(|b| drift.round_trip_ms < b.round_trip_ms) {
+            best = Some(drift);
+        }
+    }
+
+    let drift = best.unwrap();
+    cache_drift(origin(&exchange_url().await), drift);
+    Ok(drift)
+}
+
+/// Spawns a task that synchronises the clock every `interval`, so the drift of the local clock is followed. The task
+/// synchronises the clock of the exchange server of the caller (see `spawn_scoped`).
+pub fn spawn_time_sync(interval: Duration) -> tokio::task::JoinHandle<()> {
+    spawn_scoped(async move {
+        loop {
+            let _ = sync_server_time().await;
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Offset (in milliseconds) added to the local time in the timestamp of the signed requests sent to the exchange
+/// server (see `exchange_url`).
+pub async fn clock_offset() -> i64 {
+    clock_offset_of(&origin(&exchange_url().await))
+}
+
+/// Offset (in milliseconds) of the clock of a server, 0 when it was never synchronised.
+///
+/// # Arguments
+/// * `origin`: The origin of the server (see `origin`).
+///
+pub fn clock_offset_of(origin: &str) -> i64 {
+    cached_drift(origin).map_or(0, |drift| drift.offset_ms)
+}
+
+/// Get the drift of the exchange server (see `exchange_url`) measured in the last synchronisation, or `None` if the
+/// clock was never synchronised with it.
+pub async fn clock_drift() -> Option<ClockDrift> {
+    cached_drift(&origin(&exchange_url().await))
+}
+
+/// Sets the recvWindow (in milliseconds) of the signed requests. 0 brings back the automatic choice.
+pub fn set_recv_window(recv_window: u64) {
+    RECV_WINDOW_MS.store(recv_window, Ordering::Relaxed);
+}
+
+/// Get the recvWindow (in milliseconds) of the signed requests.
+///
+/// # Returns
+/// The value set with `set_recv_window`, or SYNCED_RECV_WINDOW once the clock is synchronised with the exchange server
+/// and UNSYNCED_RECV_WINDOW before it.
+///
+pub async fn recv_window() -> u64 {
+    match RECV_WINDOW_MS.load(Ordering::Relaxed) {
+        0 if clock_drift().await.is_some() => SYNCED_RECV_WINDOW,
+        0 => UNSYNCED_RECV_WINDOW,
+        recv_window => recv_window,
+    }
+}
+
+//Functions tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server;
+    use tokio::test;
+
+    /// Test measuring the drift of the local clock.
+    ///
+    /// The mock server clock is set 1.5 seconds behind the local clock and the measured offset must match it. The
+    /// offset is only kept for that server, so it is not applied to the requests of the other tests.
+    ///
+    #[test]
+    async fn sync_server_time_test() {
+        let server = mock_server::isolated();
+        server.set_clock_offset(-1500);
+
+        let (drift, offset, recv) = server
+            .run(async {
+                let drift = sync_server_time().await.unwrap();
+                (drift, clock_offset().await, recv_window().await)
+            })
+            .await;
+        assert!((drift.offset_ms + 1500).abs() < 100);
+        assert_eq!(offset, drift.offset_ms);
+        assert_eq!(recv, SYNCED_RECV_WINDOW);
+        assert_eq!(
+            server
+                .requests()
+                .iter()
+                .filter(|r| r.starts_with("GET /fapi/v1/time"))
+                .count(),
+            SYNC_SAMPLES
+        );
+
+        assert!(mock_server::isolated().run(clock_drift()).await.is_none());
+    }
+}